@@ -0,0 +1,348 @@
+#![allow(clippy::needless_pass_by_value, clippy::type_complexity)]
+use std::f32::consts::{PI, TAU};
+
+use bevy::prelude::*;
+use bevy_turborand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    barrel::{BarrelDangers, BarrelKind},
+    collision::{collide, Collider, Shape, Static},
+    config::{P8_LIGHT_GREEN, P8_ORANGE, P8_PINK, WINDOW_HEIGHT, WINDOW_WIDTH},
+    edge::EdgeMode,
+    input::{CarInput, InputSet},
+    level::LevelRules,
+    net::{self, RollbackApp},
+    pickup::Pickup,
+    player::{car_order, spawn_car, CarAssets, Movement, PlayerId},
+    race::RaceProgress,
+    ui::NewRivals,
+    GameState, TILE_SIZE,
+};
+
+const MAX_RIVALS: usize = 3;
+/// Tint of every rival car
+const RIVAL_COLORS: [Color; MAX_RIVALS] = [P8_ORANGE, P8_LIGHT_GREEN, P8_PINK];
+/// A wandering rival picks somewhere else to go this close to its goal
+const ARRIVE_DISTANCE: f32 = TILE_SIZE * 2.0;
+/// Room kept around a blast on top of its radius
+const BLAST_MARGIN: f32 = TILE_SIZE;
+/// Seconds of driving ahead checked for obstacles
+const LOOK_AHEAD: f32 = 0.4;
+const PROBE_RADIUS: f32 = TILE_SIZE * 0.5;
+/// Rivals keep this far from solid or deadly screen edges
+const EDGE_MARGIN: f32 = TILE_SIZE * 2.0;
+/// Steering per radian the car is off course
+const STEER_GAIN: f32 = 3.0;
+/// Turns sharper than this are taken drifting
+const DRIFT_ANGLE: f32 = 1.2;
+/// Boost only when heading this close to the plan
+const BOOST_ANGLE: f32 = 0.3;
+
+/// How well a computer driven car drives
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Skill {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Skill {
+    pub fn name(self) -> &'static str {
+        match self {
+            Skill::Easy => "Easy",
+            Skill::Normal => "Normal",
+            Skill::Hard => "Hard",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Skill::Easy => Skill::Normal,
+            Skill::Normal => Skill::Hard,
+            Skill::Hard => Skill::Easy,
+        }
+    }
+
+    /// Seconds between two looks at the arena
+    fn reaction(self) -> f32 {
+        match self {
+            Skill::Easy => 0.5,
+            Skill::Normal => 0.25,
+            Skill::Hard => 0.1,
+        }
+    }
+
+    /// Barrels with less fuse left than this many seconds are dodged
+    fn caution(self) -> f32 {
+        match self {
+            Skill::Easy => 1.0,
+            Skill::Normal => 2.0,
+            Skill::Hard => 3.0,
+        }
+    }
+
+    /// Largest aiming mistake, in radians
+    fn aim_error(self) -> f32 {
+        match self {
+            Skill::Easy => 0.4,
+            Skill::Normal => 0.15,
+            Skill::Hard => 0.0,
+        }
+    }
+
+    fn boosts(self) -> bool {
+        self != Skill::Easy
+    }
+}
+
+/// Computer driven cars joining every run, picked on the game over screen
+#[derive(Resource, Default)]
+pub struct RivalSettings {
+    pub count: usize,
+    pub skill: Skill,
+}
+
+/// Drives a car by filling its `CarInput`, it works on any car, player
+/// cars included
+#[derive(Component, Clone)]
+pub struct AiDriver {
+    pub skill: Skill,
+    /// Heading picked on the last look at the arena
+    heading: f32,
+    wants_boost: bool,
+    /// Where to go when there is nothing better to do
+    wander: Option<Vec2>,
+    think: Timer,
+}
+
+impl AiDriver {
+    pub fn new(skill: Skill) -> Self {
+        Self {
+            skill,
+            heading: 0.0,
+            wants_boost: false,
+            wander: None,
+            think: Timer::from_seconds(skill.reaction(), TimerMode::Repeating),
+        }
+    }
+}
+
+/// Computer driven car, replaced at the start of every run. Numbered in
+/// the order they were spawned, see `player::car_order`.
+#[derive(Component, Clone)]
+pub struct Rival(pub usize);
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RivalSettings>()
+            .rollback_component::<AiDriver>()
+            .rollback_component::<Rival>()
+            .add_systems(PostStartup, send_rivals)
+            .add_systems(OnEnter(GameState::Playing), spawn_rivals)
+            .add_systems(
+                Update,
+                (plan_routes, drive)
+                    .chain()
+                    .in_set(InputSet)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Update,
+                change_rivals
+                    .run_if(in_state(GameState::GameOver))
+                    .run_if(net::offline),
+            );
+    }
+}
+
+fn spawn_rivals(
+    mut commands: Commands,
+    rival_query: Query<Entity, With<Rival>>,
+    settings: Res<RivalSettings>,
+    assets: Res<CarAssets>,
+    rules: Res<LevelRules>,
+    mut global_rng: ResMut<GlobalRng>,
+) {
+    for entity in &rival_query {
+        commands.entity(entity).despawn_recursive();
+    }
+    for (index, color) in RIVAL_COLORS.into_iter().take(settings.count).enumerate() {
+        let position = rules.random_spawn_point(&mut global_rng);
+        spawn_car(&mut commands, &assets, color).insert((
+            Transform::from_translation(position.extend(0.0)),
+            AiDriver::new(settings.skill),
+            RaceProgress::default(),
+            Rival(index),
+        ));
+    }
+}
+
+/// Where the car wants to be: the next gate on race levels, else the closest
+/// pickup or bonus barrel, else anywhere it can drop barrels on
+fn pick_target(
+    position: Vec2,
+    driver: &mut AiDriver,
+    progress: Option<&RaceProgress>,
+    pickups: &[Vec2],
+    dangers: &BarrelDangers,
+    rules: &LevelRules,
+    global_rng: &mut GlobalRng,
+) -> Vec2 {
+    if let Some(gate) = progress.and_then(|progress| rules.checkpoints.get(progress.next_gate())) {
+        return (gate.from + gate.to) * 0.5;
+    }
+    let bonuses = dangers
+        .0
+        .iter()
+        .filter(|danger| danger.kind == BarrelKind::Bonus)
+        .map(|danger| danger.position);
+    if let Some(closest) = pickups
+        .iter()
+        .copied()
+        .chain(bonuses)
+        .min_by(|a, b| a.distance(position).total_cmp(&b.distance(position)))
+    {
+        return closest;
+    }
+    match driver.wander {
+        Some(wander) if wander.distance(position) > ARRIVE_DISTANCE => wander,
+        _ => *driver.wander.insert(rules.random_spawn_point(global_rng)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn plan_routes(
+    mut query: Query<(&Transform, &Movement, &mut AiDriver, Option<&RaceProgress>)>,
+    order_query: Query<(Entity, Option<&PlayerId>, Option<&Rival>), With<AiDriver>>,
+    pickup_query: Query<&Transform, With<Pickup>>,
+    obstacle_query: Query<(&Transform, &Collider), With<Static>>,
+    dangers: Res<BarrelDangers>,
+    rules: Res<LevelRules>,
+    mut global_rng: ResMut<GlobalRng>,
+    time: Res<Time>,
+) {
+    let pickups: Vec<Vec2> = pickup_query
+        .iter()
+        .map(|transform| transform.translation.truncate())
+        .collect();
+    let probe = Collider {
+        shape: Shape::Circle {
+            radius: PROBE_RADIUS,
+        },
+        yaw: 0.0,
+    };
+    let half_arena = Vec2::new(WINDOW_WIDTH, WINDOW_HEIGHT) * 0.5 - Vec2::splat(EDGE_MARGIN);
+
+    // Drivers draw random numbers one after the other, in the same order on every peer
+    let mut drivers: Vec<_> = order_query.iter().collect();
+    drivers.sort_unstable_by_key(|(_, id, rival)| car_order(*id, *rival));
+    for (entity, ..) in drivers {
+        let Ok((transform, movement, mut driver, progress)) = query.get_mut(entity) else {
+            continue;
+        };
+        driver.think.tick(time.delta());
+        if !driver.think.just_finished() {
+            continue;
+        }
+        let skill = driver.skill;
+        let position = transform.translation.truncate();
+        let target = pick_target(
+            position,
+            &mut driver,
+            progress,
+            &pickups,
+            &dangers,
+            &rules,
+            &mut global_rng,
+        );
+        let mut direction = (target - position).normalize_or_zero();
+
+        // Keep clear of barrels about to go off, mines are always a threat
+        let mut threatened = false;
+        for danger in &dangers.0 {
+            let close_to_blast = danger.fuse.is_some_and(|fuse| fuse < skill.caution());
+            if danger.radius <= 0.0 || !(close_to_blast || danger.kind == BarrelKind::Mine) {
+                continue;
+            }
+            let reach = danger.radius + BLAST_MARGIN;
+            let away = position - danger.position;
+            let distance = away.length();
+            if distance >= reach * 2.0 {
+                continue;
+            }
+            threatened = true;
+            direction += away.normalize_or_zero() * (2.0 - distance / reach) * 2.0;
+        }
+
+        // Steer around whatever is right ahead
+        let heading = Vec2::from_angle(movement.heading());
+        let ahead = position + heading * movement.velocity.length() * LOOK_AHEAD;
+        for (obstacle_transform, collider) in &obstacle_query {
+            if let Some(contact) = collide(
+                &probe,
+                ahead,
+                collider,
+                obstacle_transform.translation.truncate(),
+            ) {
+                direction += contact.normal * 1.5;
+            }
+        }
+
+        if rules.edges != EdgeMode::Wrap {
+            let outside = position.abs() - half_arena;
+            if outside.x > 0.0 {
+                direction.x -= position.x.signum() * outside.x / EDGE_MARGIN * 2.0;
+            }
+            if outside.y > 0.0 {
+                direction.y -= position.y.signum() * outside.y / EDGE_MARGIN * 2.0;
+            }
+        }
+
+        if direction != Vec2::ZERO {
+            driver.heading =
+                direction.y.atan2(direction.x) + global_rng.f32_normalized() * skill.aim_error();
+        }
+        driver.wants_boost = skill.boosts() && !threatened;
+    }
+}
+
+fn drive(mut query: Query<(&Movement, &AiDriver, &mut CarInput)>) {
+    for (movement, driver, mut input) in &mut query {
+        let off_course = (driver.heading - movement.heading() + PI).rem_euclid(TAU) - PI;
+        *input = CarInput {
+            steer: (off_course * STEER_GAIN).clamp(-1.0, 1.0),
+            drift: off_course.abs() > DRIFT_ANGLE,
+            boost: driver.wants_boost && off_course.abs() < BOOST_ANGLE,
+        };
+    }
+}
+
+/// I adds a rival up to the limit and back to none, K changes their skill
+fn change_rivals(
+    keys: Res<Input<KeyCode>>,
+    mut settings: ResMut<RivalSettings>,
+    mut new_rivals: EventWriter<NewRivals>,
+) {
+    if keys.just_pressed(KeyCode::I) {
+        settings.count = (settings.count + 1) % (MAX_RIVALS + 1);
+    }
+    if keys.just_pressed(KeyCode::K) {
+        settings.skill = settings.skill.next();
+    }
+    if settings.is_changed() {
+        new_rivals.send(NewRivals {
+            count: settings.count,
+            skill: settings.skill.name(),
+        });
+    }
+}
+
+fn send_rivals(settings: Res<RivalSettings>, mut new_rivals: EventWriter<NewRivals>) {
+    new_rivals.send(NewRivals {
+        count: settings.count,
+        skill: settings.skill.name(),
+    });
+}
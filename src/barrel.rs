@@ -9,104 +9,390 @@ use bevy::{
     ecs::system::SystemId,
     prelude::*,
     sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+    utils::HashMap,
 };
 use bevy_turborand::prelude::*;
-use interpolation::Ease;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 use crate::{
+    ai::Rival,
     camera,
-    config::{P8_GREY, P8_RED, WINDOW_HEIGHT, WINDOW_WIDTH},
-    particle, TILE_SIZE,
+    config::{P8_DARK_BLUE, P8_RED, WINDOW_HEIGHT, WINDOW_WIDTH},
+    drop::{update_drops, DropIn, LandedEvent},
+    edge::Wrapping,
+    health::DamageEvent,
+    level::LevelRules,
+    net::RollbackApp,
+    obstacle::Obstacle,
+    particle,
+    pickup::PickupSet,
+    player::{car_order, BonusScore, Movement, Player, PlayerId},
+    shadow::CastShadow,
+    spectate::{self, ViewSet},
+    stacked_sprite::{spawn_slices, StackedSlice, StackedSprite},
+    vox::{self, VoxModel, VoxModels},
+    GameState, TILE_SIZE,
 };
 
+/// Frames of the `barrel.png` strip
+const CLASSIC_SLICES: usize = 12;
 const SPAWN_MIN_DELAY: f32 = 0.4;
 const SPAWN_ANIMATION_DURATION: f32 = 2.5;
-const EXPLOSION_ANIMATION_DURATION: f32 = 5.0;
+const SPAWN_ATTEMPTS: usize = 24;
+const SPAWN_CAR_DISTANCE: f32 = TILE_SIZE * 4.0;
+const SPAWN_BARREL_DISTANCE: f32 = TILE_SIZE * 1.5;
+/// Share of the required room a spot needs when no spot has all of it
+const SPAWN_FALLBACK_ROOM: f32 = 0.5;
+/// Barrels start landing in front of the car from this difficulty on
+const SPAWN_AHEAD_DIFICULTY: f32 = 6.0;
+const SPAWN_AHEAD_MAX_CHANCE: f64 = 0.6;
+const SPAWN_AHEAD_DISTANCE: f32 = TILE_SIZE * 7.0;
+const SPAWN_AHEAD_SPREAD: f32 = TILE_SIZE * 2.0;
 
-#[derive(Component)]
+const START_DIFICULTY: f32 = 4.0;
+pub(crate) const MAX_DIFICULTY: f32 = 10.0;
+/// Difficulty gained every second
+const DIFICULTY_RAMP: f32 = 0.05;
+
+const MINE_SPEED: f32 = 35.0;
+const MINE_TRIGGER_RADIUS: f32 = TILE_SIZE;
+const BONUS_PICKUP_RADIUS: f32 = TILE_SIZE;
+/// Distance at which the car bumps into a standing barrel
+const BARREL_HIT_RADIUS: f32 = TILE_SIZE * 0.75;
+const BONUS_POINTS: usize = 10;
+const OIL_SLICK_RADIUS: f32 = TILE_SIZE * 2.0;
+const OIL_SLICK_DURATION: f32 = 8.0;
+const OIL_SLICK_Z: f32 = 1.0;
+/// Longest fuse left to a barrel caught in a blast
+const CHAIN_FUSE: f32 = 0.3;
+const CHAIN_POINTS: usize = 5;
+const CHAIN_SHAKE_STEP: f32 = 0.5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BarrelKind {
+    /// Plain barrel with a random fuse
+    Classic,
+    /// Detonates every barrel inside its blast right away
+    Chain,
+    /// Leaves an oil slick that takes the grip away from the car
+    Oil,
+    /// Creeps towards the car and explodes on contact
+    Mine,
+    /// Gives points when the car drives over it
+    Bonus,
+}
+
+impl BarrelKind {
+    const ALL: [BarrelKind; 5] = [
+        BarrelKind::Classic,
+        BarrelKind::Chain,
+        BarrelKind::Oil,
+        BarrelKind::Mine,
+        BarrelKind::Bonus,
+    ];
+}
+
+struct BarrelType {
+    model: Handle<VoxModel>,
+    custom_size: Option<Vec2>,
+    flip_x: bool,
+    /// Relative weight against the other types when picking what spawns
+    spawn_weight: f64,
+    fuse: (f32, f32),
+    explosion_duration: f32,
+    blast_radius: f32,
+    /// Speed given to a car right at the center of the blast
+    knockback: f32,
+    /// Turn speed given to a car right at the center of the blast
+    spin: f32,
+    effect: particle::Effect,
+    shake: f32,
+}
+
+#[derive(Resource)]
+struct BarrelRegistry(HashMap<BarrelKind, BarrelType>);
+
+#[derive(Component, Clone)]
 struct BarrelManager {
     dificulty: f32,
     spawn_time: Timer,
     spawn_system: SystemId,
 }
 
-#[derive(Component)]
-struct Barrel;
-
-#[derive(Component)]
-struct BarrelSpawnAnimation {
-    time: Timer,
+#[derive(Component, Clone)]
+struct Barrel {
+    kind: BarrelKind,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 struct BarrelAlive {
     time: Timer,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 struct BarrelExplosionAnimation {
     time: Timer,
 }
 
+/// Red circle growing under a barrel about to explode
 #[derive(Component)]
-struct BarrelSprite(usize);
+struct BarrelBlast;
+
+/// How many explosions led to this barrel going off
+#[derive(Component, Clone)]
+struct ChainDepth(usize);
+
+#[derive(Component, Clone)]
+pub struct OilSlick {
+    pub radius: f32,
+    time: Timer,
+}
+
+#[derive(Event)]
+pub struct BarrelExplodedEvent {
+    pub position: Vec3,
+    pub radius: f32,
+    pub kind: BarrelKind,
+    pub chain_depth: usize,
+    pub knockback: f32,
+    pub spin: f32,
+}
+
+/// Takes away every barrel in range without letting it go off
+#[derive(Event)]
+pub struct DefuseEvent {
+    pub position: Vec3,
+    pub radius: f32,
+}
+
+/// Where a barrel is going to go off and how soon
+#[derive(Clone)]
+pub struct Danger {
+    pub position: Vec2,
+    pub kind: BarrelKind,
+    /// Zero for barrels that do not blow up, like bonus barrels
+    pub radius: f32,
+    /// Seconds left before the blast, `None` while the barrel is still falling
+    pub fuse: Option<f32>,
+}
+
+/// Every barrel in the arena, refreshed each frame for drivers to keep clear
+#[derive(Resource, Default, Clone)]
+pub struct BarrelDangers(pub Vec<Danger>);
+
+/// What spectators are shown of a barrel
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BarrelView {
+    /// Same for as long as the barrel is around
+    pub id: u64,
+    pub kind: BarrelKind,
+    pub position: Vec2,
+    pub yaw: f32,
+    /// Height left to fall
+    pub elevation: f32,
+    /// Share of the explosion already played, `None` before the fuse runs out
+    pub explosion: Option<f32>,
+}
 
+/// Barrels sent to spectators or received from the watched game
+#[derive(Resource, Default)]
+pub struct BarrelViews(pub Vec<BarrelView>);
+
+/// Barrel drawn from a `BarrelView` by a spectator
 #[derive(Component)]
-struct BarrelShadow;
+struct ShownBarrel(u64);
 
 #[derive(Resource)]
 struct BarrelAssets {
-    sprite_texture: Handle<TextureAtlas>,
-    shadow_mesh: Mesh2dHandle,
-    shadow_material: Handle<ColorMaterial>,
-    explosion_material: Handle<ColorMaterial>,
+    blast_mesh: Mesh2dHandle,
+    blast_material: Handle<ColorMaterial>,
+    slick_material: Handle<ColorMaterial>,
 }
 
-#[derive(Resource)]
+#[derive(Resource, Clone)]
 struct BarrelCount(f32);
 
+/// Barrels go off and hit cars in this set, the systems reading what
+/// happened go after it
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BarrelSet;
+
 pub struct Plug;
 impl Plugin for Plug {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, (load_barrel, setup_manager))
+        app.add_event::<BarrelExplodedEvent>()
+            .add_event::<DefuseEvent>()
+            .add_systems(Startup, (load_barrel, setup_manager))
             .add_systems(
                 Update,
                 (
                     manage_barrels,
-                    update_barrel_explosion,
+                    land_barrels.after(update_drops),
                     update_barrel_alive,
-                    update_barrel_spawn_animation,
-                ),
+                    creep_mines,
+                    update_barrel_explosion.in_set(BarrelSet),
+                    chain_reaction,
+                    collect_bonus_barrels.in_set(BarrelSet),
+                    hit_cars.in_set(BarrelSet),
+                    update_oil_slicks,
+                    defuse_barrels,
+                    update_dangers,
+                )
+                    .chain()
+                    .after(PickupSet)
+                    // Barrels brought back by a rollback can go off right away,
+                    // after their slices are added, not before
+                    .after(spawn_slices)
+                    .run_if(in_state(GameState::Playing)),
             )
-            .insert_resource(BarrelCount(0.0));
+            .add_systems(OnEnter(GameState::Playing), clear_barrels)
+            .add_systems(
+                PostUpdate,
+                view_barrels.in_set(ViewSet).run_if(spectate::broadcasting),
+            )
+            .add_systems(Update, show_barrels.run_if(in_state(GameState::Spectating)))
+            .init_resource::<BarrelViews>()
+            .insert_resource(BarrelCount(0.0))
+            .init_resource::<BarrelDangers>()
+            .rollback_entities::<Barrel>()
+            .rollback_entities::<OilSlick>()
+            .rollback_entities::<BarrelManager>()
+            .rollback_component::<Barrel>()
+            .rollback_component::<BarrelAlive>()
+            .rollback_component::<BarrelExplosionAnimation>()
+            .rollback_component::<ChainDepth>()
+            .rollback_component::<OilSlick>()
+            .rollback_component::<BarrelManager>()
+            .rollback_component::<Mesh2dHandle>()
+            .rollback_component::<Handle<ColorMaterial>>()
+            .rollback_resource::<BarrelCount>()
+            .rollback_resource::<BarrelDangers>();
     }
 }
 
 fn load_barrel(
     asset_server: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut models: ResMut<Assets<VoxModel>>,
+    mut vox_models: ResMut<VoxModels>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
     let texture_handle = asset_server.load("barrel.png");
-    let texture_atlas =
-        TextureAtlas::from_grid(texture_handle, Vec2::splat(TILE_SIZE), 12, 1, None, None);
-    let texture_atlas_handle = texture_atlases.add(texture_atlas);
+    let texture_atlas = TextureAtlas::from_grid(
+        texture_handle,
+        Vec2::splat(TILE_SIZE),
+        CLASSIC_SLICES,
+        1,
+        None,
+        None,
+    );
+    // The hand drawn barrel is stacked like the voxel ones
+    let classic_model = models.add(VoxModel {
+        atlas: texture_atlases.add(texture_atlas),
+        slices: CLASSIC_SLICES,
+    });
     commands.insert_resource(BarrelAssets {
-        sprite_texture: texture_atlas_handle,
-        shadow_mesh: meshes
+        blast_mesh: meshes
             .add(shape::Circle::new(TILE_SIZE * 0.5).into())
             .into(),
-        shadow_material: materials.add(P8_GREY.into()),
-        explosion_material: materials.add(P8_RED.into()),
+        blast_material: materials.add(P8_RED.into()),
+        slick_material: materials.add(P8_DARK_BLUE.into()),
     });
+
+    // Voxel barrels are half the resolution of the hand drawn one
+    let voxel_size = Some(Vec2::splat(TILE_SIZE));
+    let mut registry = HashMap::new();
+    registry.insert(
+        BarrelKind::Classic,
+        BarrelType {
+            model: classic_model,
+            custom_size: None,
+            flip_x: true,
+            spawn_weight: 1.0,
+            fuse: (0.0, 10.0),
+            explosion_duration: 5.0,
+            blast_radius: TILE_SIZE * 1.25,
+            knockback: 500.0,
+            spin: 0.0,
+            effect: particle::Effect::Fire,
+            shake: 0.25,
+        },
+    );
+    registry.insert(
+        BarrelKind::Chain,
+        BarrelType {
+            model: vox_models.load(&asset_server, "barrel_chain.vox"),
+            custom_size: voxel_size,
+            flip_x: false,
+            spawn_weight: 0.35,
+            fuse: (2.0, 8.0),
+            explosion_duration: 3.0,
+            blast_radius: TILE_SIZE * 2.0,
+            knockback: 650.0,
+            spin: 4.0,
+            effect: particle::Effect::Fire,
+            shake: 0.35,
+        },
+    );
+    registry.insert(
+        BarrelKind::Oil,
+        BarrelType {
+            model: vox_models.load(&asset_server, "barrel_oil.vox"),
+            custom_size: voxel_size,
+            flip_x: false,
+            spawn_weight: 0.35,
+            fuse: (3.0, 10.0),
+            explosion_duration: 4.0,
+            blast_radius: TILE_SIZE * 1.5,
+            knockback: 350.0,
+            spin: 6.0,
+            effect: particle::Effect::Oil,
+            shake: 0.2,
+        },
+    );
+    registry.insert(
+        BarrelKind::Mine,
+        BarrelType {
+            model: vox_models.load(&asset_server, "mine.vox"),
+            custom_size: voxel_size,
+            flip_x: false,
+            spawn_weight: 0.2,
+            fuse: (8.0, 14.0),
+            explosion_duration: 1.5,
+            blast_radius: TILE_SIZE * 1.75,
+            knockback: 600.0,
+            spin: 0.0,
+            effect: particle::Effect::Fire,
+            shake: 0.3,
+        },
+    );
+    registry.insert(
+        BarrelKind::Bonus,
+        BarrelType {
+            model: vox_models.load(&asset_server, "barrel_bonus.vox"),
+            custom_size: voxel_size,
+            flip_x: false,
+            spawn_weight: 0.35,
+            fuse: (6.0, 10.0),
+            // Bonus barrels fizzle out instead of exploding
+            explosion_duration: 1.0,
+            blast_radius: 0.0,
+            knockback: 0.0,
+            spin: 0.0,
+            effect: particle::Effect::Sparkle,
+            shake: 0.0,
+        },
+    );
+    commands.insert_resource(BarrelRegistry(registry));
 }
 
 fn setup_manager(world: &mut World) {
     let spawn_barrel = world.register_system(spawn_barrel);
     world.spawn(BarrelManager {
-        dificulty: 4.0,
+        dificulty: START_DIFICULTY,
         spawn_time: Timer::from_seconds(SPAWN_MIN_DELAY, TimerMode::Once),
         spawn_system: spawn_barrel,
     });
@@ -117,9 +403,15 @@ fn manage_barrels(
     mut query: Query<&mut BarrelManager>,
     mut commands: Commands,
     mut global_rng: ResMut<GlobalRng>,
+    rules: Res<LevelRules>,
     time: Res<Time>,
 ) {
     let mut barrel_manager = query.single_mut();
+    let ramp = rules.dificulty.ramp.unwrap_or(DIFICULTY_RAMP);
+    barrel_manager.dificulty = f32::min(
+        rules.dificulty.max.unwrap_or(MAX_DIFICULTY),
+        barrel_manager.dificulty + ramp * time.delta_seconds(),
+    );
     barrel_manager.spawn_time.tick(time.delta());
     if barrel_manager.spawn_time.finished() {
         barrel_manager.spawn_time =
@@ -131,16 +423,113 @@ fn manage_barrels(
     }
 }
 
+/// Position and velocity of every car, in `car_order` so every peer samples
+/// the same car from the same random numbers
+pub(crate) fn car_motions<'a>(
+    cars: impl Iterator<Item = (Option<&'a PlayerId>, Option<&'a Rival>, Vec2, Vec2)>,
+) -> Vec<(Vec2, Vec2)> {
+    let mut cars: Vec<_> = cars.collect();
+    cars.sort_unstable_by_key(|(id, rival, ..)| car_order(*id, *rival));
+    cars.into_iter()
+        .map(|(_, _, position, velocity)| (position, velocity))
+        .collect()
+}
+
+/// Picks where the next barrel lands, away from the cars and the other barrels.
+/// Returns `None` when there is no room left anywhere.
+pub(crate) fn find_spawn_position(
+    global_rng: &mut GlobalRng,
+    rules: &LevelRules,
+    dificulty: f32,
+    cars: &[(Vec2, Vec2)],
+    barrels: &[Vec2],
+) -> Option<Vec2> {
+    let limit = Vec2::new(WINDOW_WIDTH - TILE_SIZE, WINDOW_HEIGHT - TILE_SIZE) * 0.5;
+    let ahead_chance = f64::from(
+        ((dificulty - SPAWN_AHEAD_DIFICULTY) / (MAX_DIFICULTY - SPAWN_AHEAD_DIFICULTY))
+            .clamp(0.0, 1.0),
+    ) * SPAWN_AHEAD_MAX_CHANCE;
+
+    let mut roomiest: Option<(Vec2, f32)> = None;
+    for _ in 0..SPAWN_ATTEMPTS {
+        let candidate = match global_rng.sample(cars).copied() {
+            // Cut a car off by dropping barrels where it is heading
+            Some((position, velocity))
+                if velocity.length_squared() > 1.0 && global_rng.chance(ahead_chance) =>
+            {
+                let spread = Vec2::new(global_rng.f32_normalized(), global_rng.f32_normalized());
+                let ahead = (position
+                    + velocity.normalize() * SPAWN_AHEAD_DISTANCE
+                    + spread * SPAWN_AHEAD_SPREAD)
+                    .clamp(-limit, limit);
+                if rules.in_spawn_zone(ahead) {
+                    ahead
+                } else {
+                    rules.random_spawn_point(global_rng)
+                }
+            }
+            _ => rules.random_spawn_point(global_rng),
+        };
+
+        let car_room = cars
+            .iter()
+            .map(|(position, _)| position.distance(candidate) / SPAWN_CAR_DISTANCE)
+            .fold(f32::INFINITY, f32::min);
+        let barrel_room = barrels
+            .iter()
+            .map(|barrel| barrel.distance(candidate) / SPAWN_BARREL_DISTANCE)
+            .fold(f32::INFINITY, f32::min);
+        let room = car_room.min(barrel_room);
+        if room >= 1.0 {
+            return Some(candidate);
+        }
+        if roomiest.is_none_or(|(_, best)| room > best) {
+            roomiest = Some((candidate, room));
+        }
+    }
+    roomiest
+        .filter(|(_, room)| *room >= SPAWN_FALLBACK_ROOM)
+        .map(|(candidate, _)| candidate)
+}
+
+#[allow(clippy::too_many_arguments)]
 fn spawn_barrel(
     mut commands: Commands,
-    texture_atlas_handle: Res<BarrelAssets>,
+    registry: Res<BarrelRegistry>,
+    barrel_assets: Res<BarrelAssets>,
+    models: Res<Assets<VoxModel>>,
+    manager_query: Query<&BarrelManager>,
+    player_query: Query<(Option<&PlayerId>, Option<&Rival>, &Transform, &Movement), With<Player>>,
+    barrel_query: Query<&Transform, Or<(With<Barrel>, With<Obstacle>)>>,
+    rules: Res<LevelRules>,
+    mut barrel_count: ResMut<BarrelCount>,
     mut global_rng: ResMut<GlobalRng>,
 ) {
-    let x_limit = ((WINDOW_WIDTH - TILE_SIZE) * 0.5) as i32;
-    let y_limit = ((WINDOW_HEIGHT - TILE_SIZE) * 0.5) as i32;
+    let cars = car_motions(player_query.iter().map(|(id, rival, transform, movement)| {
+        (
+            id,
+            rival,
+            transform.translation.truncate(),
+            movement.velocity.truncate(),
+        )
+    }));
+    // Obstacles need the same room around them as barrels
+    let barrels: Vec<Vec2> = barrel_query
+        .iter()
+        .map(|transform| transform.translation.truncate())
+        .collect();
+    let Some(position) = find_spawn_position(
+        &mut global_rng,
+        &rules,
+        manager_query.single().dificulty,
+        &cars,
+        &barrels,
+    ) else {
+        // Give the barrel back, the manager will try again later
+        barrel_count.0 -= 1.0;
+        return;
+    };
 
-    let x = global_rng.i32(-x_limit..=x_limit) as f32;
-    let y = global_rng.i32(-y_limit..=y_limit) as f32;
     let angle = global_rng
         .sample::<f32>(&[
             0.0,
@@ -149,102 +538,99 @@ fn spawn_barrel(
             1.5 * std::f32::consts::PI,
         ])
         .unwrap();
+    let kind = *global_rng
+        .weighted_sample(&BarrelKind::ALL, |(kind, _)| registry.0[kind].spawn_weight)
+        .unwrap();
+    let barrel_type = &registry.0[&kind];
 
-    commands
-        .spawn((
-            SpatialBundle::from_transform(Transform::from_xyz(x, y, 0.0)),
-            BarrelSpawnAnimation {
-                time: Timer::from_seconds(SPAWN_ANIMATION_DURATION, TimerMode::Once),
+    commands.spawn((
+        SpatialBundle::from_transform(Transform::from_translation(position.extend(0.0))),
+        vox::stack(
+            &models,
+            &barrel_type.model,
+            StackedSprite {
+                yaw: *angle,
+                elevation: WINDOW_HEIGHT,
+                custom_size: barrel_type.custom_size,
+                flip_x: barrel_type.flip_x,
+                ..default()
             },
-            Barrel,
-        ))
-        .with_children(|barrel| {
-            let rotation = Quat::from_rotation_z(*angle);
-            for i in 0..12 {
-                let mut transform = Transform::from_xyz(0.0, WINDOW_HEIGHT + i as f32, i as f32);
-                transform.rotation = rotation;
-                barrel.spawn((
-                    SpriteSheetBundle {
-                        texture_atlas: texture_atlas_handle.sprite_texture.clone(),
-                        sprite: TextureAtlasSprite {
-                            index: i,
-                            flip_x: true,
-                            ..default()
-                        },
-                        transform,
-                        ..default()
-                    },
-                    BarrelSprite(i),
-                ));
-            }
-            barrel.spawn((
-                MaterialMesh2dBundle {
-                    mesh: texture_atlas_handle.shadow_mesh.clone(),
-                    material: texture_atlas_handle.shadow_material.clone(),
-                    transform: Transform::from_scale(Vec3::splat(0.0)),
-                    ..default()
-                },
-                BarrelShadow,
-            ));
-        });
+        ),
+        DropIn::new(
+            SPAWN_ANIMATION_DURATION,
+            barrel_assets.blast_material.clone(),
+        ),
+        CastShadow,
+        Wrapping::default(),
+        Barrel { kind },
+    ));
 }
 
-fn update_barrel_spawn_animation(
-    mut barrel_query: Query<
-        (&mut BarrelSpawnAnimation, &Children, Entity),
-        (With<Barrel>, Without<BarrelSprite>, Without<BarrelShadow>),
-    >,
-    mut sprites_query: Query<
-        (&mut Transform, &BarrelSprite),
-        (With<BarrelSprite>, Without<Barrel>, Without<BarrelShadow>),
-    >,
-    mut shadow_query: Query<
-        &mut Transform,
-        (With<BarrelShadow>, Without<Barrel>, Without<BarrelSprite>),
-    >,
-    time: Res<Time>,
+fn land_barrels(
+    mut landed_event: EventReader<LandedEvent>,
+    barrel_query: Query<(&Barrel, &Transform)>,
+    registry: Res<BarrelRegistry>,
     mut commands: Commands,
     mut barrel_count: ResMut<BarrelCount>,
     mut global_rng: ResMut<GlobalRng>,
 ) {
-    for (mut barrel_animation_props, children, entity) in &mut barrel_query {
-        barrel_animation_props.time.tick(time.delta());
-        if barrel_animation_props.time.finished() {
-            // Remove 1/4 of a barrel
-            barrel_count.0 -= 0.25;
-            commands.entity(entity).remove::<BarrelSpawnAnimation>();
-            commands.entity(entity).insert(BarrelAlive {
-                time: Timer::from_seconds(global_rng.f32() * 10.0, TimerMode::Once),
-            });
-        }
-        let percent = barrel_animation_props.time.percent().bounce_out();
-        let current_height = f32::max(0.0, WINDOW_HEIGHT - (WINDOW_HEIGHT * percent));
-        for child in children {
-            if let Ok((mut transform, index)) = sprites_query.get_mut(*child) {
-                transform.translation =
-                    Vec3::new(0.0, current_height + index.0 as f32, index.0 as f32);
-            } else if let Ok(mut transform) = shadow_query.get_mut(*child) {
-                transform.scale = Vec3::new(percent, percent, 0.0);
-            }
-        }
+    // Barrels never land on top of each other, their positions give every
+    // peer the same order to draw the fuses in
+    let mut landed: Vec<(Entity, &Barrel, Vec2)> = landed_event
+        .read()
+        .filter_map(|landed| {
+            let (barrel, transform) = barrel_query.get(landed.entity).ok()?;
+            Some((landed.entity, barrel, transform.translation.truncate()))
+        })
+        .collect();
+    landed.sort_unstable_by(|(_, _, a), (_, _, b)| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    for (entity, barrel, _) in landed {
+        // Remove 1/4 of a barrel
+        barrel_count.0 -= 0.25;
+        let (min_fuse, max_fuse) = registry.0[&barrel.kind].fuse;
+        commands.entity(entity).insert(BarrelAlive {
+            time: Timer::from_seconds(
+                min_fuse + global_rng.f32() * (max_fuse - min_fuse),
+                TimerMode::Once,
+            ),
+        });
     }
 }
 
 fn update_barrel_alive(
-    mut barrel_query: Query<(&mut BarrelAlive, Entity), With<Barrel>>,
+    mut barrel_query: Query<(&mut BarrelAlive, &Barrel, Entity)>,
+    registry: Res<BarrelRegistry>,
     time: Res<Time>,
     mut commands: Commands,
     mut barrel_count: ResMut<BarrelCount>,
+    barrel_assets: Res<BarrelAssets>,
 ) {
-    for (mut barrel_animation_props, entity) in &mut barrel_query {
+    for (mut barrel_animation_props, barrel, entity) in &mut barrel_query {
         barrel_animation_props.time.tick(time.delta());
         if barrel_animation_props.time.finished() {
             // Remove 1/4 of a barrel
             barrel_count.0 -= 0.25;
-            commands.entity(entity).remove::<BarrelAlive>();
-            commands.entity(entity).insert(BarrelExplosionAnimation {
-                time: Timer::from_seconds(EXPLOSION_ANIMATION_DURATION, TimerMode::Once),
-            });
+            let barrel_type = &registry.0[&barrel.kind];
+            let mut entity = commands.entity(entity);
+            entity
+                .remove::<BarrelAlive>()
+                .insert(BarrelExplosionAnimation {
+                    time: Timer::from_seconds(barrel_type.explosion_duration, TimerMode::Once),
+                });
+            if barrel_type.blast_radius > 0.0 {
+                entity.with_children(|barrel| {
+                    barrel.spawn((
+                        MaterialMesh2dBundle {
+                            mesh: barrel_assets.blast_mesh.clone(),
+                            material: barrel_assets.blast_material.clone(),
+                            // Right under the barrel slices
+                            transform: Transform::from_xyz(0.0, 0.0, -0.001),
+                            ..default()
+                        },
+                        BarrelBlast,
+                    ));
+                });
+            }
         }
     }
 }
@@ -252,48 +638,478 @@ fn update_barrel_alive(
 #[allow(clippy::too_many_arguments)]
 fn update_barrel_explosion(
     mut barrel_query: Query<
-        (&mut BarrelExplosionAnimation, &Transform, &Children, Entity),
-        (With<Barrel>, Without<BarrelSprite>, Without<BarrelShadow>),
-    >,
-    mut sprites_query: Query<
-        (&mut Transform, &BarrelSprite),
-        (With<BarrelSprite>, Without<Barrel>, Without<BarrelShadow>),
-    >,
-    mut shadow_query: Query<
-        (&mut Transform, &mut Handle<ColorMaterial>),
-        (With<BarrelShadow>, Without<Barrel>, Without<BarrelSprite>),
+        (
+            &mut BarrelExplosionAnimation,
+            &Barrel,
+            &Transform,
+            Option<&Children>,
+            Option<&ChainDepth>,
+            Entity,
+        ),
+        Without<BarrelBlast>,
     >,
+    mut slices_query: Query<&mut StackedSlice>,
+    mut blast_query: Query<&mut Transform, (With<BarrelBlast>, Without<Barrel>)>,
+    registry: Res<BarrelRegistry>,
+    barrel_assets: Res<BarrelAssets>,
     mut barrel_count: ResMut<BarrelCount>,
     mut commands: Commands,
     mut spawn_event: EventWriter<particle::SpawnEvent>,
     mut shake_event: EventWriter<camera::ShakeCameraEvent>,
-    texture_atlas_handle: Res<BarrelAssets>,
+    mut exploded_event: EventWriter<BarrelExplodedEvent>,
+    mut bonus_score: EventWriter<BonusScore>,
     time: Res<Time>,
 ) {
-    for (mut barrel_props, pos, children, entity) in &mut barrel_query {
+    for (mut barrel_props, barrel, pos, children, chain_depth, entity) in &mut barrel_query {
+        let barrel_type = &registry.0[&barrel.kind];
         barrel_props.time.tick(time.delta());
         if barrel_props.time.finished() {
             commands.entity(entity).despawn_recursive();
             // Remove 2/4 of a barrel
             barrel_count.0 -= 0.50;
-            spawn_event.send(particle::SpawnEvent(pos.translation));
-            shake_event.send(camera::ShakeCameraEvent(0.25));
+            spawn_event.send(particle::SpawnEvent {
+                position: pos.translation,
+                effect: barrel_type.effect,
+            });
+            let chain_depth = chain_depth.map_or(0, |depth| depth.0);
+            if barrel_type.shake > 0.0 {
+                // Every link of a chain shakes harder than the last one
+                shake_event.send(camera::ShakeCameraEvent(
+                    barrel_type.shake * (1.0 + chain_depth as f32 * CHAIN_SHAKE_STEP),
+                ));
+            }
+            if chain_depth > 0 {
+                bonus_score.send(BonusScore {
+                    car: None,
+                    points: CHAIN_POINTS * chain_depth,
+                });
+            }
+            if barrel_type.blast_radius > 0.0 {
+                exploded_event.send(BarrelExplodedEvent {
+                    position: pos.translation,
+                    radius: barrel_type.blast_radius,
+                    kind: barrel.kind,
+                    chain_depth,
+                    knockback: barrel_type.knockback,
+                    spin: barrel_type.spin,
+                });
+            }
+            if barrel.kind == BarrelKind::Oil {
+                commands.spawn((
+                    MaterialMesh2dBundle {
+                        mesh: barrel_assets.blast_mesh.clone(),
+                        material: barrel_assets.slick_material.clone(),
+                        transform: Transform::from_translation(
+                            pos.translation.truncate().extend(OIL_SLICK_Z),
+                        )
+                        .with_scale(Vec3::splat(OIL_SLICK_RADIUS / (TILE_SIZE * 0.5))),
+                        ..default()
+                    },
+                    OilSlick {
+                        radius: OIL_SLICK_RADIUS,
+                        time: Timer::from_seconds(OIL_SLICK_DURATION, TimerMode::Once),
+                    },
+                ));
+            }
         }
-        for child in children {
-            if let Ok((mut transform, index)) = sprites_query.get_mut(*child) {
-                let percent = barrel_props.time.percent() * 0.25;
-                let sin = ((barrel_props.time.percent() + index.0 as f32 * 0.11) * 40.0).sin()
-                    * 0.15
-                    + 0.15;
-                transform.scale = Vec3::new(1.0 + percent + sin, 1.0 + percent + sin, 1.0);
+        let percent = barrel_props.time.percent();
+        for child in children.into_iter().flatten() {
+            if let Ok(mut slice) = slices_query.get_mut(*child) {
+                slice.scale = swell(percent, slice.index);
+            }
+            if let Ok(mut transform) = blast_query.get_mut(*child) {
+                transform.scale = blast_scale(barrel_type, percent);
             }
-            if let Ok((mut transform, mut color)) = shadow_query.get_mut(*child) {
-                transform.scale = Vec3::new(
-                    1.0 + (barrel_props.time.percent() * 1.5),
-                    1.0 + (barrel_props.time.percent() * 1.5),
-                    1.0,
+        }
+    }
+}
+
+/// Slices of a barrel about to go off swell and wobble
+fn swell(percent: f32, index: usize) -> f32 {
+    let sin = ((percent + index as f32 * 0.11) * 40.0).sin() * 0.15 + 0.15;
+    1.0 + percent * 0.25 + sin
+}
+
+/// The blast marker grows until it covers the blast radius
+fn blast_scale(barrel_type: &BarrelType, percent: f32) -> Vec3 {
+    let grown = 1.0 + percent * (barrel_type.blast_radius / (TILE_SIZE * 0.5) - 1.0);
+    Vec3::new(grown, grown, 1.0)
+}
+
+fn view_barrels(
+    query: Query<(
+        Entity,
+        &Barrel,
+        &Transform,
+        &StackedSprite,
+        Option<&BarrelExplosionAnimation>,
+    )>,
+    mut views: ResMut<BarrelViews>,
+) {
+    views.0 = query
+        .iter()
+        .map(|(entity, barrel, transform, stack, explosion)| BarrelView {
+            id: entity.to_bits(),
+            kind: barrel.kind,
+            position: transform.translation.truncate(),
+            yaw: stack.yaw,
+            elevation: stack.elevation,
+            explosion: explosion.map(|explosion| explosion.time.percent()),
+        })
+        .collect();
+}
+
+/// Keeps a barrel on screen for every view a spectator received
+#[allow(clippy::too_many_arguments)]
+fn show_barrels(
+    mut commands: Commands,
+    views: Res<BarrelViews>,
+    registry: Res<BarrelRegistry>,
+    barrel_assets: Res<BarrelAssets>,
+    models: Res<Assets<VoxModel>>,
+    mut shown_query: Query<(
+        Entity,
+        &ShownBarrel,
+        &mut Transform,
+        &mut StackedSprite,
+        Option<&Children>,
+    )>,
+    mut slices_query: Query<&mut StackedSlice>,
+    mut blast_query: Query<
+        (&mut Transform, &mut Visibility),
+        (With<BarrelBlast>, Without<ShownBarrel>),
+    >,
+) {
+    for (entity, shown, ..) in &shown_query {
+        if !views.0.iter().any(|view| view.id == shown.0) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for view in &views.0 {
+        let barrel_type = &registry.0[&view.kind];
+        let Some((_, _, mut transform, mut stack, children)) = shown_query
+            .iter_mut()
+            .find(|(_, shown, ..)| shown.0 == view.id)
+        else {
+            commands
+                .spawn((
+                    SpatialBundle::from_transform(Transform::from_translation(
+                        view.position.extend(0.0),
+                    )),
+                    vox::stack(
+                        &models,
+                        &barrel_type.model,
+                        StackedSprite {
+                            yaw: view.yaw,
+                            elevation: view.elevation,
+                            custom_size: barrel_type.custom_size,
+                            flip_x: barrel_type.flip_x,
+                            ..default()
+                        },
+                    ),
+                    CastShadow,
+                    ShownBarrel(view.id),
+                ))
+                .with_children(|barrel| {
+                    if barrel_type.blast_radius > 0.0 {
+                        barrel.spawn((
+                            MaterialMesh2dBundle {
+                                mesh: barrel_assets.blast_mesh.clone(),
+                                material: barrel_assets.blast_material.clone(),
+                                transform: Transform::from_xyz(0.0, 0.0, -0.001),
+                                visibility: Visibility::Hidden,
+                                ..default()
+                            },
+                            BarrelBlast,
+                        ));
+                    }
+                });
+            continue;
+        };
+        transform.translation = view.position.extend(transform.translation.z);
+        stack.yaw = view.yaw;
+        stack.elevation = view.elevation;
+        for child in children.into_iter().flatten() {
+            if let Ok(mut slice) = slices_query.get_mut(*child) {
+                slice.scale = view
+                    .explosion
+                    .map_or(1.0, |percent| swell(percent, slice.index));
+            }
+            if let Ok((mut blast, mut visibility)) = blast_query.get_mut(*child) {
+                *visibility = if view.explosion.is_some() {
+                    Visibility::Inherited
+                } else {
+                    Visibility::Hidden
+                };
+                blast.scale = blast_scale(barrel_type, view.explosion.unwrap_or(0.0));
+            }
+        }
+    }
+}
+
+fn creep_mines(
+    mut mine_query: Query<(&Barrel, &mut Transform, &mut BarrelAlive)>,
+    player_query: Query<&Transform, (With<Player>, Without<Barrel>)>,
+    time: Res<Time>,
+) {
+    for (barrel, mut transform, mut alive) in &mut mine_query {
+        if barrel.kind != BarrelKind::Mine {
+            continue;
+        }
+        // Go after the closest car
+        let Some(to_player) = player_query
+            .iter()
+            .map(|player| (player.translation - transform.translation).truncate())
+            .min_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))
+        else {
+            return;
+        };
+        if to_player.length() < MINE_TRIGGER_RADIUS {
+            let fuse = alive.time.duration();
+            alive.time.set_elapsed(fuse);
+        } else {
+            let step = to_player.normalize() * MINE_SPEED * time.delta_seconds();
+            transform.translation += step.extend(0.0);
+        }
+    }
+}
+
+fn hit_cars(
+    mut barrel_query: Query<(&Barrel, &Transform, &mut BarrelAlive)>,
+    player_query: Query<(&Transform, Entity), (With<Player>, Without<Barrel>)>,
+    mut damage_event: EventWriter<DamageEvent>,
+) {
+    for (barrel, pos, mut alive) in &mut barrel_query {
+        // Bonus barrels are meant to be driven over and mines blow up by themselves
+        if matches!(barrel.kind, BarrelKind::Bonus | BarrelKind::Mine) {
+            continue;
+        }
+        for (player, target) in &player_query {
+            if pos
+                .translation
+                .truncate()
+                .distance(player.translation.truncate())
+                < BARREL_HIT_RADIUS
+            {
+                damage_event.send(DamageEvent { target, amount: 1 });
+                let fuse = alive.time.duration();
+                alive.time.set_elapsed(fuse);
+            }
+        }
+    }
+}
+
+fn clear_barrels(
+    mut commands: Commands,
+    barrel_query: Query<Entity, Or<(With<Barrel>, With<OilSlick>)>>,
+    mut manager_query: Query<&mut BarrelManager>,
+    mut barrel_count: ResMut<BarrelCount>,
+    rules: Res<LevelRules>,
+) {
+    for entity in &barrel_query {
+        commands.entity(entity).despawn_recursive();
+    }
+    let mut barrel_manager = manager_query.single_mut();
+    barrel_manager.dificulty = rules.dificulty.start.unwrap_or(START_DIFICULTY);
+    barrel_manager.spawn_time = Timer::from_seconds(SPAWN_MIN_DELAY, TimerMode::Once);
+    barrel_count.0 = 0.0;
+}
+
+fn collect_bonus_barrels(
+    barrel_query: Query<(&Barrel, &Transform, Entity), With<BarrelAlive>>,
+    player_query: Query<(Entity, &Transform), (With<Player>, Without<Barrel>)>,
+    mut commands: Commands,
+    mut barrel_count: ResMut<BarrelCount>,
+    mut spawn_event: EventWriter<particle::SpawnEvent>,
+    mut bonus_score: EventWriter<BonusScore>,
+) {
+    for (barrel, pos, entity) in &barrel_query {
+        if barrel.kind != BarrelKind::Bonus {
+            continue;
+        }
+        let position = pos.translation.truncate();
+        if let Some((car, _)) = player_query.iter().find(|(_, player)| {
+            position.distance(player.translation.truncate()) < BONUS_PICKUP_RADIUS
+        }) {
+            commands.entity(entity).despawn_recursive();
+            // Remove the last 3/4 of a barrel
+            barrel_count.0 -= 0.75;
+            spawn_event.send(particle::SpawnEvent {
+                position: pos.translation,
+                effect: particle::Effect::Sparkle,
+            });
+            bonus_score.send(BonusScore {
+                car: Some(car),
+                points: BONUS_POINTS,
+            });
+        }
+    }
+}
+
+fn chain_reaction(
+    mut commands: Commands,
+    mut exploded_event: EventReader<BarrelExplodedEvent>,
+    mut barrel_query: Query<(&Transform, &mut BarrelAlive, Option<&ChainDepth>, Entity)>,
+) {
+    for explosion in exploded_event.read() {
+        for (pos, mut alive, chain_depth, entity) in &mut barrel_query {
+            if pos
+                .translation
+                .truncate()
+                .distance(explosion.position.truncate())
+                >= explosion.radius
+            {
+                continue;
+            }
+            let fuse = alive.time.duration();
+            if explosion.kind == BarrelKind::Chain {
+                alive.time.set_elapsed(fuse);
+            } else {
+                let elapsed = alive.time.elapsed();
+                alive.time.set_elapsed(
+                    elapsed.max(fuse.saturating_sub(Duration::from_secs_f32(CHAIN_FUSE))),
                 );
-                *color = texture_atlas_handle.explosion_material.clone();
+            }
+            // Keep the longest chain when several blasts reach the same barrel
+            let depth = explosion.chain_depth + 1;
+            if chain_depth.is_none_or(|current| current.0 < depth) {
+                commands.entity(entity).insert(ChainDepth(depth));
+            }
+        }
+    }
+}
+
+fn defuse_barrels(
+    mut defuse_event: EventReader<DefuseEvent>,
+    barrel_query: Query<(&Transform, Has<DropIn>, Has<BarrelAlive>, Entity), With<Barrel>>,
+    mut commands: Commands,
+    mut barrel_count: ResMut<BarrelCount>,
+    mut spawn_event: EventWriter<particle::SpawnEvent>,
+) {
+    let mut defused = Vec::new();
+    for defuse in defuse_event.read() {
+        for (pos, falling, alive, entity) in &barrel_query {
+            if defused.contains(&entity)
+                || pos
+                    .translation
+                    .truncate()
+                    .distance(defuse.position.truncate())
+                    >= defuse.radius
+            {
+                continue;
+            }
+            defused.push(entity);
+            commands.entity(entity).despawn_recursive();
+            // Give back whatever is left of the barrel
+            barrel_count.0 -= if falling {
+                1.0
+            } else if alive {
+                0.75
+            } else {
+                0.5
+            };
+            spawn_event.send(particle::SpawnEvent {
+                position: pos.translation,
+                effect: particle::Effect::Sparkle,
+            });
+        }
+    }
+}
+
+fn update_oil_slicks(
+    mut slick_query: Query<(&mut OilSlick, &mut Transform, Entity)>,
+    mut commands: Commands,
+    time: Res<Time>,
+) {
+    for (mut slick, mut transform, entity) in &mut slick_query {
+        slick.time.tick(time.delta());
+        if slick.time.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        // Dry up during the last part of its life
+        slick.radius = OIL_SLICK_RADIUS * (1.0 - slick.time.percent().powi(4));
+        transform.scale = Vec3::splat(slick.radius / (TILE_SIZE * 0.5));
+    }
+}
+
+fn update_dangers(
+    barrel_query: Query<(
+        &Barrel,
+        &Transform,
+        Option<&BarrelAlive>,
+        Option<&BarrelExplosionAnimation>,
+    )>,
+    registry: Res<BarrelRegistry>,
+    mut dangers: ResMut<BarrelDangers>,
+) {
+    dangers.0.clear();
+    for (barrel, transform, alive, explosion) in &barrel_query {
+        let barrel_type = &registry.0[&barrel.kind];
+        let fuse = match (alive, explosion) {
+            (Some(alive), _) => Some(alive.time.remaining_secs() + barrel_type.explosion_duration),
+            (None, Some(explosion)) => Some(explosion.time.remaining_secs()),
+            (None, None) => None,
+        };
+        dangers.0.push(Danger {
+            position: transform.translation.truncate(),
+            kind: barrel.kind,
+            radius: barrel_type.blast_radius,
+            fuse,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_arena_has_no_spawn_position() {
+        // Barrels half their spacing apart leave no spot with enough room
+        let step = SPAWN_BARREL_DISTANCE * SPAWN_FALLBACK_ROOM;
+        let mut barrels = Vec::new();
+        let mut x = -WINDOW_WIDTH * 0.5;
+        while x <= WINDOW_WIDTH * 0.5 {
+            let mut y = -WINDOW_HEIGHT * 0.5;
+            while y <= WINDOW_HEIGHT * 0.5 {
+                barrels.push(Vec2::new(x, y));
+                y += step;
+            }
+            x += step;
+        }
+        let rules = LevelRules::default();
+        for seed in 0..10 {
+            let mut global_rng = GlobalRng::with_seed(seed);
+            let position =
+                find_spawn_position(&mut global_rng, &rules, START_DIFICULTY, &[], &barrels);
+            assert_eq!(position, None);
+        }
+    }
+
+    #[test]
+    fn spawn_position_keeps_away_from_cars_and_obstacles() {
+        let rules = LevelRules {
+            spawn_zones: vec![Rect::new(-200.0, -100.0, 350.0, 100.0)],
+            ..default()
+        };
+        // Obstacles fill the left of the zone, a car waits in a corner of the right
+        let mut obstacles = Vec::new();
+        for x in (-200..=0).step_by(16) {
+            for y in (-100..=100).step_by(16) {
+                obstacles.push(Vec2::new(x as f32, y as f32));
+            }
+        }
+        let cars = [(Vec2::new(350.0, 100.0), Vec2::ZERO)];
+        for seed in 0..50 {
+            let mut global_rng = GlobalRng::with_seed(seed);
+            let position =
+                find_spawn_position(&mut global_rng, &rules, START_DIFICULTY, &cars, &obstacles)
+                    .expect("the right of the zone has room");
+            assert!(rules.in_spawn_zone(position));
+            assert!(position.distance(cars[0].0) >= SPAWN_CAR_DISTANCE);
+            for obstacle in &obstacles {
+                assert!(position.distance(*obstacle) >= SPAWN_BARREL_DISTANCE);
             }
         }
     }
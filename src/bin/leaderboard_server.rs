@@ -0,0 +1,284 @@
+//! Reference leaderboard server, keeps the boards in memory and plays every
+//! submitted replay back to check its score. Replays are played on a worker
+//! thread, so the boards can still be read while one is checked.
+//!
+//! `cargo run --bin leaderboard_server -- --port 7780`, then start the game
+//! with `--leaderboard http://127.0.0.1:7780 --name NAME`.
+//! `--verify FILE...` checks replay files instead of serving.
+#![allow(clippy::needless_pass_by_value)]
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, TrySendError},
+        Arc, Mutex,
+    },
+};
+
+use serde::Serialize;
+use super_oxi_car::{
+    leaderboard::{Entry, Submission, SubmitReply},
+    replay::{self, Replay, VerifyError},
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const DEFAULT_PORT: u16 = 7780;
+/// Entries sent by `GET /scores`
+const TOP_ENTRIES: usize = 10;
+/// Longest replay checked, half an hour at 60 frames a second
+const MAX_FRAMES: usize = 60 * 60 * 30;
+/// Largest submission read, enough for a replay of `MAX_FRAMES`
+const MAX_BODY: usize = 16 * 1024 * 1024;
+/// Submissions waiting for the worker, more are turned away until it catches up
+const QUEUE: usize = 8;
+
+/// Scores of one level played in one mode, best first
+type Boards = HashMap<(usize, String), Vec<Entry>>;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut port = DEFAULT_PORT;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = args
+                    .next()
+                    .and_then(|port| port.parse().ok())
+                    .unwrap_or(port);
+            }
+            "--verify" => verify_files(args),
+            _ => (),
+        }
+    }
+    let server = match Server::http(("127.0.0.1", port)) {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("could not listen on port {port}: {error}");
+            std::process::exit(1);
+        }
+    };
+    println!("leaderboard listening on http://127.0.0.1:{port}");
+
+    let boards = Arc::new(Mutex::new(Boards::new()));
+    let (submissions, queue) = mpsc::sync_channel(QUEUE);
+    let worker_boards = Arc::clone(&boards);
+    std::thread::spawn(move || {
+        for mut request in queue {
+            // A replay that crashes the check only loses its own answer
+            let response =
+                panic::catch_unwind(AssertUnwindSafe(|| submit(&worker_boards, &mut request)))
+                    .unwrap_or_else(|_| {
+                        Response::from_string("the replay could not be checked")
+                            .with_status_code(500)
+                    });
+            let _ = request.respond(response);
+        }
+    });
+    for request in server.incoming_requests() {
+        let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+        let (path, query) = (path.to_string(), query.to_string());
+        match (request.method(), path.as_str()) {
+            (Method::Post, "/scores") => {
+                if let Err(TrySendError::Full(request) | TrySendError::Disconnected(request)) =
+                    submissions.try_send(request)
+                {
+                    let busy = Response::from_string("too many runs to check, try again later");
+                    let _ = request.respond(busy.with_status_code(503));
+                }
+            }
+            (Method::Get, "/scores") => {
+                let entries = board(&boards.lock().unwrap(), &query);
+                let _ = request.respond(json(200, &entries));
+            }
+            _ => {
+                let _ = request.respond(Response::empty(404));
+            }
+        }
+    }
+}
+
+fn submit(boards: &Mutex<Boards>, request: &mut Request) -> Response<Cursor<Vec<u8>>> {
+    // The length is not always given, so the body is also cut short
+    let mut body = String::new();
+    let read = request
+        .as_reader()
+        .take(MAX_BODY as u64 + 1)
+        .read_to_string(&mut body);
+    if request
+        .body_length()
+        .is_some_and(|length| length > MAX_BODY)
+        || body.len() > MAX_BODY
+    {
+        return Response::from_string("submission too big").with_status_code(413);
+    }
+    let submission: Submission = match read
+        .map_err(|error| error.to_string())
+        .and_then(|_| serde_json::from_str(&body).map_err(|error| error.to_string()))
+    {
+        Ok(submission) => submission,
+        Err(error) => return Response::from_string(error).with_status_code(400),
+    };
+    let reply = match check(&submission) {
+        Ok(()) => {
+            let mut boards = boards.lock().unwrap();
+            let board = boards
+                .entry((submission.level, submission.mode.name().to_string()))
+                .or_default();
+            let rank = board.partition_point(|entry| entry.score >= submission.score);
+            board.insert(
+                rank,
+                Entry {
+                    name: submission.name.clone(),
+                    score: submission.score,
+                    time: submission.time,
+                    seed: submission.seed,
+                },
+            );
+            println!(
+                "{} scored {} on level {} in {}, rank {}",
+                submission.name,
+                submission.score,
+                submission.level,
+                submission.mode.name(),
+                rank + 1
+            );
+            SubmitReply {
+                accepted: true,
+                rank: Some(rank + 1),
+                reason: None,
+            }
+        }
+        Err(reason) => {
+            println!("turned down a run from {}: {reason}", submission.name);
+            SubmitReply {
+                accepted: false,
+                rank: None,
+                reason: Some(reason),
+            }
+        }
+    };
+    let status = if reply.accepted { 200 } else { 422 };
+    json(status, &reply)
+}
+
+/// Plays the replay back, the claimed score and time have to be the ones it
+/// ends with
+fn check(submission: &Submission) -> Result<(), String> {
+    let replay: Replay =
+        ron::from_str(&submission.replay).map_err(|error| format!("bad replay: {error}"))?;
+    // Boards go by mode name, an easier target or a shorter time limit
+    // would be ranked with the real runs
+    if !submission.mode.is_canonical() {
+        return Err("the mode has rules of its own".to_string());
+    }
+    if replay.seed != submission.seed
+        || replay.level != submission.level
+        || replay.mode != submission.mode
+        || replay.scores.first() != Some(&submission.score)
+        || replay.time.as_secs_f32() != submission.time
+    {
+        return Err("the replay is of another run".to_string());
+    }
+    if replay.frames.len() > MAX_FRAMES {
+        return Err(format!("the replay is over {MAX_FRAMES} frames"));
+    }
+    if replay.players != 1 {
+        return Err("only one player runs are ranked".to_string());
+    }
+    replay::verify(&replay).map_err(|error| error.to_string())?;
+    Ok(())
+}
+
+/// Checks replay files saved with `--record`, the exit code tells whether
+/// they all played back the way they were recorded
+fn verify_files(paths: impl Iterator<Item = String>) -> ! {
+    let mut failed = false;
+    for path in paths {
+        match Replay::load(&path)
+            .map_err(VerifyError::from)
+            .and_then(|replay| replay::verify(&replay))
+        {
+            Ok(summary) => println!(
+                "{path}: ok, {} frames, {:.2?}, scores {:?}",
+                summary.frames, summary.time, summary.scores
+            ),
+            Err(error) => {
+                println!("{path}: {error}");
+                failed = true;
+            }
+        }
+    }
+    std::process::exit(i32::from(failed));
+}
+
+/// Best entries of the board picked by `?level=N&mode=NAME`
+fn board(boards: &Boards, query: &str) -> Vec<Entry> {
+    let mut level = 0;
+    let mut mode = String::new();
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match &*key {
+            "level" => level = value.parse().unwrap_or(0),
+            "mode" => mode = value.into_owned(),
+            _ => (),
+        }
+    }
+    boards
+        .get(&(level, mode))
+        .map(|board| board.iter().take(TOP_ENTRIES).cloned().collect())
+        .unwrap_or_default()
+}
+
+fn json(status: u16, body: &impl Serialize) -> Response<Cursor<Vec<u8>>> {
+    let json = serde_json::to_string(body).unwrap_or_default();
+    let mut response = Response::from_string(json).with_status_code(status);
+    if let Ok(header) = Header::from_bytes("Content-Type", "application/json") {
+        response.add_header(header);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use super_oxi_car::replay::{GameMode, Skill};
+
+    fn submission(mode: GameMode, replay_mode: GameMode) -> Submission {
+        let replay = Replay {
+            seed: 7,
+            level: 0,
+            mode: replay_mode,
+            players: 1,
+            rivals: 0,
+            skill: Skill::Normal,
+            frames: Vec::new(),
+            scores: vec![5],
+            time: Duration::from_secs(2),
+        };
+        Submission {
+            name: "cheater".to_string(),
+            score: 5,
+            time: 2.0,
+            seed: 7,
+            mode,
+            level: 0,
+            replay: ron::to_string(&replay).unwrap(),
+        }
+    }
+
+    #[test]
+    fn tampered_modes_are_turned_down() {
+        let own_rules = Err("the mode has rules of its own".to_string());
+        let easy = GameMode::Collect { target: 1 };
+        let short = GameMode::ScoreAttack { seconds: 1.0 };
+        assert_eq!(check(&submission(easy, easy)), own_rules);
+        assert_eq!(check(&submission(short, short)), own_rules);
+        // The replay has to be of the mode it is ranked in
+        assert_eq!(
+            check(&submission(GameMode::Survival, easy)),
+            Err("the replay is of another run".to_string())
+        );
+    }
+}
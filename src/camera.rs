@@ -6,6 +6,7 @@
     clippy::module_name_repetitions
 )]
 use core::f32;
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
 
 use bevy::{
     core_pipeline::clear_color::ClearColorConfig,
@@ -17,9 +18,12 @@ use bevy::{
     },
 };
 
-use crate::config::{
-    GAME_CAMERA_CLEAR_COLOR, GAME_CAMERA_NAME, GAME_CAMERA_TARGET_NAME, WINDOW_CAMERA_CLEAR_COLOR,
-    WINDOW_CAMERA_NAME, WINDOW_HEIGHT, WINDOW_WIDTH,
+use crate::{
+    config::{
+        GAME_CAMERA_CLEAR_COLOR, GAME_CAMERA_NAME, GAME_CAMERA_TARGET_NAME,
+        WINDOW_CAMERA_CLEAR_COLOR, WINDOW_CAMERA_NAME, WINDOW_HEIGHT, WINDOW_WIDTH,
+    },
+    stacked_sprite::StackedSprite,
 };
 
 const BGRA_PIXEL_SIZE: usize = 4;
@@ -27,9 +31,39 @@ const BGRA_PIXEL_SIZE: usize = 4;
 const CAMERA_SHAKE_SPEED: f32 = 130.0;
 const CAMERA_SHAKE_TO: f32 = 7.0;
 
+const CAMERA_ORBIT_SPEED: f32 = 1.5;
+const CAMERA_FOLLOW_SPEED: f32 = 4.0;
+
 #[derive(Debug, Component)]
 pub struct GameCamera {
     shake_queue: Vec<f32>,
+    shake_offset: f32,
+    yaw: f32,
+}
+
+/// Camera drawing the letterboxed game view to the window
+#[derive(Component)]
+pub struct WindowCamera;
+
+/// Entity the camera lines up with while in `CameraMode::CarUp`
+#[derive(Component)]
+pub struct CameraFocus;
+
+/// Point the view is centered on and how far it is zoomed out, only
+/// spectators move it away from the middle of the play area
+#[derive(Resource, Clone, Copy)]
+pub struct CameraView {
+    pub center: Vec2,
+    pub zoom: f32,
+}
+
+impl Default for CameraView {
+    fn default() -> Self {
+        Self {
+            center: Vec2::ZERO,
+            zoom: 1.0,
+        }
+    }
 }
 
 #[derive(Event)]
@@ -38,12 +72,30 @@ pub struct ShakeCameraEvent(pub f32);
 #[derive(Resource)]
 struct ShakeDirection(f32);
 
+#[derive(Resource, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Rotate the view around the play area with Q and E
+    Orbit,
+    /// Rotate the view with the focused car so it always faces up
+    CarUp,
+}
+
 pub struct Plug;
 impl Plugin for Plug {
     fn build(&self, app: &mut App) {
         app.add_event::<ShakeCameraEvent>()
+            .insert_resource(CameraMode::Orbit)
+            .init_resource::<CameraView>()
             .add_systems(Startup, (setup_camera, init_shake_resource))
-            .add_systems(Update, shake_camera);
+            .add_systems(
+                Update,
+                (
+                    switch_camera_mode,
+                    (shake_camera, rotate_camera),
+                    update_camera_transform,
+                )
+                    .chain(),
+            );
     }
 }
 
@@ -62,6 +114,7 @@ fn setup_camera(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
     commands
         .spawn(windows_camera)
         .insert(Name::new(WINDOW_CAMERA_NAME))
+        .insert(WindowCamera)
         // Only draw layer 1
         .insert(RenderLayers::layer(1));
 
@@ -115,20 +168,45 @@ fn setup_camera(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
         .insert(Name::new(GAME_CAMERA_NAME))
         .insert(GameCamera {
             shake_queue: Vec::new(),
+            shake_offset: 0.0,
+            yaw: 0.0,
         });
 }
 
+/// World position under the cursor, going through the window camera to the
+/// letterboxed render target and then through the game camera.
+/// Returns `None` when the cursor is outside the game view.
+pub fn cursor_to_world(
+    window: &Window,
+    window_camera: (&Camera, &GlobalTransform),
+    game_camera: (&Camera, &GlobalTransform),
+) -> Option<Vec2> {
+    let cursor = window.cursor_position()?;
+    let on_target = window_camera
+        .0
+        .viewport_to_world_2d(window_camera.1, cursor)?;
+    // The render target sprite is centered on the window camera, one texel per unit
+    let texel = Vec2::new(
+        on_target.x + WINDOW_WIDTH * 0.5,
+        WINDOW_HEIGHT * 0.5 - on_target.y,
+    );
+    if texel.x < 0.0 || texel.y < 0.0 || texel.x > WINDOW_WIDTH || texel.y > WINDOW_HEIGHT {
+        return None;
+    }
+    game_camera.0.viewport_to_world_2d(game_camera.1, texel)
+}
+
 fn init_shake_resource(mut commands: Commands) {
     commands.insert_resource(ShakeDirection(1.0));
 }
 
 fn shake_camera(
     mut shake_event: EventReader<ShakeCameraEvent>,
-    mut camera_querry: Query<(&mut GameCamera, &mut Transform)>,
+    mut camera_querry: Query<&mut GameCamera>,
     time: Res<Time>,
     mut shake_dir: ResMut<ShakeDirection>,
 ) {
-    let (mut camera_prop, mut pos) = camera_querry
+    let mut camera_prop = camera_querry
         .get_single_mut()
         .expect("Failed to get game camera");
     camera_prop.shake_queue.retain(|f| *f > 0.0);
@@ -139,16 +217,72 @@ fn shake_camera(
     for time in &mut camera_prop.shake_queue {
         *time -= tick;
     }
-    let mut target = Vec3::new(CAMERA_SHAKE_TO * shake_dir.0, 0.0, 0.0);
+    let mut target = CAMERA_SHAKE_TO * shake_dir.0;
     if camera_prop.shake_queue.is_empty() {
-        target = Vec3::ZERO;
+        target = 0.0;
     }
-    if (target.x - pos.translation.x).abs() < 0.25 {
-        pos.translation = target;
+    if (target - camera_prop.shake_offset).abs() < 0.25 {
+        camera_prop.shake_offset = target;
         shake_dir.0 *= -1.0;
-    } else if (target.x - pos.translation.x).is_sign_negative() {
-        pos.translation.x += CAMERA_SHAKE_SPEED * -1.0 * tick;
+    } else if (target - camera_prop.shake_offset).is_sign_negative() {
+        camera_prop.shake_offset -= CAMERA_SHAKE_SPEED * tick;
     } else {
-        pos.translation.x += CAMERA_SHAKE_SPEED * tick;
+        camera_prop.shake_offset += CAMERA_SHAKE_SPEED * tick;
     }
 }
+
+fn switch_camera_mode(keys: Res<Input<KeyCode>>, mut mode: ResMut<CameraMode>) {
+    if keys.just_pressed(KeyCode::C) {
+        *mode = match *mode {
+            CameraMode::Orbit => CameraMode::CarUp,
+            CameraMode::CarUp => CameraMode::Orbit,
+        };
+    }
+}
+
+fn rotate_camera(
+    keys: Res<Input<KeyCode>>,
+    mode: Res<CameraMode>,
+    mut camera_querry: Query<&mut GameCamera>,
+    focus_query: Query<&StackedSprite, With<CameraFocus>>,
+    time: Res<Time>,
+) {
+    let mut camera_prop = camera_querry
+        .get_single_mut()
+        .expect("Failed to get game camera");
+    let tick = time.delta_seconds();
+    match *mode {
+        CameraMode::Orbit => {
+            if keys.pressed(KeyCode::Q) {
+                camera_prop.yaw += CAMERA_ORBIT_SPEED * tick;
+            }
+            if keys.pressed(KeyCode::E) {
+                camera_prop.yaw -= CAMERA_ORBIT_SPEED * tick;
+            }
+        }
+        CameraMode::CarUp => {
+            if let Ok(focus) = focus_query.get_single() {
+                // The car sprites face the x axis, turn the view so that is "up"
+                let target = focus.yaw - FRAC_PI_2;
+                let difference = (target - camera_prop.yaw + PI).rem_euclid(TAU) - PI;
+                camera_prop.yaw += difference * f32::min(1.0, CAMERA_FOLLOW_SPEED * tick);
+            }
+        }
+    }
+    camera_prop.yaw = camera_prop.yaw.rem_euclid(TAU);
+}
+
+fn update_camera_transform(
+    mut camera_querry: Query<(&GameCamera, &mut Transform, &mut OrthographicProjection)>,
+    view: Res<CameraView>,
+) {
+    let (camera_prop, mut pos, mut projection) = camera_querry
+        .get_single_mut()
+        .expect("Failed to get game camera");
+    // The view spins around its center, the shake follows the view
+    pos.rotation = Quat::from_rotation_z(camera_prop.yaw);
+    let shake = pos.rotation * Vec3::new(camera_prop.shake_offset, 0.0, 0.0);
+    pos.translation.x = view.center.x + shake.x;
+    pos.translation.y = view.center.y + shake.y;
+    projection.scale = view.zoom;
+}
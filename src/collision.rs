@@ -0,0 +1,380 @@
+#![allow(clippy::needless_pass_by_value)]
+use bevy::prelude::*;
+
+use crate::net::RollbackApp;
+
+/// Outline used to test overlaps, centered on the entity position
+#[derive(Clone, Copy)]
+pub enum Shape {
+    /// Rectangle turned by the collider yaw
+    Box {
+        half_size: Vec2,
+    },
+    Circle {
+        radius: f32,
+    },
+}
+
+#[derive(Component, Clone)]
+pub struct Collider {
+    pub shape: Shape,
+    pub yaw: f32,
+}
+
+/// Colliders that never move, only the rest are pushed out of them
+#[derive(Component)]
+pub struct Static;
+
+/// How to push `a` out of `b`
+pub struct Contact {
+    /// Unit vector pointing from `b` towards `a`
+    pub normal: Vec2,
+    pub depth: f32,
+    /// World position where the two touch, in the middle of the overlap
+    pub point: Vec2,
+}
+
+/// A moving collider overlapping a static one, or two moving colliders
+/// overlapping each other, which are only reported once per pair
+#[derive(Event)]
+pub struct CollisionEvent {
+    pub entity: Entity,
+    pub other: Entity,
+    pub normal: Vec2,
+    pub depth: f32,
+    pub point: Vec2,
+}
+
+/// Movement systems go before this set and collision responses after it
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CollisionSet;
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CollisionEvent>()
+            .rollback_component::<Collider>()
+            .add_systems(Update, detect_collisions.in_set(CollisionSet));
+    }
+}
+
+fn detect_collisions(
+    moving_query: Query<(Entity, &Transform, &Collider), Without<Static>>,
+    static_query: Query<(Entity, &Transform, &Collider), With<Static>>,
+    mut collision_event: EventWriter<CollisionEvent>,
+) {
+    for (entity, transform, collider) in &moving_query {
+        for (other, other_transform, other_collider) in &static_query {
+            if let Some(contact) = collide(
+                collider,
+                transform.translation.truncate(),
+                other_collider,
+                other_transform.translation.truncate(),
+            ) {
+                collision_event.send(CollisionEvent {
+                    entity,
+                    other,
+                    normal: contact.normal,
+                    depth: contact.depth,
+                    point: contact.point,
+                });
+            }
+        }
+    }
+    for [(entity, transform, collider), (other, other_transform, other_collider)] in
+        moving_query.iter_combinations()
+    {
+        if let Some(contact) = collide(
+            collider,
+            transform.translation.truncate(),
+            other_collider,
+            other_transform.translation.truncate(),
+        ) {
+            collision_event.send(CollisionEvent {
+                entity,
+                other,
+                normal: contact.normal,
+                depth: contact.depth,
+                point: contact.point,
+            });
+        }
+    }
+}
+
+/// Separating axis test between two colliders
+pub fn collide(a: &Collider, a_pos: Vec2, b: &Collider, b_pos: Vec2) -> Option<Contact> {
+    match (a.shape, b.shape) {
+        (Shape::Circle { radius: a_radius }, Shape::Circle { radius: b_radius }) => {
+            let offset = a_pos - b_pos;
+            let depth = a_radius + b_radius - offset.length();
+            let normal = offset.try_normalize().unwrap_or(Vec2::X);
+            (depth > 0.0).then(|| Contact {
+                normal,
+                depth,
+                point: a_pos - normal * (a_radius - depth * 0.5),
+            })
+        }
+        (Shape::Circle { radius }, Shape::Box { half_size }) => {
+            circle_box(a_pos, radius, b_pos, half_size, b.yaw)
+        }
+        (Shape::Box { half_size }, Shape::Circle { radius }) => {
+            circle_box(b_pos, radius, a_pos, half_size, a.yaw).map(|contact| Contact {
+                normal: -contact.normal,
+                depth: contact.depth,
+                point: contact.point,
+            })
+        }
+        (Shape::Box { half_size: a_half }, Shape::Box { half_size: b_half }) => {
+            box_box(a_pos, a_half, a.yaw, b_pos, b_half, b.yaw)
+        }
+    }
+}
+
+fn circle_box(
+    circle_pos: Vec2,
+    radius: f32,
+    box_pos: Vec2,
+    half_size: Vec2,
+    yaw: f32,
+) -> Option<Contact> {
+    // Work in the box space, where it is axis aligned
+    let local = Vec2::from_angle(-yaw).rotate(circle_pos - box_pos);
+    let closest = local.clamp(-half_size, half_size);
+    let (normal, depth) = if closest == local {
+        // The center is inside the box, leave by the nearest side
+        let room = half_size - local.abs();
+        if room.x < room.y {
+            (Vec2::new(local.x.signum(), 0.0), room.x + radius)
+        } else {
+            (Vec2::new(0.0, local.y.signum()), room.y + radius)
+        }
+    } else {
+        let offset = local - closest;
+        let distance = offset.length();
+        if distance >= radius {
+            return None;
+        }
+        (offset / distance, radius - distance)
+    };
+    let normal = Vec2::from_angle(yaw).rotate(normal);
+    Some(Contact {
+        normal,
+        depth,
+        point: circle_pos - normal * (radius - depth * 0.5),
+    })
+}
+
+fn box_box(
+    a_pos: Vec2,
+    a_half: Vec2,
+    a_yaw: f32,
+    b_pos: Vec2,
+    b_half: Vec2,
+    b_yaw: f32,
+) -> Option<Contact> {
+    let a_axes = [Vec2::from_angle(a_yaw), Vec2::from_angle(a_yaw).perp()];
+    let b_axes = [Vec2::from_angle(b_yaw), Vec2::from_angle(b_yaw).perp()];
+    let offset = a_pos - b_pos;
+
+    let mut best: Option<Contact> = None;
+    for axis in a_axes.iter().chain(b_axes.iter()) {
+        // Half the length of each box projected on the axis
+        let a_reach = a_half.x * a_axes[0].dot(*axis).abs() + a_half.y * a_axes[1].dot(*axis).abs();
+        let b_reach = b_half.x * b_axes[0].dot(*axis).abs() + b_half.y * b_axes[1].dot(*axis).abs();
+        let distance = offset.dot(*axis);
+        let depth = a_reach + b_reach - distance.abs();
+        if depth <= 0.0 {
+            return None;
+        }
+        if best.as_ref().is_none_or(|contact| depth < contact.depth) {
+            let normal = if distance < 0.0 { -*axis } else { *axis };
+            best = Some(Contact {
+                normal,
+                depth,
+                point: Vec2::ZERO,
+            });
+        }
+    }
+    // Halfway between the corners of each box that reach furthest into the other
+    best.map(|contact| {
+        let a_corner = corners(a_pos, a_half, a_axes)
+            .into_iter()
+            .min_by(|x, y| x.dot(contact.normal).total_cmp(&y.dot(contact.normal)))
+            .unwrap_or(a_pos);
+        let b_corner = corners(b_pos, b_half, b_axes)
+            .into_iter()
+            .max_by(|x, y| x.dot(contact.normal).total_cmp(&y.dot(contact.normal)))
+            .unwrap_or(b_pos);
+        Contact {
+            point: (a_corner + b_corner) * 0.5,
+            ..contact
+        }
+    })
+}
+
+fn corners(position: Vec2, half: Vec2, axes: [Vec2; 2]) -> [Vec2; 4] {
+    let x = axes[0] * half.x;
+    let y = axes[1] * half.y;
+    [
+        position + x + y,
+        position + x - y,
+        position - x + y,
+        position - x - y,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_4;
+
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn circle(radius: f32) -> Collider {
+        Collider {
+            shape: Shape::Circle { radius },
+            yaw: 0.0,
+        }
+    }
+
+    fn square(half: f32, yaw: f32) -> Collider {
+        Collider {
+            shape: Shape::Box {
+                half_size: Vec2::splat(half),
+            },
+            yaw,
+        }
+    }
+
+    fn assert_near(actual: Vec2, expected: Vec2) {
+        assert!(
+            actual.abs_diff_eq(expected, EPSILON),
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn touching_is_not_overlapping() {
+        assert!(collide(&circle(1.0), Vec2::new(2.0, 0.0), &circle(1.0), Vec2::ZERO).is_none());
+        assert!(collide(
+            &circle(1.0),
+            Vec2::new(2.0, 0.0),
+            &square(1.0, 0.0),
+            Vec2::ZERO
+        )
+        .is_none());
+        assert!(collide(
+            &square(1.0, 0.0),
+            Vec2::new(0.0, 2.0),
+            &square(1.0, 0.0),
+            Vec2::ZERO
+        )
+        .is_none());
+        assert!(collide(&circle(1.0), Vec2::new(1.9, 0.0), &circle(1.0), Vec2::ZERO).is_some());
+    }
+
+    #[test]
+    fn circles_push_apart_along_centers() {
+        let contact = collide(&circle(1.0), Vec2::new(0.0, 1.5), &circle(1.0), Vec2::ZERO).unwrap();
+        assert_near(contact.normal, Vec2::Y);
+        assert!((contact.depth - 0.5).abs() < EPSILON);
+        assert_near(contact.point, Vec2::new(0.0, 0.75));
+    }
+
+    #[test]
+    fn normal_points_from_b_to_a() {
+        let contact = collide(
+            &circle(1.0),
+            Vec2::new(-1.5, 0.0),
+            &square(1.0, 0.0),
+            Vec2::ZERO,
+        )
+        .unwrap();
+        assert_near(contact.normal, Vec2::NEG_X);
+        assert!((contact.depth - 0.5).abs() < EPSILON);
+
+        // Swapping the colliders flips the normal
+        let contact = collide(
+            &square(1.0, 0.0),
+            Vec2::ZERO,
+            &circle(1.0),
+            Vec2::new(-1.5, 0.0),
+        )
+        .unwrap();
+        assert_near(contact.normal, Vec2::X);
+        assert!((contact.depth - 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn boxes_leave_by_the_shallowest_axis() {
+        let contact = collide(
+            &square(1.0, 0.0),
+            Vec2::new(1.8, 0.5),
+            &square(1.0, 0.0),
+            Vec2::ZERO,
+        )
+        .unwrap();
+        assert_near(contact.normal, Vec2::X);
+        assert!((contact.depth - 0.2).abs() < EPSILON);
+        assert!((contact.point.x - 0.9).abs() < EPSILON);
+    }
+
+    #[test]
+    fn rotated_boxes() {
+        // A diamond reaches sqrt(2) along x, so it only touches a box whose
+        // side is closer than that
+        let diamond = square(1.0, FRAC_PI_4);
+        let reach = 2.0_f32.sqrt();
+        assert!(collide(
+            &diamond,
+            Vec2::new(1.0 + reach + 0.01, 0.0),
+            &square(1.0, 0.0),
+            Vec2::ZERO
+        )
+        .is_none());
+        let contact = collide(
+            &diamond,
+            Vec2::new(1.0 + reach - 0.1, 0.0),
+            &square(1.0, 0.0),
+            Vec2::ZERO,
+        )
+        .unwrap();
+        assert_near(contact.normal, Vec2::X);
+        assert!((contact.depth - 0.1).abs() < EPSILON);
+
+        // A circle against a turned box is pushed along the turned side
+        let contact = collide(
+            &circle(0.5),
+            Vec2::new(0.9, 0.9),
+            &square(1.0, FRAC_PI_4),
+            Vec2::ZERO,
+        )
+        .unwrap();
+        assert_near(contact.normal, Vec2::new(1.0, 1.0).normalize());
+        assert!((contact.depth - (1.5 - 0.9 * reach)).abs() < EPSILON);
+    }
+
+    #[test]
+    fn circle_inside_box_leaves_by_nearest_side() {
+        let contact = collide(
+            &circle(0.5),
+            Vec2::new(0.2, 0.7),
+            &square(1.0, 0.0),
+            Vec2::ZERO,
+        )
+        .unwrap();
+        assert_near(contact.normal, Vec2::Y);
+        assert!((contact.depth - 0.8).abs() < EPSILON);
+
+        let contact = collide(
+            &circle(0.5),
+            Vec2::new(-0.9, 0.1),
+            &square(1.0, 0.0),
+            Vec2::ZERO,
+        )
+        .unwrap();
+        assert_near(contact.normal, Vec2::NEG_X);
+        assert!((contact.depth - 0.6).abs() < EPSILON);
+    }
+}
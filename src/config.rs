@@ -1,5 +1,5 @@
 #![allow(clippy::cast_precision_loss, dead_code)]
-use bevy::prelude::Color;
+use bevy::prelude::{Color, Vec2};
 
 pub const P8_BLACK: Color = Color::Rgba {
     red: 0x00 as f32 / 255.0,
@@ -98,6 +98,25 @@ pub const P8_CREAM: Color = Color::Rgba {
     alpha: 1.0,
 };
 
+pub const P8_PALETTE: [Color; 16] = [
+    P8_BLACK,
+    P8_DARK_BLUE,
+    P8_WINE,
+    P8_DARK_GREEN,
+    P8_BROWN,
+    P8_DARK_GREY,
+    P8_LIGHT_GREY,
+    P8_WHITE,
+    P8_RED,
+    P8_ORANGE,
+    P8_YELLOW,
+    P8_LIGHT_GREEN,
+    P8_LIGHT_BLUE,
+    P8_GREY,
+    P8_PINK,
+    P8_CREAM,
+];
+
 // Screen config
 pub const WINDOW_WIDTH: f32 = 800.0;
 pub const WINDOW_HEIGHT: f32 = 600.0;
@@ -109,3 +128,6 @@ pub const WINDOW_CAMERA_CLEAR_COLOR: Color = P8_BLACK;
 pub const GAME_CAMERA_NAME: &str = "game camera";
 pub const GAME_CAMERA_TARGET_NAME: &str = "game camera target";
 pub const GAME_CAMERA_CLEAR_COLOR: Color = P8_CREAM;
+
+// Shadows are cast away from the light, towards this direction
+pub const SHADOW_LIGHT_DIRECTION: Vec2 = Vec2::new(1.0, -1.0);
@@ -0,0 +1,122 @@
+#![allow(clippy::needless_pass_by_value)]
+use bevy::{
+    prelude::*,
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+};
+use interpolation::Ease;
+
+use crate::{
+    config::WINDOW_HEIGHT, input::InputSet, net::RollbackApp, stacked_sprite::StackedSprite,
+    GameState, TILE_SIZE,
+};
+
+/// Makes a `StackedSprite` fall from the top of the screen and bounce on the
+/// ground, with a marker pulsing on the landing spot until it gets there
+#[derive(Component, Clone)]
+pub struct DropIn {
+    pub time: Timer,
+    pub marker: Handle<ColorMaterial>,
+}
+
+impl DropIn {
+    pub fn new(duration: f32, marker: Handle<ColorMaterial>) -> Self {
+        Self {
+            time: Timer::from_seconds(duration, TimerMode::Once),
+            marker,
+        }
+    }
+}
+
+/// Landing spot of a falling object
+#[derive(Component)]
+struct DropMarker;
+
+/// Sent once a dropped entity touches the ground, `DropIn` is removed by then
+#[derive(Event)]
+pub struct LandedEvent {
+    pub entity: Entity,
+}
+
+#[derive(Resource)]
+struct DropAssets {
+    marker_mesh: Mesh2dHandle,
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.add_event::<LandedEvent>()
+            .rollback_component::<DropIn>()
+            .add_systems(Startup, load_drop)
+            .add_systems(
+                Update,
+                (spawn_markers, update_drops, update_markers)
+                    .chain()
+                    .before(InputSet)
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+fn load_drop(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    commands.insert_resource(DropAssets {
+        marker_mesh: meshes
+            .add(shape::Circle::new(TILE_SIZE * 0.5).into())
+            .into(),
+    });
+}
+
+fn spawn_markers(
+    mut commands: Commands,
+    query: Query<(Entity, &DropIn), Added<DropIn>>,
+    drop_assets: Res<DropAssets>,
+) {
+    for (entity, drop) in &query {
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn((
+                MaterialMesh2dBundle {
+                    mesh: drop_assets.marker_mesh.clone(),
+                    material: drop.marker.clone(),
+                    transform: Transform::from_xyz(0.0, 0.0, -0.002),
+                    ..default()
+                },
+                DropMarker,
+            ));
+        });
+    }
+}
+
+pub fn update_drops(
+    mut query: Query<(&mut DropIn, &mut StackedSprite, Entity)>,
+    mut commands: Commands,
+    mut landed_event: EventWriter<LandedEvent>,
+    time: Res<Time>,
+) {
+    for (mut drop, mut stack, entity) in &mut query {
+        drop.time.tick(time.delta());
+        let percent = drop.time.percent().bounce_out();
+        stack.elevation = f32::max(0.0, WINDOW_HEIGHT - (WINDOW_HEIGHT * percent));
+        if drop.time.finished() {
+            commands.entity(entity).remove::<DropIn>();
+            landed_event.send(LandedEvent { entity });
+        }
+    }
+}
+
+fn update_markers(
+    mut commands: Commands,
+    mut marker_query: Query<(&mut Transform, &Parent, Entity), With<DropMarker>>,
+    drop_query: Query<&DropIn>,
+) {
+    for (mut transform, parent, entity) in &mut marker_query {
+        match drop_query.get(parent.get()) {
+            Ok(drop) if !drop.time.finished() => {
+                // Pulse faster the closer the object is to the ground
+                let percent = drop.time.percent();
+                let pulse = (percent * percent * 60.0).sin() * 0.1;
+                transform.scale = Vec3::new(0.5 + pulse, 0.5 + pulse, 1.0);
+            }
+            _ => commands.entity(entity).despawn_recursive(),
+        }
+    }
+}
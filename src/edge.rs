@@ -0,0 +1,251 @@
+#![allow(clippy::needless_pass_by_value, clippy::type_complexity)]
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collision::{Collider, Shape, Static},
+    config::{WINDOW_HEIGHT, WINDOW_WIDTH},
+    health::KnockOutEvent,
+    level::LevelRules,
+    net::RollbackApp,
+    obstacle::{Obstacle, ObstacleKind},
+    player::{bump_cars, start_position, Movement, PlayerCount, PlayerId},
+    stacked_sprite::{spawn_slices, StackedSprite},
+    GameState, TILE_SIZE,
+};
+
+const ARENA_SIZE: Vec2 = Vec2::new(WINDOW_WIDTH, WINDOW_HEIGHT);
+/// Thickness of the walls around walled arenas, thick enough that the car
+/// can not tunnel through them in one frame
+const EDGE_WALL_THICKNESS: f32 = TILE_SIZE * 2.0;
+
+/// What happens to things that leave the arena through a screen edge
+#[derive(Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EdgeMode {
+    /// Come back from the opposite edge
+    #[default]
+    Wrap,
+    /// Bounce off solid walls along the edges
+    Walls,
+    /// Cars that drive out lose a life
+    Deadly,
+}
+
+impl EdgeMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            EdgeMode::Wrap => "Wrap",
+            EdgeMode::Walls => "Walls",
+            EdgeMode::Deadly => "Deadly",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            EdgeMode::Wrap => EdgeMode::Walls,
+            EdgeMode::Walls => EdgeMode::Deadly,
+            EdgeMode::Deadly => EdgeMode::Wrap,
+        }
+    }
+}
+
+/// Moves to the opposite edge when it leaves the arena of a wrapping level.
+///
+/// Stacked sprites are also drawn on the other side while they cross.
+#[derive(Component, Default)]
+pub struct Wrapping {
+    /// Copies drawn across the horizontal, vertical and both edges
+    ghosts: [Option<Entity>; 3],
+}
+
+/// Copy of a wrapping stacked sprite, drawn on the far side of the arena
+#[derive(Component)]
+struct WrapGhost {
+    owner: Entity,
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        // Ghosts are removed after their slices are added, not before
+        app.rollback_marker::<Wrapping>()
+            .add_systems(
+                Update,
+                (wrap_positions, update_ghosts, knock_out_cars)
+                    .chain()
+                    .after(bump_cars)
+                    .after(spawn_slices)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(Update, remove_orphan_ghosts.after(spawn_slices));
+    }
+}
+
+/// Solid walls just outside the screen, built with the level
+pub fn spawn_edge_walls(commands: &mut Commands) {
+    let half = ARENA_SIZE * 0.5;
+    let thickness = EDGE_WALL_THICKNESS * 0.5;
+    // Long enough to close the corners
+    let along_x = Vec2::new(half.x + EDGE_WALL_THICKNESS, thickness);
+    let along_y = Vec2::new(thickness, half.y + EDGE_WALL_THICKNESS);
+    let walls = [
+        (Vec2::new(0.0, half.y + thickness), along_x),
+        (Vec2::new(0.0, -half.y - thickness), along_x),
+        (Vec2::new(half.x + thickness, 0.0), along_y),
+        (Vec2::new(-half.x - thickness, 0.0), along_y),
+    ];
+    for (position, half_size) in walls {
+        commands.spawn((
+            SpatialBundle::from_transform(Transform::from_translation(position.extend(0.0))),
+            Collider {
+                shape: Shape::Box { half_size },
+                yaw: 0.0,
+            },
+            Static,
+            Obstacle {
+                kind: ObstacleKind::Edge,
+            },
+        ));
+    }
+}
+
+fn wrap_positions(rules: Res<LevelRules>, mut query: Query<&mut Transform, With<Wrapping>>) {
+    if rules.edges != EdgeMode::Wrap {
+        return;
+    }
+    let half = ARENA_SIZE * 0.5;
+    for mut transform in &mut query {
+        let position = transform.translation.truncate();
+        let wrapped = (position + half).rem_euclid(ARENA_SIZE) - half;
+        // Avoid touching the transform of everything that did not cross
+        if wrapped != position {
+            transform.translation.x = wrapped.x;
+            transform.translation.y = wrapped.y;
+        }
+    }
+}
+
+/// Offset to the copy across each edge the sprite is overlapping
+fn ghost_offsets(position: Vec2, reach: f32) -> [Option<Vec2>; 3] {
+    let half = ARENA_SIZE * 0.5 - Vec2::splat(reach);
+    let across = |value: f32, limit: f32, size: f32| {
+        if value > limit {
+            Some(-size)
+        } else if value < -limit {
+            Some(size)
+        } else {
+            None
+        }
+    };
+    let x = across(position.x, half.x, ARENA_SIZE.x);
+    let y = across(position.y, half.y, ARENA_SIZE.y);
+    [
+        x.map(|x| Vec2::new(x, 0.0)),
+        y.map(|y| Vec2::new(0.0, y)),
+        x.zip(y).map(|(x, y)| Vec2::new(x, y)),
+    ]
+}
+
+fn update_ghosts(
+    mut commands: Commands,
+    rules: Res<LevelRules>,
+    mut owner_query: Query<
+        (
+            Entity,
+            &Transform,
+            &StackedSprite,
+            &Visibility,
+            &mut Wrapping,
+        ),
+        Without<WrapGhost>,
+    >,
+    mut ghost_query: Query<(&mut Transform, &mut StackedSprite, &mut Visibility), With<WrapGhost>>,
+) {
+    for (owner, transform, stack, visibility, mut wrapping) in &mut owner_query {
+        let reach = stack
+            .custom_size
+            .unwrap_or(Vec2::splat(TILE_SIZE))
+            .max_element()
+            * stack.scale
+            * 0.5;
+        let offsets = if rules.edges == EdgeMode::Wrap {
+            ghost_offsets(transform.translation.truncate(), reach)
+        } else {
+            [None; 3]
+        };
+        for (slot, offset) in wrapping.ghosts.iter_mut().zip(offsets) {
+            match (*slot, offset) {
+                (Some(ghost), Some(offset)) => {
+                    let Ok((mut ghost_transform, mut ghost_stack, mut ghost_visibility)) =
+                        ghost_query.get_mut(ghost)
+                    else {
+                        continue;
+                    };
+                    ghost_transform.translation.x = transform.translation.x + offset.x;
+                    ghost_transform.translation.y = transform.translation.y + offset.y;
+                    ghost_stack.yaw = stack.yaw;
+                    ghost_stack.elevation = stack.elevation;
+                    ghost_stack.scale = stack.scale;
+                    ghost_stack.color = stack.color;
+                    *ghost_visibility = *visibility;
+                }
+                (None, Some(offset)) => {
+                    let position = transform.translation.truncate() + offset;
+                    *slot = Some(
+                        commands
+                            .spawn((
+                                SpatialBundle::from_transform(Transform::from_translation(
+                                    position.extend(0.0),
+                                )),
+                                stack.clone(),
+                                WrapGhost { owner },
+                            ))
+                            .id(),
+                    );
+                }
+                (Some(ghost), None) => {
+                    commands.entity(ghost).despawn_recursive();
+                    *slot = None;
+                }
+                (None, None) => (),
+            }
+        }
+    }
+}
+
+fn remove_orphan_ghosts(
+    mut commands: Commands,
+    ghost_query: Query<(Entity, &WrapGhost)>,
+    owner_query: Query<(), With<Wrapping>>,
+) {
+    for (entity, ghost) in &ghost_query {
+        if owner_query.get(ghost.owner).is_err() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+pub fn knock_out_cars(
+    rules: Res<LevelRules>,
+    count: Res<PlayerCount>,
+    mut query: Query<(Entity, &mut Transform, &mut Movement, Option<&PlayerId>)>,
+    mut knock_out_event: EventWriter<KnockOutEvent>,
+) {
+    if rules.edges != EdgeMode::Deadly {
+        return;
+    }
+    let half = ARENA_SIZE * 0.5;
+    for (entity, mut transform, mut movement, id) in &mut query {
+        let position = transform.translation.truncate();
+        if position.abs().cmple(half).all() {
+            continue;
+        }
+        knock_out_event.send(KnockOutEvent { target: entity });
+        // Computer driven cars have no place on the start line, any start will do
+        let start = id.map_or(rules.player_start, |id| {
+            start_position(&rules, id.0, count.0)
+        });
+        transform.translation = start.extend(0.0);
+        movement.stop(rules.player_angle);
+    }
+}
@@ -0,0 +1,389 @@
+#![allow(clippy::needless_pass_by_value, clippy::too_many_arguments)]
+use std::f32::consts::FRAC_PI_4;
+
+use bevy::{input::mouse::MouseWheel, prelude::*, window::PrimaryWindow};
+
+use crate::{
+    camera::{cursor_to_world, GameCamera, WindowCamera},
+    config::{P8_LIGHT_BLUE, P8_ORANGE, P8_YELLOW},
+    level::{CurrentLevel, Level, SpawnZone, LEVELS},
+    net,
+    obstacle::ObstacleSpec,
+    race::Gate,
+    ui::NewEditorStatus,
+    GameState, TILE_SIZE,
+};
+
+/// Placed objects snap to this grid
+const SNAP: f32 = TILE_SIZE * 0.5;
+/// Walls, zones and gates shorter than this are dropped
+const MIN_DRAG: f32 = TILE_SIZE * 0.5;
+/// Right click removes obstacles this close to the cursor
+const ERASE_RADIUS: f32 = TILE_SIZE;
+const ROTATE_STEP: f32 = FRAC_PI_4 * 0.5;
+/// Ground tiles the paint tool cycles through, see `level::Level`
+const TILES: [char; 6] = ['#', '-', '=', 'g', 'd', '.'];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    Paint,
+    Cone,
+    Crate,
+    Wall,
+    SpawnZone,
+    PlayerStart,
+    /// Race checkpoints, placed in driving order and dragged from the left
+    /// of the road to the right
+    Gate,
+}
+
+impl Tool {
+    fn name(self) -> &'static str {
+        match self {
+            Tool::Paint => "Paint",
+            Tool::Cone => "Cone",
+            Tool::Crate => "Crate",
+            Tool::Wall => "Wall",
+            Tool::SpawnZone => "Spawn zone",
+            Tool::PlayerStart => "Player start",
+            Tool::Gate => "Gate",
+        }
+    }
+}
+
+#[derive(Resource)]
+struct Editor {
+    tool: Tool,
+    tile: usize,
+    /// Yaw given to the next crate
+    yaw: f32,
+    /// Where the wall or zone being dragged started
+    drag_start: Option<Vec2>,
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Editor {
+            tool: Tool::Paint,
+            tile: 0,
+            yaw: 0.0,
+            drag_start: None,
+        })
+        .add_systems(
+            Update,
+            toggle_editor
+                .run_if(in_state(GameState::Playing).or_else(in_state(GameState::Editor)))
+                .run_if(net::offline),
+        )
+        .add_systems(OnEnter(GameState::Editor), start_editing)
+        .add_systems(OnExit(GameState::Editor), stop_editing)
+        .add_systems(
+            Update,
+            (select_tool, edit_level, draw_editor, save_level)
+                .chain()
+                .run_if(in_state(GameState::Editor)),
+        );
+    }
+}
+
+fn toggle_editor(
+    keys: Res<Input<KeyCode>>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if keys.just_pressed(KeyCode::F1) {
+        next_state.set(match state.get() {
+            GameState::Editor => GameState::Loading,
+            _ => GameState::Editor,
+        });
+    }
+}
+
+fn start_editing(
+    mut editor: ResMut<Editor>,
+    current: Res<CurrentLevel>,
+    mut levels: ResMut<Assets<Level>>,
+) {
+    // Show the status again
+    editor.set_changed();
+    // Give the paint tool the whole screen to work with
+    if let Some(level) = levels.get_mut(&current.handle) {
+        level.fill_ground();
+    }
+}
+
+fn stop_editing(mut editor: ResMut<Editor>, mut new_status: EventWriter<NewEditorStatus>) {
+    editor.drag_start = None;
+    new_status.send(NewEditorStatus(None));
+}
+
+fn select_tool(
+    keys: Res<Input<KeyCode>>,
+    mut wheel_event: EventReader<MouseWheel>,
+    mut editor: ResMut<Editor>,
+    current: Res<CurrentLevel>,
+    mut levels: ResMut<Assets<Level>>,
+    mut new_status: EventWriter<NewEditorStatus>,
+) {
+    let tools = [
+        (KeyCode::Key1, Tool::Paint),
+        (KeyCode::Key2, Tool::Cone),
+        (KeyCode::Key3, Tool::Crate),
+        (KeyCode::Key4, Tool::Wall),
+        (KeyCode::Key5, Tool::SpawnZone),
+        (KeyCode::Key6, Tool::PlayerStart),
+        (KeyCode::Key7, Tool::Gate),
+    ];
+    for (key, tool) in tools {
+        if keys.just_pressed(key) {
+            editor.tool = tool;
+            editor.drag_start = None;
+        }
+    }
+
+    let scroll: f32 = wheel_event.read().map(|wheel| wheel.y.signum()).sum();
+    let steps = scroll as i32 + i32::from(keys.just_pressed(KeyCode::Tab));
+    if steps != 0 {
+        match editor.tool {
+            Tool::Paint => {
+                editor.tile = (editor.tile as i32 + steps).rem_euclid(TILES.len() as i32) as usize;
+            }
+            Tool::Crate => editor.yaw += ROTATE_STEP * steps as f32,
+            Tool::PlayerStart => {
+                if let Some(level) = levels.get_mut(&current.handle) {
+                    level.player_angle += FRAC_PI_4 * steps as f32;
+                }
+            }
+            _ => (),
+        }
+    }
+    if keys.just_pressed(KeyCode::M) {
+        if let Some(level) = levels.get_mut(&current.handle) {
+            level.edges = level.edges.next();
+            editor.set_changed();
+        }
+    }
+    if !editor.is_changed() {
+        return;
+    }
+
+    let detail = match editor.tool {
+        Tool::Paint => format!(" '{}'", TILES[editor.tile]),
+        Tool::Crate => format!(" {:.0}deg", editor.yaw.to_degrees()),
+        Tool::Gate => format!(
+            " {}",
+            levels
+                .get(&current.handle)
+                .map_or(0, |level| level.checkpoints.len())
+                + 1
+        ),
+        _ => String::new(),
+    };
+    let edges = levels
+        .get(&current.handle)
+        .map_or("", |level| level.edges.name());
+    new_status.send(NewEditorStatus(Some(format!(
+        "EDITOR  {}{detail}  Edges: {edges}\n1-7 tool  wheel/Tab option  M edges  S save  F1 play",
+        editor.tool.name()
+    ))));
+}
+
+fn edit_level(
+    mouse: Res<Input<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    window_camera_query: Query<(&Camera, &GlobalTransform), With<WindowCamera>>,
+    game_camera_query: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+    mut editor: ResMut<Editor>,
+    current: Res<CurrentLevel>,
+    mut levels: ResMut<Assets<Level>>,
+) {
+    let (Ok(window), Ok(window_camera), Ok(game_camera)) = (
+        window_query.get_single(),
+        window_camera_query.get_single(),
+        game_camera_query.get_single(),
+    ) else {
+        return;
+    };
+    let Some(cursor) = cursor_to_world(window, window_camera, game_camera) else {
+        return;
+    };
+    let snapped = (cursor / SNAP).round() * SNAP;
+    // Only borrow the level mutably on a real edit, that is what triggers a rebuild
+    let Some(level) = levels.get(&current.handle) else {
+        return;
+    };
+
+    match editor.tool {
+        Tool::Paint => {
+            let tile = if mouse.pressed(MouseButton::Left) {
+                TILES[editor.tile]
+            } else if mouse.pressed(MouseButton::Right) {
+                '.'
+            } else {
+                return;
+            };
+            if let Some((column, row)) = level.tile_at(cursor) {
+                if level.ground[row].chars().nth(column) != Some(tile) {
+                    if let Some(level) = levels.get_mut(&current.handle) {
+                        level.set_tile(column, row, tile);
+                    }
+                }
+            }
+        }
+        Tool::Cone | Tool::Crate | Tool::PlayerStart if mouse.just_pressed(MouseButton::Left) => {
+            let Some(level) = levels.get_mut(&current.handle) else {
+                return;
+            };
+            match editor.tool {
+                Tool::Cone => level.obstacles.push(ObstacleSpec::Cone(snapped)),
+                Tool::Crate => level.obstacles.push(ObstacleSpec::Crate {
+                    position: snapped,
+                    yaw: editor.yaw,
+                }),
+                _ => level.player_start = snapped,
+            }
+        }
+        Tool::Wall | Tool::SpawnZone | Tool::Gate if mouse.just_pressed(MouseButton::Left) => {
+            editor.drag_start = Some(snapped);
+        }
+        Tool::Wall | Tool::SpawnZone | Tool::Gate if mouse.just_released(MouseButton::Left) => {
+            let Some(start) = editor.drag_start.take() else {
+                return;
+            };
+            if start.distance(snapped) < MIN_DRAG {
+                return;
+            }
+            let Some(level) = levels.get_mut(&current.handle) else {
+                return;
+            };
+            match editor.tool {
+                Tool::Wall => level.obstacles.push(ObstacleSpec::Wall {
+                    from: start,
+                    to: snapped,
+                }),
+                Tool::Gate => level.checkpoints.push(Gate {
+                    from: start,
+                    to: snapped,
+                }),
+                _ => level.spawn_zones.push(SpawnZone {
+                    from: start,
+                    to: snapped,
+                }),
+            }
+        }
+        Tool::Gate if mouse.just_pressed(MouseButton::Right) => {
+            let nearest = level
+                .checkpoints
+                .iter()
+                .enumerate()
+                .map(|(index, gate)| (index, gate.distance_to(cursor)))
+                .filter(|(_, distance)| *distance < ERASE_RADIUS)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let (Some((index, _)), Some(level)) = (nearest, levels.get_mut(&current.handle)) {
+                level.checkpoints.remove(index);
+            }
+        }
+        Tool::SpawnZone if mouse.just_pressed(MouseButton::Right) => {
+            let hit = level
+                .spawn_zones
+                .iter()
+                .rposition(|zone| Rect::from_corners(zone.from, zone.to).contains(cursor));
+            if let (Some(index), Some(level)) = (hit, levels.get_mut(&current.handle)) {
+                level.spawn_zones.remove(index);
+            }
+        }
+        Tool::Cone | Tool::Crate | Tool::Wall if mouse.just_pressed(MouseButton::Right) => {
+            let nearest = level
+                .obstacles
+                .iter()
+                .enumerate()
+                .map(|(index, spec)| (index, spec.distance_to(cursor)))
+                .filter(|(_, distance)| *distance < ERASE_RADIUS)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            if let (Some((index, _)), Some(level)) = (nearest, levels.get_mut(&current.handle)) {
+                level.obstacles.remove(index);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn draw_editor(
+    mut gizmos: Gizmos,
+    editor: Res<Editor>,
+    current: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    window_camera_query: Query<(&Camera, &GlobalTransform), With<WindowCamera>>,
+    game_camera_query: Query<(&Camera, &GlobalTransform), With<GameCamera>>,
+) {
+    let Some(level) = levels.get(&current.handle) else {
+        return;
+    };
+    for zone in &level.spawn_zones {
+        let rect = Rect::from_corners(zone.from, zone.to);
+        gizmos.rect_2d(rect.center(), 0.0, rect.size(), P8_LIGHT_BLUE);
+    }
+    let heading = Vec2::from_angle(level.player_angle) * TILE_SIZE;
+    gizmos.circle_2d(level.player_start, TILE_SIZE * 0.5, P8_YELLOW);
+    gizmos.line_2d(level.player_start, level.player_start + heading, P8_YELLOW);
+
+    let (Ok(window), Ok(window_camera), Ok(game_camera)) = (
+        window_query.get_single(),
+        window_camera_query.get_single(),
+        game_camera_query.get_single(),
+    ) else {
+        return;
+    };
+    let Some(cursor) = cursor_to_world(window, window_camera, game_camera) else {
+        return;
+    };
+    let snapped = (cursor / SNAP).round() * SNAP;
+    match (editor.tool, editor.drag_start) {
+        (Tool::Paint, _) => {
+            if let Some((column, row)) = level.tile_at(cursor) {
+                let center = level.ground_origin()
+                    + Vec2::new(column as f32 + 0.5, -(row as f32 + 0.5)) * TILE_SIZE;
+                gizmos.rect_2d(center, 0.0, Vec2::splat(TILE_SIZE), P8_ORANGE);
+            }
+        }
+        (Tool::Wall | Tool::Gate, Some(start)) => gizmos.line_2d(start, snapped, P8_ORANGE),
+        (Tool::SpawnZone, Some(start)) => {
+            let rect = Rect::from_corners(start, snapped);
+            gizmos.rect_2d(rect.center(), 0.0, rect.size(), P8_ORANGE);
+        }
+        (Tool::Crate, _) => {
+            gizmos.rect_2d(snapped, editor.yaw, Vec2::splat(TILE_SIZE), P8_ORANGE);
+        }
+        _ => {
+            gizmos.circle_2d(snapped, TILE_SIZE * 0.25, P8_ORANGE);
+        }
+    }
+}
+
+fn save_level(keys: Res<Input<KeyCode>>, current: Res<CurrentLevel>, levels: Res<Assets<Level>>) {
+    if !keys.just_pressed(KeyCode::S) {
+        return;
+    }
+    let Some(level) = levels.get(&current.handle) else {
+        return;
+    };
+    let text = match ron::ser::to_string_pretty(level, ron::ser::PrettyConfig::default()) {
+        Ok(text) => text,
+        Err(error) => {
+            error!("could not write level: {error}");
+            return;
+        }
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = format!("assets/{}", LEVELS[current.index]);
+        match std::fs::write(&path, text) {
+            Ok(()) => info!("level saved to {path}"),
+            Err(error) => error!("could not save level to {path}: {error}"),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    warn!("levels can not be saved on the web:\n{text}");
+}
@@ -0,0 +1,232 @@
+#![allow(clippy::needless_pass_by_value, clippy::cast_possible_truncation)]
+use std::hash::Hash;
+
+use bevy::{ecs::query::ReadOnlyWorldQuery, prelude::*};
+
+use crate::{
+    barrel::{BarrelExplodedEvent, BarrelSet},
+    edge::knock_out_cars,
+    net::{RollbackApp, StateHash},
+    player::PlayerId,
+    replay::Fnv,
+    ui::NewHealth,
+    GameState, TILE_SIZE,
+};
+
+const MAX_HP: u32 = 3;
+const START_LIVES: u32 = 3;
+const INVULNERABLE_DURATION: f32 = 2.0;
+/// Times per second the car blinks while it can not be hurt
+const FLASH_RATE: f32 = 12.0;
+/// Distance from the car center at which a blast still hurts
+const HIT_RADIUS: f32 = TILE_SIZE * 0.5;
+
+#[derive(Component, Clone)]
+pub struct Health {
+    hp: u32,
+    lives: u32,
+    invulnerable: Timer,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        // Start invulnerable so nothing can hit the car while it spawns
+        Self {
+            hp: MAX_HP,
+            lives: START_LIVES,
+            invulnerable: Timer::from_seconds(INVULNERABLE_DURATION, TimerMode::Once),
+        }
+    }
+}
+
+impl StateHash for Health {
+    fn state_hash(&self, hasher: &mut Fnv) {
+        (self.hp, self.lives).hash(hasher);
+        self.invulnerable.elapsed().hash(hasher);
+    }
+}
+
+/// Blocks every hit while the entity holds it
+#[derive(Component, Default)]
+pub struct Shield;
+
+#[derive(Event)]
+pub struct DamageEvent {
+    pub target: Entity,
+    pub amount: u32,
+}
+
+/// Takes a whole life, shields and invulnerability do not help
+#[derive(Event)]
+pub struct KnockOutEvent {
+    pub target: Entity,
+}
+
+/// Sent when damage actually got through
+#[derive(Event)]
+pub struct HurtEvent {
+    pub target: Entity,
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageEvent>()
+            .add_event::<HurtEvent>()
+            .add_event::<KnockOutEvent>()
+            .rollback_checked_component::<Health>()
+            .rollback_marker::<Shield>()
+            .add_systems(
+                Update,
+                (blast_damage, take_damage, knock_out, flash_invulnerable)
+                    .chain()
+                    .after(BarrelSet)
+                    .after(knock_out_cars)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            // Cars show up after startup, whenever the number of players changes
+            .add_systems(Update, send_health::<Added<Health>>)
+            .add_systems(
+                OnExit(GameState::GameOver),
+                (reset_health, send_health::<()>).chain(),
+            );
+    }
+}
+
+fn blast_damage(
+    query: Query<(Entity, &Transform), With<Health>>,
+    mut exploded_event: EventReader<BarrelExplodedEvent>,
+    mut damage_event: EventWriter<DamageEvent>,
+) {
+    for explosion in exploded_event.read() {
+        for (entity, transform) in &query {
+            let distance = transform
+                .translation
+                .truncate()
+                .distance(explosion.position.truncate());
+            if distance < explosion.radius + HIT_RADIUS {
+                damage_event.send(DamageEvent {
+                    target: entity,
+                    amount: 1,
+                });
+            }
+        }
+    }
+}
+
+fn take_damage(
+    mut query: Query<(&mut Health, Option<&PlayerId>, Has<Shield>)>,
+    mut damage_event: EventReader<DamageEvent>,
+    mut hurt_event: EventWriter<HurtEvent>,
+    mut new_health: EventWriter<NewHealth>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for damage in damage_event.read() {
+        let Ok((mut health, id, shielded)) = query.get_mut(damage.target) else {
+            continue;
+        };
+        if shielded || !health.invulnerable.finished() || health.lives == 0 {
+            continue;
+        }
+        health.hp = health.hp.saturating_sub(damage.amount);
+        if health.hp == 0 {
+            lose_life(&mut health, id, &mut next_state);
+        }
+        hurt(
+            &mut health,
+            id,
+            damage.target,
+            &mut hurt_event,
+            &mut new_health,
+        );
+    }
+}
+
+pub fn knock_out(
+    mut query: Query<(&mut Health, Option<&PlayerId>)>,
+    mut knock_out_event: EventReader<KnockOutEvent>,
+    mut hurt_event: EventWriter<HurtEvent>,
+    mut new_health: EventWriter<NewHealth>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for knock_out in knock_out_event.read() {
+        let Ok((mut health, id)) = query.get_mut(knock_out.target) else {
+            continue;
+        };
+        if health.lives == 0 {
+            continue;
+        }
+        lose_life(&mut health, id, &mut next_state);
+        hurt(
+            &mut health,
+            id,
+            knock_out.target,
+            &mut hurt_event,
+            &mut new_health,
+        );
+    }
+}
+
+/// The run is over as soon as any player is out of lives, computer driven
+/// cars start over instead
+fn lose_life(health: &mut Health, id: Option<&PlayerId>, next_state: &mut NextState<GameState>) {
+    health.lives -= 1;
+    if health.lives > 0 {
+        health.hp = MAX_HP;
+    } else if id.is_some() {
+        next_state.set(GameState::GameOver);
+    } else {
+        *health = Health::default();
+    }
+}
+
+fn hurt(
+    health: &mut Health,
+    id: Option<&PlayerId>,
+    target: Entity,
+    hurt_event: &mut EventWriter<HurtEvent>,
+    new_health: &mut EventWriter<NewHealth>,
+) {
+    health.invulnerable = Timer::from_seconds(INVULNERABLE_DURATION, TimerMode::Once);
+    hurt_event.send(HurtEvent { target });
+    if let Some(id) = id {
+        new_health.send(NewHealth {
+            player: id.0,
+            hp: health.hp,
+            max_hp: MAX_HP,
+            lives: health.lives,
+        });
+    }
+}
+
+fn flash_invulnerable(mut query: Query<(&mut Health, &mut Visibility)>, time: Res<Time>) {
+    for (mut health, mut visibility) in &mut query {
+        health.invulnerable.tick(time.delta());
+        let blink = (health.invulnerable.elapsed_secs() * FLASH_RATE) as u32 % 2 == 1;
+        *visibility = if !health.invulnerable.finished() && blink {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+    }
+}
+
+fn reset_health(mut query: Query<&mut Health>) {
+    for mut health in &mut query {
+        *health = Health::default();
+    }
+}
+
+fn send_health<F: ReadOnlyWorldQuery>(
+    query: Query<(&Health, &PlayerId), F>,
+    mut new_health: EventWriter<NewHealth>,
+) {
+    for (health, id) in &query {
+        new_health.send(NewHealth {
+            player: id.0,
+            hp: health.hp,
+            max_hp: MAX_HP,
+            lives: health.lives,
+        });
+    }
+}
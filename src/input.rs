@@ -0,0 +1,115 @@
+#![allow(clippy::needless_pass_by_value)]
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{ai::AiDriver, net::RollbackApp};
+
+/// Stick tilt below this is ignored
+const STICK_DEAD_ZONE: f32 = 0.2;
+/// Sharpest turn either way, `CarInput::steer` stays within it
+pub const MAX_STEER: f32 = 1.0;
+
+/// What the driver of a car wants this frame, the car systems only read this
+#[derive(Component, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct CarInput {
+    /// Positive turns left, negative turns right
+    pub steer: f32,
+    pub drift: bool,
+    pub boost: bool,
+}
+
+/// Keyboard half used by one player
+#[derive(Clone, Copy)]
+pub struct KeyBindings {
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub drift: KeyCode,
+    pub boost: KeyCode,
+}
+
+impl KeyBindings {
+    /// Left hand for the first player, arrows for the second one
+    pub const PLAYERS: [KeyBindings; 2] = [
+        KeyBindings {
+            left: KeyCode::A,
+            right: KeyCode::D,
+            drift: KeyCode::Space,
+            boost: KeyCode::ShiftLeft,
+        },
+        KeyBindings {
+            left: KeyCode::Left,
+            right: KeyCode::Right,
+            drift: KeyCode::Down,
+            boost: KeyCode::Up,
+        },
+    ];
+}
+
+/// Which keyboard half and gamepad drive the car
+#[derive(Component, Clone, Copy)]
+pub struct Controls(pub usize);
+
+/// Drivers fill `CarInput` in this set, cars move after it
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InputSet;
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.rollback_component::<CarInput>()
+            .add_systems(Update, read_input.in_set(InputSet));
+    }
+}
+
+/// Every player drives with their half of the keyboard, and the gamepad
+/// connected in the same order if there is one. Cars with an `AiDriver` are
+/// left to it.
+pub fn read_input(
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut query: Query<(&Controls, &mut CarInput), Without<AiDriver>>,
+) {
+    let pads: Vec<Gamepad> = gamepads.iter().collect();
+    for (controls, mut input) in &mut query {
+        let mut steer = 0.0;
+        let mut drift = false;
+        let mut boost = false;
+
+        if let Some(bindings) = KeyBindings::PLAYERS.get(controls.0) {
+            if keys.pressed(bindings.left) {
+                steer += 1.0;
+            }
+            if keys.pressed(bindings.right) {
+                steer -= 1.0;
+            }
+            drift |= keys.pressed(bindings.drift);
+            boost |= keys.pressed(bindings.boost);
+        }
+
+        if let Some(&gamepad) = pads.get(controls.0) {
+            let button = |button_type| buttons.pressed(GamepadButton::new(gamepad, button_type));
+            let stick = axes
+                .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+                .unwrap_or(0.0);
+            if stick.abs() > STICK_DEAD_ZONE {
+                steer -= stick;
+            }
+            if button(GamepadButtonType::DPadLeft) {
+                steer += 1.0;
+            }
+            if button(GamepadButtonType::DPadRight) {
+                steer -= 1.0;
+            }
+            drift |= button(GamepadButtonType::South) || button(GamepadButtonType::LeftTrigger2);
+            boost |= button(GamepadButtonType::East) || button(GamepadButtonType::RightTrigger2);
+        }
+
+        *input = CarInput {
+            steer: steer.clamp(-MAX_STEER, MAX_STEER),
+            drift,
+            boost,
+        };
+    }
+}
@@ -0,0 +1,225 @@
+#![allow(clippy::needless_pass_by_value, clippy::module_name_repetitions)]
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    mode::GameMode,
+    replay::{NewReplay, Recording, Replay},
+    ui::NewLeaderboard,
+};
+
+const DEFAULT_NAME: &str = "player";
+/// Best scores listed on the game over screen
+const SHOWN_ENTRIES: usize = 5;
+/// The server plays the replay back before answering, give it some time
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// `--leaderboard URL` sends every one player run to a leaderboard server,
+/// under the name given with `--name`. `src/bin/leaderboard_server.rs` is a
+/// server to run locally.
+#[derive(Resource, Clone)]
+pub struct LeaderboardConfig {
+    pub url: String,
+    pub name: String,
+}
+
+impl LeaderboardConfig {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut url = None;
+        let mut name = DEFAULT_NAME.to_string();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--leaderboard" => url = args.next(),
+                "--name" => name = args.next().unwrap_or(name),
+                _ => (),
+            }
+        }
+        Some(Self {
+            url: url?.trim_end_matches('/').to_string(),
+            name,
+        })
+    }
+}
+
+/// Run sent to `POST /scores`, the replay lets the server check the score
+#[derive(Serialize, Deserialize)]
+pub struct Submission {
+    pub name: String,
+    pub score: usize,
+    /// Seconds of game time the run lasted
+    pub time: f32,
+    pub seed: u64,
+    pub mode: GameMode,
+    pub level: usize,
+    /// Contents of the replay file
+    pub replay: String,
+}
+
+/// Answer to a `Submission`
+#[derive(Serialize, Deserialize)]
+pub struct SubmitReply {
+    pub accepted: bool,
+    /// Place on the board, 1 is the best
+    pub rank: Option<usize>,
+    /// Why the run was turned down
+    pub reason: Option<String>,
+}
+
+/// One line of the board sent by `GET /scores?level=N&mode=NAME`, every level
+/// and mode has its own board
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub score: usize,
+    pub time: f32,
+    pub seed: u64,
+}
+
+#[derive(Debug, Error)]
+enum RequestError {
+    #[error("request failed: {0}")]
+    Http(Box<ureq::Error>),
+    #[error("unreadable answer: {0}")]
+    Answer(#[from] std::io::Error),
+}
+
+impl From<ureq::Error> for RequestError {
+    fn from(error: ureq::Error) -> Self {
+        Self::Http(Box::new(error))
+    }
+}
+
+#[derive(Resource)]
+struct Leaderboard {
+    config: LeaderboardConfig,
+    sender: Sender<String>,
+    /// Lines for the game over screen, written by the request threads
+    replies: Mutex<Receiver<String>>,
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        // A config set up before the plugin wins over the command line
+        let Some(config) = app
+            .world
+            .remove_resource::<LeaderboardConfig>()
+            .or_else(|| LeaderboardConfig::from_args(std::env::args().skip(1)))
+        else {
+            return;
+        };
+        let (sender, replies) = channel();
+        app.insert_resource(Leaderboard {
+            config,
+            sender,
+            replies: Mutex::new(replies),
+        })
+        // Out of `Update`, which has to match the one replays are checked with
+        .add_systems(PostUpdate, (submit_runs, show_replies));
+        if !app.world.contains_resource::<Recording>() {
+            app.insert_resource(Recording::default());
+        }
+    }
+}
+
+/// Sends every finished run away without holding the game up
+fn submit_runs(
+    leaderboard: Res<Leaderboard>,
+    mut new_replay: EventReader<NewReplay>,
+    mut new_leaderboard: EventWriter<NewLeaderboard>,
+) {
+    for NewReplay(replay) in new_replay.read() {
+        if replay.players != 1 {
+            new_leaderboard.send(NewLeaderboard(
+                "Only one player runs go on the leaderboard".to_string(),
+            ));
+            continue;
+        }
+        new_leaderboard.send(NewLeaderboard("Sending the run...".to_string()));
+        let config = leaderboard.config.clone();
+        let sender = leaderboard.sender.clone();
+        let replay = replay.clone();
+        std::thread::spawn(move || {
+            let reply = submit(&config, &replay).unwrap_or_else(|error| {
+                warn!("leaderboard: {error}");
+                format!("Leaderboard unreachable at {}", config.url)
+            });
+            // The game may be gone already
+            let _ = sender.send(reply);
+        });
+    }
+}
+
+fn submit(config: &LeaderboardConfig, replay: &Replay) -> Result<String, RequestError> {
+    let submission = Submission {
+        name: config.name.clone(),
+        score: replay.scores.first().copied().unwrap_or(0),
+        time: replay.time.as_secs_f32(),
+        seed: replay.seed,
+        mode: replay.mode,
+        level: replay.level,
+        replay: ron::to_string(replay).unwrap_or_default(),
+    };
+    let reply: SubmitReply = match ureq::post(&format!("{}/scores", config.url))
+        .timeout(TIMEOUT)
+        .send_json(&submission)
+    {
+        Ok(response) => response.into_json()?,
+        // Turned down runs come back with an error status and a reply
+        Err(ureq::Error::Status(_, response)) => response.into_json()?,
+        Err(error) => return Err(error.into()),
+    };
+    let entries: Vec<Entry> = ureq::get(&format!("{}/scores", config.url))
+        .timeout(TIMEOUT)
+        .query("level", &replay.level.to_string())
+        .query("mode", replay.mode.name())
+        .call()?
+        .into_json()?;
+
+    let mut lines = vec![match reply {
+        SubmitReply {
+            accepted: true,
+            rank: Some(rank),
+            ..
+        } => format!("Leaderboard rank {rank}"),
+        SubmitReply { accepted: true, .. } => "On the leaderboard".to_string(),
+        SubmitReply { reason, .. } => format!(
+            "Not on the leaderboard: {}",
+            reason.unwrap_or_else(|| "refused".to_string())
+        ),
+    }];
+    lines.extend(
+        entries
+            .iter()
+            .take(SHOWN_ENTRIES)
+            .enumerate()
+            .map(|(index, entry)| {
+                format!(
+                    "{}. {} {} in {:.1}s",
+                    index + 1,
+                    entry.name,
+                    entry.score,
+                    entry.time
+                )
+            }),
+    );
+    Ok(lines.join("\n"))
+}
+
+fn show_replies(leaderboard: Res<Leaderboard>, mut new_leaderboard: EventWriter<NewLeaderboard>) {
+    let Ok(replies) = leaderboard.replies.lock() else {
+        return;
+    };
+    for reply in replies.try_iter() {
+        new_leaderboard.send(NewLeaderboard(reply));
+    }
+}
@@ -0,0 +1,418 @@
+#![allow(
+    clippy::needless_pass_by_value,
+    clippy::cast_precision_loss,
+    clippy::module_name_repetitions,
+    clippy::type_complexity
+)]
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext, LoadState},
+    prelude::*,
+    reflect::TypePath,
+    utils::BoxedFuture,
+};
+use bevy_turborand::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    config::{
+        P8_BROWN, P8_DARK_GREEN, P8_DARK_GREY, P8_LIGHT_GREY, P8_WHITE, WINDOW_HEIGHT, WINDOW_WIDTH,
+    },
+    edge::{spawn_edge_walls, EdgeMode},
+    net,
+    obstacle::{spawn_obstacle, Obstacle, ObstacleAssets, ObstacleSpec},
+    race::{spawn_gates, Gate},
+    spectate::Spectator,
+    ui::NewLevelStatus,
+    vox::{self, VoxModel},
+    GameState, TILE_SIZE,
+};
+
+/// Arenas played one after the other, the first one is loaded on start
+pub const LEVELS: [&str; 4] = [
+    "levels/arena.level.ron",
+    "levels/warehouse.level.ron",
+    "levels/docks.level.ron",
+    "levels/circuit.level.ron",
+];
+/// Ground is drawn under everything else
+const GROUND_Z: f32 = 0.0;
+/// Size of the ground grid that covers the whole screen
+pub const GROUND_COLUMNS: usize = 25;
+pub const GROUND_ROWS: usize = 19;
+
+/// Arena layout, read from a `.level.ron` file.
+///
+/// `ground` holds one string per row of tiles, top row first, and one
+/// character per tile:
+/// - `.` bare ground, shows the clear color
+/// - `#` asphalt
+/// - `=` road marking
+/// - `-` concrete
+/// - `g` grass
+/// - `d` dirt
+#[derive(Asset, TypePath, Clone, Serialize, Deserialize)]
+pub struct Level {
+    pub name: String,
+    #[serde(default)]
+    pub ground: Vec<String>,
+    #[serde(default)]
+    pub obstacles: Vec<ObstacleSpec>,
+    /// Areas where barrels and pickups can land, the whole arena when empty
+    #[serde(default)]
+    pub spawn_zones: Vec<SpawnZone>,
+    /// Gates of the race route in driving order, not a race when empty. See
+    /// `Gate` for which way they face.
+    #[serde(default)]
+    pub checkpoints: Vec<Gate>,
+    #[serde(default)]
+    pub player_start: Vec2,
+    /// Car heading on start, in radians
+    #[serde(default)]
+    pub player_angle: f32,
+    #[serde(default)]
+    pub edges: EdgeMode,
+    #[serde(default)]
+    pub dificulty: DificultyOverrides,
+}
+
+impl Level {
+    /// World position of the top left corner of the ground grid, the grid is
+    /// centered on the screen and its rows go from the top down
+    pub fn ground_origin(&self) -> Vec2 {
+        Vec2::new(
+            -(self
+                .ground
+                .iter()
+                .map(|line| line.chars().count())
+                .max()
+                .unwrap_or(0) as f32),
+            self.ground.len() as f32,
+        ) * TILE_SIZE
+            * 0.5
+    }
+
+    /// Column and row of the tile under a world position
+    pub fn tile_at(&self, position: Vec2) -> Option<(usize, usize)> {
+        let offset = (position - self.ground_origin()) / TILE_SIZE;
+        let (column, row) = (offset.x.floor(), (-offset.y).floor());
+        if column < 0.0 || row < 0.0 {
+            return None;
+        }
+        let (column, row) = (column as usize, row as usize);
+        let line = self.ground.get(row)?;
+        (column < line.chars().count()).then_some((column, row))
+    }
+
+    pub fn set_tile(&mut self, column: usize, row: usize, tile: char) {
+        if let Some(line) = self.ground.get_mut(row) {
+            *line = line
+                .chars()
+                .enumerate()
+                .map(|(index, old)| if index == column { tile } else { old })
+                .collect();
+        }
+    }
+
+    /// Pad the ground with bare tiles until it covers the whole screen
+    pub fn fill_ground(&mut self) {
+        self.ground.resize(GROUND_ROWS, String::new());
+        for line in &mut self.ground {
+            let missing = GROUND_COLUMNS.saturating_sub(line.chars().count());
+            line.extend(std::iter::repeat_n('.', missing));
+        }
+    }
+}
+
+/// Rectangle given by two opposite corners
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct SpawnZone {
+    pub from: Vec2,
+    pub to: Vec2,
+}
+
+/// Barrel difficulty settings that replace the defaults of `barrel`
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
+pub struct DificultyOverrides {
+    pub start: Option<f32>,
+    pub max: Option<f32>,
+    /// Difficulty gained every second
+    pub ramp: Option<f32>,
+}
+
+/// Rules of the level being played, filled once its file is loaded
+#[derive(Resource, Default)]
+pub struct LevelRules {
+    pub player_start: Vec2,
+    pub player_angle: f32,
+    pub spawn_zones: Vec<Rect>,
+    pub checkpoints: Vec<Gate>,
+    pub edges: EdgeMode,
+    pub dificulty: DificultyOverrides,
+}
+
+impl LevelRules {
+    /// Random point inside the spawn zones, zones are picked by area
+    pub fn random_spawn_point(&self, global_rng: &mut GlobalRng) -> Vec2 {
+        let limit = Vec2::new(WINDOW_WIDTH - TILE_SIZE, WINDOW_HEIGHT - TILE_SIZE) * 0.5;
+        let Some(zone) = self.pick_zone(global_rng) else {
+            return Vec2::new(global_rng.f32_normalized(), global_rng.f32_normalized()) * limit;
+        };
+        let point = zone.min + zone.size() * Vec2::new(global_rng.f32(), global_rng.f32());
+        point.clamp(-limit, limit)
+    }
+
+    pub fn in_spawn_zone(&self, point: Vec2) -> bool {
+        self.spawn_zones.is_empty() || self.spawn_zones.iter().any(|zone| zone.contains(point))
+    }
+
+    fn pick_zone(&self, global_rng: &mut GlobalRng) -> Option<Rect> {
+        let area = |zone: &Rect| zone.width() * zone.height();
+        let total: f32 = self.spawn_zones.iter().map(area).sum();
+        let mut left = global_rng.f32() * total;
+        for zone in &self.spawn_zones {
+            left -= area(zone);
+            if left <= 0.0 {
+                return Some(*zone);
+            }
+        }
+        self.spawn_zones.last().copied()
+    }
+}
+
+#[derive(Resource)]
+pub struct CurrentLevel {
+    pub index: usize,
+    pub handle: Handle<Level>,
+    /// Last level that was built, played instead of a level that fails to load
+    built: Option<usize>,
+}
+
+/// Everything built from the level file, removed when the level changes
+#[derive(Component)]
+pub struct LevelPiece;
+
+#[derive(Debug, Error)]
+pub enum LevelError {
+    #[error("could not read level file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse level file: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+}
+
+#[derive(Default)]
+struct LevelLoader;
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Level>()
+            .init_asset_loader::<LevelLoader>()
+            .init_resource::<LevelRules>()
+            .add_systems(Startup, load_first_level)
+            .add_systems(
+                Update,
+                build_level
+                    .run_if(in_state(GameState::Loading))
+                    .run_if(vox::models_loaded)
+                    .run_if(net::ready),
+            )
+            .add_systems(Update, rebuild_level.run_if(in_state(GameState::Editor)))
+            .add_systems(
+                Update,
+                next_level
+                    .run_if(in_state(GameState::GameOver))
+                    .run_if(net::offline),
+            );
+    }
+}
+
+impl AssetLoader for LevelLoader {
+    type Asset = Level;
+    type Settings = ();
+    type Error = LevelError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Level, LevelError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
+
+fn load_first_level(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(CurrentLevel {
+        index: 0,
+        handle: asset_server.load(LEVELS[0]),
+        built: None,
+    });
+}
+
+fn next_level(
+    keys: Res<Input<KeyCode>>,
+    mut current: ResMut<CurrentLevel>,
+    asset_server: Res<AssetServer>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if keys.just_pressed(KeyCode::N) {
+        current.index = (current.index + 1) % LEVELS.len();
+        current.handle = asset_server.load(LEVELS[current.index]);
+        next_state.set(GameState::Loading);
+    }
+}
+
+fn tile_color(tile: char) -> Option<Color> {
+    match tile {
+        '#' => Some(P8_DARK_GREY),
+        '=' => Some(P8_WHITE),
+        'g' => Some(P8_DARK_GREEN),
+        'd' => Some(P8_BROWN),
+        '-' => Some(P8_LIGHT_GREY),
+        _ => None,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_level(
+    mut commands: Commands,
+    mut current: ResMut<CurrentLevel>,
+    asset_server: Res<AssetServer>,
+    levels: Res<Assets<Level>>,
+    obstacle_assets: Res<ObstacleAssets>,
+    models: Res<Assets<VoxModel>>,
+    old_pieces: Query<Entity, Or<(With<LevelPiece>, With<Obstacle>)>>,
+    mut rules: ResMut<LevelRules>,
+    spectator: Option<Res<Spectator>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut new_status: EventWriter<NewLevelStatus>,
+    mut fell_back: Local<bool>,
+    mut stuck_on: Local<Option<Handle<Level>>>,
+) {
+    if asset_server.load_state(&current.handle) == LoadState::Failed {
+        let failed = LEVELS[current.index];
+        let fallback = current
+            .built
+            .filter(|index| *index != current.index)
+            .unwrap_or(0);
+        if fallback == current.index {
+            // Told once, the load state stays failed until another level is picked
+            if stuck_on.as_ref() == Some(&current.handle) {
+                return;
+            }
+            *stuck_on = Some(current.handle.clone());
+            error!("level {failed} failed to load, there is no other level to play");
+            new_status.send(NewLevelStatus(format!("{failed} is broken")));
+            return;
+        }
+        error!(
+            "level {failed} failed to load, playing {} instead",
+            LEVELS[fallback]
+        );
+        new_status.send(NewLevelStatus(format!(
+            "{failed} is broken, playing {} instead",
+            LEVELS[fallback]
+        )));
+        current.index = fallback;
+        current.handle = asset_server.load(LEVELS[fallback]);
+        *fell_back = true;
+        return;
+    }
+    let Some(level) = levels.get(&current.handle) else {
+        return;
+    };
+    // The warning stays up while the level played instead of the broken one lasts
+    if !std::mem::take(&mut *fell_back) {
+        new_status.send(NewLevelStatus(String::new()));
+    }
+    *stuck_on = None;
+    current.built = Some(current.index);
+    spawn_level(&mut commands, level, &obstacle_assets, &models, &old_pieces);
+
+    *rules = LevelRules {
+        player_start: level.player_start,
+        player_angle: level.player_angle,
+        spawn_zones: level
+            .spawn_zones
+            .iter()
+            .map(|zone| Rect::from_corners(zone.from, zone.to))
+            .collect(),
+        checkpoints: level.checkpoints.clone(),
+        edges: level.edges,
+        dificulty: level.dificulty,
+    };
+    info!("level loaded: {}", level.name);
+    next_state.set(if spectator.is_some() {
+        GameState::Spectating
+    } else {
+        GameState::Playing
+    });
+}
+
+/// Redraw the arena whenever the level being edited changes
+fn rebuild_level(
+    mut commands: Commands,
+    current: Res<CurrentLevel>,
+    levels: Res<Assets<Level>>,
+    mut level_event: EventReader<AssetEvent<Level>>,
+    obstacle_assets: Res<ObstacleAssets>,
+    models: Res<Assets<VoxModel>>,
+    old_pieces: Query<Entity, Or<(With<LevelPiece>, With<Obstacle>)>>,
+) {
+    let modified = level_event
+        .read()
+        .any(|event| event.is_modified(current.handle.id()));
+    if let (true, Some(level)) = (modified, levels.get(&current.handle)) {
+        spawn_level(&mut commands, level, &obstacle_assets, &models, &old_pieces);
+    }
+}
+
+fn spawn_level(
+    commands: &mut Commands,
+    level: &Level,
+    obstacle_assets: &ObstacleAssets,
+    models: &Assets<VoxModel>,
+    old_pieces: &Query<Entity, Or<(With<LevelPiece>, With<Obstacle>)>>,
+) {
+    for entity in old_pieces {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let origin = level.ground_origin();
+    for (row, line) in level.ground.iter().enumerate() {
+        for (column, tile) in line.chars().enumerate() {
+            let Some(color) = tile_color(tile) else {
+                continue;
+            };
+            let position = origin + Vec2::new(column as f32 + 0.5, -(row as f32 + 0.5)) * TILE_SIZE;
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color,
+                        custom_size: Some(Vec2::splat(TILE_SIZE)),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(position.extend(GROUND_Z)),
+                    ..default()
+                },
+                LevelPiece,
+            ));
+        }
+    }
+    for spec in &level.obstacles {
+        spawn_obstacle(commands, obstacle_assets, models, spec);
+    }
+    spawn_gates(commands, &level.checkpoints);
+    if level.edges == EdgeMode::Walls {
+        spawn_edge_walls(commands);
+    }
+}
@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+
+mod ai;
+mod barrel;
+mod camera;
+mod collision;
+pub mod config;
+mod drop;
+mod edge;
+mod editor;
+mod health;
+mod input;
+pub mod leaderboard;
+mod level;
+mod mode;
+mod net;
+mod obstacle;
+mod particle;
+mod pickup;
+mod player;
+mod race;
+pub mod replay;
+mod shadow;
+mod spectate;
+mod stacked_sprite;
+mod ui;
+mod vox;
+
+const TILE_SIZE: f32 = 32.0;
+
+#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameState {
+    /// Waiting for the level file before building the arena
+    #[default]
+    Loading,
+    Playing,
+    GameOver,
+    /// Simulation paused while the level is being edited
+    Editor,
+    /// Drawing a game sent from somewhere else, nothing is simulated
+    Spectating,
+}
+
+/// Adds the game to an app that already has the Bevy plugins, shared by the
+/// window and the headless replay checks
+pub fn add_game(app: &mut App) {
+    app.add_state::<GameState>();
+
+    // Gameplay systems are ordered from the drops to the score, leaving it to
+    // the scheduler would let two processes play the same inputs differently
+    app.add_plugins((
+        camera::Plug,
+        player::Plug,
+        ui::Plug,
+        barrel::Plug,
+        particle::Plug,
+        stacked_sprite::Plug,
+        vox::Plug,
+        shadow::Plug,
+    ));
+    app.add_plugins((
+        health::Plug,
+        input::Plug,
+        drop::Plug,
+        pickup::Plug,
+        collision::Plug,
+        obstacle::Plug,
+        level::Plug,
+        editor::Plug,
+        edge::Plug,
+        race::Plug,
+        mode::Plug,
+        ai::Plug,
+    ));
+    app.add_plugins((net::Plug, spectate::Plug, leaderboard::Plug, replay::Plug));
+}
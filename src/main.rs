@@ -1,15 +1,6 @@
 use bevy::{prelude::*, window::WindowResolution};
 use bevy_turborand::prelude::RngPlugin;
-use config::{WINDOW_HEIGHT, WINDOW_TITLE, WINDOW_WIDTH};
-
-mod barrel;
-mod camera;
-mod config;
-mod particle;
-mod player;
-mod ui;
-
-const TILE_SIZE: f32 = 32.0;
+use super_oxi_car::config::{WINDOW_HEIGHT, WINDOW_TITLE, WINDOW_WIDTH};
 
 fn main() {
     let mut app = App::new();
@@ -29,14 +20,7 @@ fn main() {
         RngPlugin::default(),
     ))
     .insert_resource(Msaa::Off);
-
-    app.add_plugins((
-        camera::Plug,
-        player::Plug,
-        ui::Plug,
-        barrel::Plug,
-        particle::Plug,
-    ));
+    super_oxi_car::add_game(&mut app);
 
     app.run();
 }
@@ -0,0 +1,286 @@
+#![allow(clippy::needless_pass_by_value, clippy::cast_possible_truncation)]
+use std::hash::Hash;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    barrel::{BarrelExplodedEvent, BarrelSet},
+    net::{self, RollbackApp, StateHash},
+    pickup::{PickedUpEvent, PickupSet},
+    player::{
+        BonusScore, Movement, Player, PlayerCount, PlayerId, ScoreManager, ScoreSet, MAX_PLAYERS,
+    },
+    replay::Fnv,
+    ui::{NewGameMode, NewModeStatus, NewRunResult},
+    GameState,
+};
+
+const COLLECT_TARGET: u32 = 10;
+const NO_DRIFT_TARGET: u32 = 30;
+const SCORE_ATTACK_SECONDS: f32 = 60.0;
+/// Points for every pickup in `GameMode::Collect`
+const PICKUP_POINTS: usize = 5;
+/// Points for every barrel gone off in `GameMode::NoDrift`
+const BARREL_POINTS: usize = 1;
+
+/// Rules of the run, picked on the game over screen.
+///
+/// Every mode ends when a car runs out of lives.
+#[derive(Resource, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum GameMode {
+    /// Last as long as possible
+    #[default]
+    Survival,
+    /// Win by being the first to grab this many pickups
+    Collect { target: u32 },
+    /// Win once this many barrels went off, drifting loses on the spot
+    NoDrift { target: u32 },
+    /// Score as much as possible before the time runs out
+    ScoreAttack { seconds: f32 },
+}
+
+impl GameMode {
+    const ALL: [GameMode; 4] = [
+        GameMode::Survival,
+        GameMode::Collect {
+            target: COLLECT_TARGET,
+        },
+        GameMode::NoDrift {
+            target: NO_DRIFT_TARGET,
+        },
+        GameMode::ScoreAttack {
+            seconds: SCORE_ATTACK_SECONDS,
+        },
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GameMode::Survival => "Survival",
+            GameMode::Collect { .. } => "Collector",
+            GameMode::NoDrift { .. } => "No drifting",
+            GameMode::ScoreAttack { .. } => "Score attack",
+        }
+    }
+
+    /// Whether this is one of the modes picked on the game over screen, with
+    /// the target or time limit every run of that mode has
+    pub fn is_canonical(self) -> bool {
+        Self::ALL.contains(&self)
+    }
+
+    /// Whether the car scores every second just for staying alive
+    pub fn time_points(self) -> bool {
+        matches!(self, GameMode::Survival)
+    }
+
+    fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|mode| *mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// How the run ended when it was not by losing every life
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Outcome {
+    Won,
+    TimeUp,
+    /// The player who drifted
+    Drifted(usize),
+}
+
+#[derive(Resource, Default, Clone)]
+struct ModeProgress {
+    players: usize,
+    /// Pickups grabbed by every player
+    pickups: [u32; MAX_PLAYERS],
+    barrels: u32,
+    elapsed: f32,
+    outcome: Option<Outcome>,
+}
+
+impl StateHash for ModeProgress {
+    fn state_hash(&self, hasher: &mut Fnv) {
+        (self.pickups, self.barrels, self.outcome).hash(hasher);
+        self.elapsed.to_bits().hash(hasher);
+    }
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameMode>()
+            .init_resource::<ModeProgress>()
+            .rollback_checked_resource::<ModeProgress>()
+            .add_systems(PostStartup, send_mode)
+            .add_systems(OnEnter(GameState::Playing), reset_progress)
+            .add_systems(
+                Update,
+                (count_progress, check_outcome)
+                    .chain()
+                    .after(PickupSet)
+                    .after(BarrelSet)
+                    .before(ScoreSet)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(OnEnter(GameState::GameOver), send_result)
+            .add_systems(
+                Update,
+                switch_mode
+                    .run_if(in_state(GameState::GameOver))
+                    .run_if(net::offline),
+            );
+    }
+}
+
+fn reset_progress(
+    mode: Res<GameMode>,
+    count: Res<PlayerCount>,
+    mut progress: ResMut<ModeProgress>,
+    mut new_status: EventWriter<NewModeStatus>,
+) {
+    *progress = ModeProgress {
+        players: count.0,
+        ..default()
+    };
+    new_status.send(NewModeStatus(status(*mode, &progress)));
+}
+
+fn status(mode: GameMode, progress: &ModeProgress) -> String {
+    match mode {
+        GameMode::Survival => String::new(),
+        GameMode::Collect { target } => {
+            let pickups: Vec<String> = progress.pickups[..progress.players]
+                .iter()
+                .map(u32::to_string)
+                .collect();
+            format!("Pickups {}/{target}", pickups.join(" - "))
+        }
+        GameMode::NoDrift { target } => format!("Barrels {}/{target}", progress.barrels),
+        GameMode::ScoreAttack { seconds } => {
+            format!("Time {:.0}", (seconds - progress.elapsed).max(0.0).ceil())
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn count_progress(
+    mode: Res<GameMode>,
+    mut progress: ResMut<ModeProgress>,
+    player_query: Query<&PlayerId, With<Player>>,
+    mut picked_up_event: EventReader<PickedUpEvent>,
+    mut exploded_event: EventReader<BarrelExplodedEvent>,
+    mut bonus_score: EventWriter<BonusScore>,
+    mut new_status: EventWriter<NewModeStatus>,
+    time: Res<Time>,
+) {
+    let exploded = exploded_event.read().count() as u32;
+    let last_status = status(*mode, &progress);
+
+    for picked_up in picked_up_event.read() {
+        let Ok(id) = player_query.get(picked_up.car) else {
+            continue;
+        };
+        progress.pickups[id.0] += 1;
+        if matches!(*mode, GameMode::Collect { .. }) {
+            bonus_score.send(BonusScore {
+                car: Some(picked_up.car),
+                points: PICKUP_POINTS,
+            });
+        }
+    }
+    progress.barrels += exploded;
+    progress.elapsed += time.delta_seconds();
+    if matches!(*mode, GameMode::NoDrift { .. }) {
+        for _ in 0..exploded {
+            bonus_score.send(BonusScore {
+                car: None,
+                points: BARREL_POINTS,
+            });
+        }
+    }
+
+    let status = status(*mode, &progress);
+    if status != last_status {
+        new_status.send(NewModeStatus(status));
+    }
+}
+
+fn check_outcome(
+    mode: Res<GameMode>,
+    mut progress: ResMut<ModeProgress>,
+    player_query: Query<(&Movement, &PlayerId), With<Player>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let outcome = match *mode {
+        GameMode::Survival => None,
+        GameMode::Collect { target } => progress
+            .pickups
+            .iter()
+            .any(|pickups| *pickups >= target)
+            .then_some(Outcome::Won),
+        GameMode::NoDrift { target } => {
+            if let Some((_, id)) = player_query.iter().find(|(movement, _)| movement.drifting) {
+                Some(Outcome::Drifted(id.0))
+            } else {
+                (progress.barrels >= target).then_some(Outcome::Won)
+            }
+        }
+        GameMode::ScoreAttack { seconds } => {
+            (progress.elapsed >= seconds).then_some(Outcome::TimeUp)
+        }
+    };
+    if outcome.is_some() {
+        progress.outcome = outcome;
+        next_state.set(GameState::GameOver);
+    }
+}
+
+fn send_result(
+    mode: Res<GameMode>,
+    progress: Res<ModeProgress>,
+    score_query: Query<(&ScoreManager, &PlayerId), With<Player>>,
+    mut new_result: EventWriter<NewRunResult>,
+) {
+    let title = match progress.outcome {
+        Some(Outcome::Won) => "YOU WIN",
+        Some(Outcome::TimeUp) => "TIME UP",
+        Some(Outcome::Drifted(_)) => "NO DRIFTING!",
+        None => "GAME OVER",
+    };
+    let mut scores: Vec<(usize, usize)> = score_query
+        .iter()
+        .map(|(score, id)| (id.0, score.score()))
+        .collect();
+    scores.sort_unstable();
+    let scores = match scores.as_slice() {
+        [(_, score)] => format!("Score {score}"),
+        scores => scores
+            .iter()
+            .map(|(player, score)| format!("P{} {score}", player + 1))
+            .collect::<Vec<_>>()
+            .join("   "),
+    };
+    let detail = match progress.outcome {
+        Some(Outcome::Drifted(player)) if score_query.iter().count() > 1 => {
+            format!("{}  -  P{} drifted  -  {scores}", mode.name(), player + 1)
+        }
+        _ => format!("{}  -  {scores}", mode.name()),
+    };
+    new_result.send(NewRunResult { title, detail });
+}
+
+fn switch_mode(
+    keys: Res<Input<KeyCode>>,
+    mut mode: ResMut<GameMode>,
+    mut new_mode: EventWriter<NewGameMode>,
+) {
+    if keys.just_pressed(KeyCode::M) {
+        *mode = mode.next();
+        new_mode.send(NewGameMode(mode.name()));
+    }
+}
+
+fn send_mode(mode: Res<GameMode>, mut new_mode: EventWriter<NewGameMode>) {
+    new_mode.send(NewGameMode(mode.name()));
+}
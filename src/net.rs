@@ -0,0 +1,1488 @@
+#![allow(
+    clippy::needless_pass_by_value,
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap
+)]
+use std::{
+    any::Any,
+    collections::{BTreeMap, VecDeque},
+    hash::Hash,
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    app::StateTransition,
+    ecs::schedule::ExecutorKind,
+    input::InputSystem,
+    prelude::*,
+    time::TimeUpdateStrategy,
+    utils::{HashMap, HashSet},
+};
+use bevy_turborand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::CameraFocus,
+    input::{read_input, CarInput, Controls, InputSet},
+    player::{PlayerCount, PlayerId, MAX_PLAYERS},
+    replay::{self, Fnv},
+    ui::NewNetStatus,
+    GameState,
+};
+
+/// Length of a frame, every peer runs the same ones
+const TICK: Duration = Duration::from_nanos(16_666_667);
+/// Frames between pressing a key and the car reacting, time for the input
+/// to reach the other peers before they need it
+const INPUT_DELAY: u32 = 2;
+/// Most frames run ahead of the inputs received from a peer
+const MAX_PREDICTION: u32 = 12;
+/// Frames that can be rolled back to, more than the prediction needs
+const SNAPSHOTS: usize = MAX_PREDICTION as usize * 4;
+/// Frames between two state checksums compared with the other peers
+const CHECKSUM_INTERVAL: u32 = 30;
+/// Checksums repeated in every packet in case some get lost
+const SENT_CHECKSUMS: usize = 4;
+/// Inputs sent in every packet, they are sent again until acknowledged
+const MAX_SENT_INPUTS: usize = 32;
+/// Spreads the per frame seeds of the random numbers apart
+const FRAME_SEED_STEP: u64 = 0x9E37_79B9_7F4A_7C15;
+const HELLO_INTERVAL: Duration = Duration::from_millis(250);
+/// Inputs are sent again this often while waiting for a peer
+const RESEND_INTERVAL: Duration = Duration::from_millis(30);
+/// A peer silent for this long is left behind, its car keeps its last input
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const PACKET_SIZE: usize = 16 * 1024;
+
+/// How to take part in an online game, read from the command line.
+///
+/// `--host PORT` waits for `--players N` cars, 2 by default, and
+/// `--join ADDRESS:PORT` takes a seat in a hosted game. `--lag MS` and
+/// `--loss PERCENT` hold back and drop sent packets to try bad connections
+/// on loopback. `--seed N` fixes the random numbers, online or not.
+#[derive(Resource, Clone)]
+pub struct NetConfig {
+    pub role: Option<Role>,
+    pub players: usize,
+    pub lag: Duration,
+    /// Share of the sent packets dropped, between 0 and 1
+    pub loss: f64,
+    pub seed: Option<u64>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Role {
+    Host { port: u16 },
+    Join { host: SocketAddr },
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            role: None,
+            players: 2,
+            lag: Duration::ZERO,
+            loss: 0.0,
+            seed: None,
+        }
+    }
+}
+
+impl NetConfig {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Self {
+        let mut config = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--host" => {
+                    config.role = parse(&arg, args.next()).map(|port| Role::Host { port });
+                }
+                "--join" => {
+                    config.role = args
+                        .next()
+                        .and_then(|host| host.to_socket_addrs().ok()?.next())
+                        .map(|host| Role::Join { host });
+                    if config.role.is_none() {
+                        error!("--join needs a reachable address like 127.0.0.1:7777");
+                    }
+                }
+                "--players" => {
+                    if let Some(players) = parse::<usize>(&arg, args.next()) {
+                        config.players = players.clamp(2, MAX_PLAYERS);
+                    }
+                }
+                "--lag" => {
+                    if let Some(lag) = parse(&arg, args.next()) {
+                        config.lag = Duration::from_millis(lag);
+                    }
+                }
+                "--loss" => {
+                    if let Some(loss) = parse::<f64>(&arg, args.next()) {
+                        config.loss = (loss / 100.0).clamp(0.0, 1.0);
+                    }
+                }
+                "--seed" => config.seed = parse(&arg, args.next()),
+                _ => (),
+            }
+        }
+        config
+    }
+}
+
+fn parse<T: FromStr>(name: &str, value: Option<String>) -> Option<T> {
+    let parsed = value.as_deref().and_then(|value| value.parse().ok());
+    if parsed.is_none() {
+        error!("{name} needs a number, got {value:?}");
+    }
+    parsed
+}
+
+/// What a player does in one frame, the part of `CarInput` sent to peers
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+struct NetInput {
+    steer: i8,
+    drift: bool,
+    boost: bool,
+    /// Starts the next run from the game over screen
+    restart: bool,
+}
+
+impl NetInput {
+    fn new(input: CarInput, restart: bool) -> Self {
+        Self {
+            steer: (input.steer.clamp(-1.0, 1.0) * 127.0).round() as i8,
+            drift: input.drift,
+            boost: input.boost,
+            restart,
+        }
+    }
+
+    fn car_input(self) -> CarInput {
+        CarInput {
+            steer: f32::from(self.steer) / 127.0,
+            drift: self.drift,
+            boost: self.boost,
+        }
+    }
+}
+
+/// Inputs of one player, with the guesses made for frames not received yet
+#[derive(Default)]
+struct InputLog {
+    /// Every input from the first frame on, without gaps
+    known: Vec<NetInput>,
+    /// Guessed inputs that were played, checked once the real ones arrive
+    guesses: BTreeMap<u32, NetInput>,
+}
+
+impl InputLog {
+    fn new() -> Self {
+        Self {
+            known: vec![NetInput::default(); INPUT_DELAY as usize],
+            guesses: BTreeMap::new(),
+        }
+    }
+
+    fn received(&self) -> u32 {
+        self.known.len() as u32
+    }
+
+    /// Input of `frame`, guessed as the last one received when it is not
+    /// there yet. Restarts are never guessed.
+    fn get(&mut self, frame: u32) -> NetInput {
+        if let Some(input) = self.known.get(frame as usize) {
+            return *input;
+        }
+        let guess = NetInput {
+            restart: false,
+            ..self.known.last().copied().unwrap_or_default()
+        };
+        self.guesses.insert(frame, guess);
+        guess
+    }
+
+    /// Adds inputs starting at frame `start`, returns the first frame that
+    /// was played with a wrong guess
+    fn receive(&mut self, start: u32, inputs: &[NetInput]) -> Option<u32> {
+        // Packets with a gap before them are ignored, the gap is sent again
+        let skip = self.received().checked_sub(start)? as usize;
+        let mut wrong = None;
+        for (frame, input) in (start..).zip(inputs).skip(skip) {
+            self.known.push(*input);
+            if self
+                .guesses
+                .remove(&frame)
+                .is_some_and(|guess| guess != *input)
+            {
+                wrong.get_or_insert(frame);
+            }
+        }
+        wrong
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum Message {
+    /// Asks the host for a seat
+    Hello,
+    /// Seat given by the host once every player is there, with where to
+    /// reach the others. The host leaves itself out, the joiner knows it.
+    Welcome {
+        player: usize,
+        seed: u64,
+        peers: Vec<Option<SocketAddr>>,
+    },
+    Inputs {
+        player: usize,
+        /// Frame of the first input
+        start: u32,
+        inputs: Vec<NetInput>,
+        /// Inputs of the receiver the sender has, the rest is sent again
+        received: u32,
+        /// Frame the sender is on and how far it is ahead of the receiver
+        frame: u32,
+        lead: i64,
+        checksums: Vec<(u32, u64)>,
+    },
+}
+
+/// Socket sending packets late and losing some of them when asked to
+struct Link {
+    socket: UdpSocket,
+    lag: Duration,
+    loss: f64,
+    /// Packets held back, with when to send them
+    queue: Vec<(Instant, SocketAddr, Vec<u8>)>,
+    rng: RngComponent,
+}
+
+impl Link {
+    fn send(&mut self, to: SocketAddr, message: &Message) {
+        let Ok(packet) = ron::to_string(message) else {
+            return;
+        };
+        if self.rng.chance(self.loss) {
+            return;
+        }
+        // Some jitter on top of the lag, so packets also arrive out of order
+        let lag = self.lag.mul_f32(1.0 + self.rng.f32() * 0.25);
+        self.queue
+            .push((Instant::now() + lag, to, packet.into_bytes()));
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        let now = Instant::now();
+        let socket = &self.socket;
+        self.queue.retain(|(when, to, packet)| {
+            if *when > now {
+                return true;
+            }
+            if let Err(error) = socket.send_to(packet, to) {
+                debug!("packet to {to} not sent: {error}");
+            }
+            false
+        });
+    }
+
+    fn receive(&mut self) -> Vec<(SocketAddr, Message)> {
+        let mut buffer = [0; PACKET_SIZE];
+        let mut messages = Vec::new();
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((size, from)) => {
+                    let message = std::str::from_utf8(&buffer[..size])
+                        .ok()
+                        .and_then(|packet| ron::from_str(packet).ok());
+                    if let Some(message) = message {
+                        messages.push((from, message));
+                    }
+                }
+                Err(error) => match error.kind() {
+                    ErrorKind::WouldBlock => break,
+                    // Peers that went away show up as errors on some systems
+                    ErrorKind::ConnectionReset | ErrorKind::ConnectionRefused => (),
+                    _ => {
+                        warn!("could not receive: {error}");
+                        break;
+                    }
+                },
+            }
+        }
+        messages
+    }
+}
+
+enum Phase {
+    Hosting,
+    Joining {
+        host: SocketAddr,
+        last_hello: Instant,
+    },
+    Running,
+}
+
+/// Online game in progress, peer to peer with rollback.
+///
+/// Every peer runs the whole game and sends its inputs to the others. Inputs
+/// not received yet are guessed, and when a guess turns out wrong the world
+/// goes back to the frame of the guess and plays it again. Only what the
+/// modules registered with `RollbackApp` goes back in time, anything else,
+/// like particles, just sees some frames twice.
+#[derive(Resource)]
+pub struct NetSession {
+    link: Link,
+    phase: Phase,
+    players: usize,
+    local: usize,
+    /// Where to reach every player, `None` for the local one
+    peers: Vec<Option<SocketAddr>>,
+    seed: u64,
+    /// Whether the first run started and frames are counted
+    started: bool,
+    frame: u32,
+    /// Whether the frame being run was already played before
+    resimulating: bool,
+    inputs: Vec<InputLog>,
+    /// Local inputs every peer has
+    acked: Vec<u32>,
+    /// Frame every peer was last on, and how far ahead of us it said it was
+    peer_frames: Vec<u32>,
+    peer_leads: Vec<i64>,
+    last_heard: Vec<Instant>,
+    last_sent: Instant,
+    next_tick: Instant,
+    rollback_to: Option<u32>,
+    next_rollback_id: u32,
+    /// Checksums of the confirmed frames, and those from peers not checked yet
+    checksums: BTreeMap<u32, u64>,
+    peer_checksums: Vec<(usize, u32, u64)>,
+    next_checksum: u32,
+    desync: Option<u32>,
+    /// Whether a peer stopped answering, its inputs are guessed from then on
+    lost: bool,
+    /// Whether this app update skipped the frame to wait for a peer
+    holding: bool,
+}
+
+impl NetSession {
+    fn new(config: &NetConfig) -> std::io::Result<Self> {
+        let (port, phase, peers) = match config.role {
+            Some(Role::Host { port }) => (port, Phase::Hosting, vec![None]),
+            Some(Role::Join { host }) => (
+                0,
+                Phase::Joining {
+                    host,
+                    last_hello: Instant::now() - HELLO_INTERVAL,
+                },
+                Vec::new(),
+            ),
+            None => return Err(ErrorKind::InvalidInput.into()),
+        };
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_nonblocking(true)?;
+        let mut rng = RngComponent::new();
+        let seed = config.seed.unwrap_or_else(|| rng.u64(..));
+        Ok(Self {
+            link: Link {
+                socket,
+                lag: config.lag,
+                loss: config.loss,
+                queue: Vec::new(),
+                rng,
+            },
+            phase,
+            players: config.players,
+            local: 0,
+            peers,
+            seed,
+            started: false,
+            frame: 0,
+            resimulating: false,
+            inputs: Vec::new(),
+            acked: Vec::new(),
+            peer_frames: Vec::new(),
+            peer_leads: Vec::new(),
+            last_heard: Vec::new(),
+            last_sent: Instant::now(),
+            next_tick: Instant::now(),
+            rollback_to: None,
+            next_rollback_id: 0,
+            checksums: BTreeMap::new(),
+            peer_checksums: Vec::new(),
+            next_checksum: CHECKSUM_INTERVAL,
+            desync: None,
+            lost: false,
+            holding: false,
+        })
+    }
+
+    /// Every player is there and the game can start
+    pub fn connected(&self) -> bool {
+        matches!(self.phase, Phase::Running)
+    }
+
+    fn start_running(&mut self) {
+        self.players = self.peers.len();
+        self.inputs = (0..self.players).map(|_| InputLog::new()).collect();
+        self.acked = vec![0; self.players];
+        self.peer_frames = vec![0; self.players];
+        self.peer_leads = vec![0; self.players];
+        self.last_heard = vec![Instant::now(); self.players];
+        self.phase = Phase::Running;
+        info!("online as player {} of {}", self.local + 1, self.players);
+    }
+
+    fn remote_players(&self) -> impl Iterator<Item = (usize, SocketAddr)> + '_ {
+        self.peers
+            .iter()
+            .enumerate()
+            .filter_map(|(player, peer)| Some((player, (*peer)?)))
+    }
+
+    fn poll(&mut self) {
+        self.link.flush();
+        for (from, message) in self.link.receive() {
+            self.handle(from, message);
+        }
+        match self.phase {
+            Phase::Joining {
+                host,
+                ref mut last_hello,
+            } if last_hello.elapsed() >= HELLO_INTERVAL => {
+                *last_hello = Instant::now();
+                self.link.send(host, &Message::Hello);
+            }
+            Phase::Running if self.started && !self.lost => {
+                let silent = self
+                    .remote_players()
+                    .map(|(player, _)| player)
+                    .find(|player| self.last_heard[*player].elapsed() > DISCONNECT_TIMEOUT);
+                if let Some(player) = silent {
+                    warn!("lost player {}, their car keeps its last input", player + 1);
+                    self.lost = true;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn handle(&mut self, from: SocketAddr, message: Message) {
+        match message {
+            Message::Hello => self.welcome(from),
+            Message::Welcome {
+                player,
+                seed,
+                mut peers,
+            } => {
+                let Phase::Joining { host, .. } = self.phase else {
+                    return;
+                };
+                if from != host {
+                    return;
+                }
+                // The host is player 0, a seat past the others is made up
+                if player == 0 || player >= peers.len() || peers.len() > MAX_PLAYERS {
+                    warn!("bad seat {player} of {} from the host", peers.len());
+                    return;
+                }
+                if let Some(slot) = peers.first_mut() {
+                    *slot = Some(host);
+                }
+                if let Some(slot) = peers.get_mut(player) {
+                    *slot = None;
+                }
+                self.local = player;
+                self.peers = peers;
+                self.seed = seed;
+                self.start_running();
+            }
+            Message::Inputs {
+                player,
+                start,
+                inputs,
+                received,
+                frame,
+                lead,
+                checksums,
+            } => {
+                if !self.connected() || self.peers.get(player) != Some(&Some(from)) {
+                    return;
+                }
+                self.last_heard[player] = Instant::now();
+                self.acked[player] = self.acked[player].max(received);
+                if frame >= self.peer_frames[player] {
+                    self.peer_frames[player] = frame;
+                    self.peer_leads[player] = lead;
+                }
+                if let Some(wrong) = self.inputs[player].receive(start, &inputs) {
+                    self.rollback_to = Some(self.rollback_to.map_or(wrong, |from| from.min(wrong)));
+                }
+                for (frame, checksum) in checksums {
+                    self.compare(player, frame, checksum);
+                }
+            }
+        }
+    }
+
+    /// Seats a joiner, everyone gets told where to find each other once the
+    /// last seat is taken
+    fn welcome(&mut self, from: SocketAddr) {
+        if self.local != 0 || matches!(self.phase, Phase::Joining { .. }) {
+            return;
+        }
+        let seated = self.peers.contains(&Some(from));
+        if !seated && matches!(self.phase, Phase::Hosting) && self.peers.len() < self.players {
+            info!("player {} joined from {from}", self.peers.len() + 1);
+            self.peers.push(Some(from));
+            if self.peers.len() == self.players {
+                self.start_running();
+            }
+        }
+        if !self.connected() {
+            return;
+        }
+        // Sent to everyone the first time, again to whoever did not get it
+        let joiners: Vec<(usize, SocketAddr)> = self
+            .remote_players()
+            .filter(|(_, peer)| !seated || *peer == from)
+            .collect();
+        for (player, peer) in joiners {
+            let welcome = Message::Welcome {
+                player,
+                seed: self.seed,
+                peers: self.peers.clone(),
+            };
+            self.link.send(peer, &welcome);
+        }
+    }
+
+    fn send_inputs(&mut self) {
+        let local = &self.inputs[self.local].known;
+        let checksums: Vec<(u32, u64)> = self
+            .checksums
+            .iter()
+            .rev()
+            .take(SENT_CHECKSUMS)
+            .map(|(frame, checksum)| (*frame, *checksum))
+            .collect();
+        let mut messages = Vec::new();
+        for (player, peer) in self.remote_players() {
+            let start = self.acked[player].min(local.len() as u32);
+            let end = local.len().min(start as usize + MAX_SENT_INPUTS);
+            messages.push((
+                peer,
+                Message::Inputs {
+                    player: self.local,
+                    start,
+                    inputs: local[start as usize..end].to_vec(),
+                    received: self.inputs[player].received(),
+                    frame: self.frame,
+                    lead: i64::from(self.frame) - i64::from(self.peer_frames[player]),
+                    checksums: checksums.clone(),
+                },
+            ));
+        }
+        for (peer, message) in messages {
+            self.link.send(peer, &message);
+        }
+        self.last_sent = Instant::now();
+    }
+
+    /// Frames with every input received, they will not be rolled back
+    fn confirmed(&self) -> u32 {
+        self.inputs
+            .iter()
+            .map(InputLog::received)
+            .min()
+            .unwrap_or(0)
+            .min(self.frame)
+    }
+
+    /// Inputs received from the peer furthest behind
+    fn slowest_peer(&self) -> u32 {
+        self.remote_players()
+            .map(|(player, _)| self.inputs[player].received())
+            .min()
+            .unwrap_or(u32::MAX)
+    }
+
+    /// Whether a peer is too far behind to keep guessing its inputs, the
+    /// frame waits for it then. `poll` gives up on peers silent for too long.
+    fn waiting_for_peers(&mut self) -> bool {
+        let waiting = !self.lost && self.frame > self.slowest_peer().saturating_add(MAX_PREDICTION);
+        if waiting && self.last_sent.elapsed() > RESEND_INTERVAL {
+            self.send_inputs();
+        }
+        waiting
+    }
+
+    /// Runs a frame every tick, a bit slower while ahead of the other peers
+    fn keep_pace(&mut self) {
+        // Both sides see each other late by the same lag, so half the
+        // difference of the leads is how far ahead this peer really is
+        let ahead = self
+            .remote_players()
+            .map(|(player, _)| {
+                let lead = i64::from(self.frame) - i64::from(self.peer_frames[player]);
+                (lead - self.peer_leads[player]) / 2
+            })
+            .max()
+            .unwrap_or(0);
+        if ahead > 1 {
+            self.next_tick += TICK;
+        }
+        let now = Instant::now();
+        if self.next_tick > now {
+            std::thread::sleep(self.next_tick - now);
+        }
+        self.next_tick = self.next_tick.max(now) + TICK;
+    }
+
+    /// Keeps the checksums of the frames that became confirmed and compares
+    /// them with the ones the peers sent
+    fn check_sync(&mut self, snapshots: &Snapshots) {
+        let confirmed = self.confirmed();
+        while self.next_checksum <= confirmed {
+            let frame = self.next_checksum;
+            self.next_checksum += CHECKSUM_INTERVAL;
+            let Some(snapshot) = snapshots.get(frame) else {
+                continue;
+            };
+            self.checksums.insert(frame, snapshot.checksum);
+            let pending: Vec<(usize, u32, u64)> = self
+                .peer_checksums
+                .iter()
+                .copied()
+                .filter(|(_, checked, _)| *checked <= frame)
+                .collect();
+            self.peer_checksums
+                .retain(|(_, checked, _)| *checked > frame);
+            for (player, checked, checksum) in pending {
+                self.compare(player, checked, checksum);
+            }
+        }
+        while self.checksums.len() > SENT_CHECKSUMS {
+            self.checksums.pop_first();
+        }
+    }
+
+    fn compare(&mut self, player: usize, frame: u32, checksum: u64) {
+        match self.checksums.get(&frame) {
+            Some(local) if *local != checksum && self.desync.is_none() => {
+                warn!("out of sync with player {} at frame {frame}", player + 1);
+                self.desync = Some(frame);
+            }
+            Some(_) => (),
+            None if frame >= self.next_checksum => {
+                self.peer_checksums.push((player, frame, checksum));
+            }
+            // Too old to be checked
+            None => (),
+        }
+    }
+
+    fn status(&self) -> String {
+        match self.phase {
+            Phase::Hosting => format!("Waiting for players {}/{}", self.peers.len(), self.players),
+            Phase::Joining { host, .. } => format!("Joining {host}"),
+            Phase::Running => {
+                let mut status = format!("Online as P{} of {}", self.local + 1, self.players);
+                if self.lost {
+                    status.push_str(" - connection lost");
+                }
+                if let Some(frame) = self.desync {
+                    status.push_str(&format!(" - out of sync since frame {frame}"));
+                }
+                status
+            }
+        }
+    }
+}
+
+/// Entity that is saved with every frame and brought back by rollbacks
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash)]
+struct Rollback(u32);
+
+type Saved = Box<dyn Any + Send + Sync>;
+type SaveFn = fn(&mut World) -> Saved;
+type LoadFn = fn(&mut World, &Saved, &HashMap<Rollback, Entity>);
+
+/// What every module asked to be rolled back
+#[derive(Resource, Default)]
+struct RollbackRegistry {
+    /// Finds the entities to roll back that are not tracked yet
+    roots: Vec<fn(&mut World) -> Vec<Entity>>,
+    saved: Vec<(SaveFn, LoadFn)>,
+    /// Hash every part of the checksums compared with the other peers
+    checked: Vec<fn(&mut World) -> u64>,
+}
+
+/// Rolled back state the peers compare to find out they went out of sync.
+/// Only what every peer computes the same way goes in.
+pub trait StateHash {
+    fn state_hash(&self, hasher: &mut Fnv);
+}
+
+/// Height is left out, it is only used to sort sprites
+impl StateHash for Transform {
+    fn state_hash(&self, hasher: &mut Fnv) {
+        let values = [
+            self.translation.x,
+            self.translation.y,
+            self.rotation.z,
+            self.rotation.w,
+        ];
+        values.map(f32::to_bits).hash(hasher);
+    }
+}
+
+/// Lets every module pick what goes back in time in online games
+pub trait RollbackApp {
+    /// Entities with `T` are saved and brought back, the components
+    /// registered below are the ones saved with them
+    fn rollback_entities<T: Component>(&mut self) -> &mut Self;
+    fn rollback_component<T: Component + Clone>(&mut self) -> &mut Self;
+    /// Only saves whether entities have `T`, given back as `T::default()`
+    fn rollback_marker<T: Component + Default>(&mut self) -> &mut Self;
+    fn rollback_resource<T: Resource + Clone>(&mut self) -> &mut Self;
+    /// Rolled back like `rollback_component`, and part of the checksums
+    fn rollback_checked_component<T: Component + Clone + StateHash>(&mut self) -> &mut Self;
+    fn rollback_checked_resource<T: Resource + Clone + StateHash>(&mut self) -> &mut Self;
+}
+
+impl RollbackApp for App {
+    fn rollback_entities<T: Component>(&mut self) -> &mut Self {
+        registry(self).roots.push(untracked::<T>);
+        self
+    }
+
+    fn rollback_component<T: Component + Clone>(&mut self) -> &mut Self {
+        registry(self)
+            .saved
+            .push((save_component::<T>, load_component::<T>));
+        self
+    }
+
+    fn rollback_marker<T: Component + Default>(&mut self) -> &mut Self {
+        registry(self)
+            .saved
+            .push((save_marker::<T>, load_marker::<T>));
+        self
+    }
+
+    fn rollback_resource<T: Resource + Clone>(&mut self) -> &mut Self {
+        registry(self)
+            .saved
+            .push((save_resource::<T>, load_resource::<T>));
+        self
+    }
+
+    fn rollback_checked_component<T: Component + Clone + StateHash>(&mut self) -> &mut Self {
+        registry(self).checked.push(hash_component::<T>);
+        self.rollback_component::<T>()
+    }
+
+    fn rollback_checked_resource<T: Resource + Clone + StateHash>(&mut self) -> &mut Self {
+        registry(self).checked.push(hash_resource::<T>);
+        self.rollback_resource::<T>()
+    }
+}
+
+fn registry(app: &mut App) -> Mut<'_, RollbackRegistry> {
+    app.world
+        .get_resource_or_insert_with(RollbackRegistry::default)
+}
+
+fn untracked<T: Component>(world: &mut World) -> Vec<Entity> {
+    world
+        .query_filtered::<Entity, (With<T>, Without<Rollback>)>()
+        .iter(world)
+        .collect()
+}
+
+fn save_component<T: Component + Clone>(world: &mut World) -> Saved {
+    let saved: HashMap<Rollback, T> = world
+        .query::<(&Rollback, &T)>()
+        .iter(world)
+        .map(|(id, value)| (*id, value.clone()))
+        .collect();
+    Box::new(saved)
+}
+
+fn load_component<T: Component + Clone>(
+    world: &mut World,
+    saved: &Saved,
+    entities: &HashMap<Rollback, Entity>,
+) {
+    let Some(saved) = saved.downcast_ref::<HashMap<Rollback, T>>() else {
+        return;
+    };
+    for (id, entity) in entities {
+        let mut entity = world.entity_mut(*entity);
+        match saved.get(id) {
+            Some(value) => {
+                entity.insert(value.clone());
+            }
+            None => {
+                entity.remove::<T>();
+            }
+        }
+    }
+}
+
+fn save_marker<T: Component>(world: &mut World) -> Saved {
+    let saved: HashSet<Rollback> = world
+        .query_filtered::<&Rollback, With<T>>()
+        .iter(world)
+        .copied()
+        .collect();
+    Box::new(saved)
+}
+
+fn load_marker<T: Component + Default>(
+    world: &mut World,
+    saved: &Saved,
+    entities: &HashMap<Rollback, Entity>,
+) {
+    let Some(saved) = saved.downcast_ref::<HashSet<Rollback>>() else {
+        return;
+    };
+    for (id, entity) in entities {
+        let mut entity = world.entity_mut(*entity);
+        // Markers already there are left alone, they may hold on to other entities
+        match (saved.contains(id), entity.contains::<T>()) {
+            (true, false) => {
+                entity.insert(T::default());
+            }
+            (false, true) => {
+                entity.remove::<T>();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Sum of the hashes of every rolled back `T`, in whatever order they are stored
+fn hash_component<T: Component + StateHash>(world: &mut World) -> u64 {
+    world
+        .query_filtered::<&T, With<Rollback>>()
+        .iter(world)
+        .map(|value| replay::checksum(|hasher| value.state_hash(hasher)))
+        .fold(0, u64::wrapping_add)
+}
+
+fn hash_resource<T: Resource + StateHash>(world: &mut World) -> u64 {
+    world.get_resource::<T>().map_or(0, |value| {
+        replay::checksum(|hasher| value.state_hash(hasher))
+    })
+}
+
+fn save_resource<T: Resource + Clone>(world: &mut World) -> Saved {
+    Box::new(world.get_resource::<T>().cloned())
+}
+
+fn load_resource<T: Resource + Clone>(
+    world: &mut World,
+    saved: &Saved,
+    _: &HashMap<Rollback, Entity>,
+) {
+    if let Some(Some(value)) = saved.downcast_ref::<Option<T>>() {
+        world.insert_resource(value.clone());
+    }
+}
+
+/// The world as a frame starts
+struct Snapshot {
+    frame: u32,
+    entities: Vec<Rollback>,
+    saved: Vec<Saved>,
+    state: GameState,
+    next_state: Option<GameState>,
+    time_speed: f32,
+    checksum: u64,
+}
+
+impl Snapshot {
+    fn take(world: &mut World, registry: &RollbackRegistry, frame: u32) -> Self {
+        Self {
+            frame,
+            entities: world.query::<&Rollback>().iter(world).copied().collect(),
+            saved: registry.saved.iter().map(|(save, _)| save(world)).collect(),
+            state: *world.resource::<State<GameState>>().get(),
+            next_state: world.resource::<NextState<GameState>>().0,
+            time_speed: world.resource::<Time<Virtual>>().relative_speed(),
+            checksum: replay::checksum(|hasher| {
+                for hash in &registry.checked {
+                    hash(world).hash(hasher);
+                }
+            }),
+        }
+    }
+
+    fn restore(&self, world: &mut World, registry: &RollbackRegistry) {
+        // Spawned during the last frame, so not tagged yet and newer than any snapshot
+        for root in &registry.roots {
+            for entity in root(world) {
+                world.entity_mut(entity).despawn_recursive();
+            }
+        }
+        let mut entities: HashMap<Rollback, Entity> = HashMap::new();
+        let live: Vec<(Entity, Rollback)> = world
+            .query::<(Entity, &Rollback)>()
+            .iter(world)
+            .map(|(entity, id)| (entity, *id))
+            .collect();
+        for (entity, id) in live {
+            if self.entities.contains(&id) {
+                entities.insert(id, entity);
+            } else {
+                world.entity_mut(entity).despawn_recursive();
+            }
+        }
+        for id in &self.entities {
+            entities
+                .entry(*id)
+                .or_insert_with(|| world.spawn((SpatialBundle::default(), *id)).id());
+        }
+        for ((_, load), saved) in registry.saved.iter().zip(&self.saved) {
+            load(world, saved, &entities);
+        }
+        if *world.resource::<State<GameState>>().get() != self.state {
+            world.insert_resource(State::new(self.state));
+        }
+        world.resource_mut::<NextState<GameState>>().0 = self.next_state;
+        world
+            .resource_mut::<Time<Virtual>>()
+            .set_relative_speed(self.time_speed);
+    }
+}
+
+/// Last frames, oldest first
+#[derive(Resource, Default)]
+struct Snapshots(VecDeque<Snapshot>);
+
+/// Schedules of the frame taken out while it waits for a peer
+#[derive(Resource, Default)]
+struct HeldFrame(Vec<Schedule>);
+
+impl Snapshots {
+    fn get(&self, frame: u32) -> Option<&Snapshot> {
+        self.0.iter().find(|snapshot| snapshot.frame == frame)
+    }
+
+    fn save(&mut self, snapshot: Snapshot) {
+        // Playing a frame again makes the ones after it out of date
+        while self
+            .0
+            .back()
+            .is_some_and(|last| last.frame >= snapshot.frame)
+        {
+            self.0.pop_back();
+        }
+        self.0.push_back(snapshot);
+        while self.0.len() > SNAPSHOTS {
+            self.0.pop_front();
+        }
+    }
+}
+
+/// Seed of the random numbers in `frame`, so a frame played again draws the
+/// same numbers
+pub fn frame_seed(seed: u64, frame: u32) -> u64 {
+    seed.wrapping_add(u64::from(frame).wrapping_mul(FRAME_SEED_STEP))
+}
+
+/// Keys and pads that can not be shared with the other peers do nothing online
+pub fn offline(session: Option<Res<NetSession>>) -> bool {
+    session.is_none()
+}
+
+/// Offline or with every player there
+pub fn ready(session: Option<Res<NetSession>>) -> bool {
+    session.is_none_or(|session| session.connected())
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.rollback_checked_component::<Transform>()
+            .rollback_component::<Visibility>();
+
+        // A config set up before the plugin wins over the command line
+        let config = app
+            .world
+            .remove_resource::<NetConfig>()
+            .unwrap_or_else(|| NetConfig::from_args(std::env::args().skip(1)));
+        if let Some(seed) = config.seed {
+            app.insert_resource(GlobalRng::with_seed(seed));
+        }
+        if config.role.is_none() {
+            return;
+        }
+        let session = match NetSession::new(&config) {
+            Ok(session) => session,
+            Err(error) => {
+                error!("can not go online: {error}");
+                return;
+            }
+        };
+
+        // Every peer has to run the systems in the same order and with the
+        // same time steps to get the same results
+        app.edit_schedule(Update, |schedule| {
+            schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+        });
+        app.insert_resource(session)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(TICK))
+            .init_resource::<Snapshots>()
+            .init_resource::<HeldFrame>()
+            .init_resource::<RollbackRegistry>()
+            .add_systems(PreUpdate, advance.after(InputSystem))
+            .add_systems(
+                Update,
+                (
+                    take_seat.before(InputSet),
+                    apply_inputs.in_set(InputSet).after(read_input),
+                    show_status,
+                ),
+            )
+            .add_systems(Last, end_frame);
+    }
+}
+
+/// Runs before every frame: reads the network, rolls back when a guess was
+/// wrong and saves the state the frame starts from
+fn advance(world: &mut World) {
+    let held = std::mem::take(&mut world.resource_mut::<HeldFrame>().0);
+    for schedule in held {
+        world.resource_mut::<Schedules>().insert(schedule);
+    }
+    let playing = *world.resource::<State<GameState>>().get() == GameState::Playing
+        || world.resource::<NextState<GameState>>().0 == Some(GameState::Playing);
+    let mut session = world.resource_mut::<NetSession>();
+    session.poll();
+    if !session.connected() {
+        return;
+    }
+    if !session.started {
+        let players = session.players;
+        if world.resource::<PlayerCount>().0 != players {
+            world.resource_mut::<PlayerCount>().0 = players;
+        }
+        let mut session = world.resource_mut::<NetSession>();
+        // Frames are counted from the start of the first run
+        if !playing {
+            return;
+        }
+        session.started = true;
+        session.next_tick = Instant::now();
+    }
+
+    let mut session = world.resource_mut::<NetSession>();
+    session.holding = session.waiting_for_peers();
+    if session.holding {
+        // Sleeping until the peer catches up would freeze the window, the
+        // frame is skipped instead and the next update polls again
+        let mut schedules = world.resource_mut::<Schedules>();
+        let held = [schedules.remove(StateTransition), schedules.remove(Update)];
+        world.resource_mut::<HeldFrame>().0 = held.into_iter().flatten().collect();
+        return;
+    }
+    session.keep_pace();
+    let frame = session.frame;
+    let rollback = session.rollback_to.take();
+    world.resource_scope(|world, mut snapshots: Mut<Snapshots>| {
+        if let Some(from) = rollback {
+            resimulate(world, &mut snapshots, from, frame);
+        }
+        start_frame(world, &mut snapshots, frame);
+        world.resource_mut::<NetSession>().check_sync(&snapshots);
+    });
+}
+
+/// Brings the world back to frame `from` and plays it again up to `to`,
+/// with the inputs known now
+fn resimulate(world: &mut World, snapshots: &mut Snapshots, from: u32, to: u32) {
+    let Some(snapshot) = snapshots.get(from) else {
+        warn!("can not roll back to frame {from}, it is too old");
+        return;
+    };
+    world.resource_scope(|world, registry: Mut<RollbackRegistry>| {
+        snapshot.restore(world, &registry);
+    });
+    world.resource_mut::<NetSession>().resimulating = true;
+    for frame in from..to {
+        world.run_schedule(First);
+        start_frame(world, snapshots, frame);
+        world.run_schedule(StateTransition);
+        world.run_schedule(Update);
+    }
+    world.resource_mut::<NetSession>().resimulating = false;
+    // Time and events move on to the frame about to run
+    world.run_schedule(First);
+}
+
+/// Seeds the random numbers of `frame` and saves the state it starts from
+fn start_frame(world: &mut World, snapshots: &mut Snapshots, frame: u32) {
+    let mut session = world.resource_mut::<NetSession>();
+    session.frame = frame;
+    let seed = frame_seed(session.seed, frame);
+    world.insert_resource(GlobalRng::with_seed(seed));
+
+    world.resource_scope(|world, registry: Mut<RollbackRegistry>| {
+        for root in &registry.roots {
+            for entity in root(world) {
+                let mut session = world.resource_mut::<NetSession>();
+                let id = Rollback(session.next_rollback_id);
+                session.next_rollback_id += 1;
+                world.entity_mut(entity).insert(id);
+            }
+        }
+        snapshots.save(Snapshot::take(world, &registry, frame));
+    });
+}
+
+/// The local car listens to this keyboard and the camera follows it
+fn take_seat(
+    mut commands: Commands,
+    session: Res<NetSession>,
+    query: Query<(Entity, &PlayerId, Option<&Controls>, Has<CameraFocus>)>,
+) {
+    // Whatever seat it has, the local car uses the first keyboard half
+    for (entity, id, controls, focused) in &query {
+        let local = id.0 == session.local;
+        match (local, controls) {
+            (true, Some(Controls(0))) | (false, None) => {}
+            (true, _) => {
+                commands.entity(entity).insert(Controls(0));
+            }
+            (false, Some(_)) => {
+                commands.entity(entity).remove::<Controls>();
+            }
+        }
+        if local && !focused {
+            commands.entity(entity).insert(CameraFocus);
+        } else if !local && focused {
+            commands.entity(entity).remove::<CameraFocus>();
+        }
+    }
+}
+
+/// Keeps the local input for later frames and drives every car with the
+/// input of the current one, guessed when it did not arrive yet
+fn apply_inputs(
+    mut session: ResMut<NetSession>,
+    keys: Res<Input<KeyCode>>,
+    mut query: Query<(&PlayerId, &mut CarInput)>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !session.started {
+        return;
+    }
+    let session = &mut *session;
+    if !session.resimulating {
+        let local = query
+            .iter()
+            .find(|(id, _)| id.0 == session.local)
+            .map(|(_, input)| *input)
+            .unwrap_or_default();
+        session.inputs[session.local]
+            .known
+            .push(NetInput::new(local, keys.just_pressed(KeyCode::R)));
+    }
+
+    let frame = session.frame;
+    let inputs: Vec<NetInput> = session
+        .inputs
+        .iter_mut()
+        .map(|log| log.get(frame))
+        .collect();
+    for (id, mut input) in &mut query {
+        if let Some(net_input) = inputs.get(id.0) {
+            *input = net_input.car_input();
+        }
+    }
+    // Anyone can start the next run
+    if *state.get() == GameState::GameOver && inputs.iter().any(|input| input.restart) {
+        next_state.set(GameState::Playing);
+    }
+}
+
+fn end_frame(mut session: ResMut<NetSession>) {
+    if !session.started || session.holding {
+        return;
+    }
+    session.frame += 1;
+    session.send_inputs();
+}
+
+fn show_status(
+    session: Res<NetSession>,
+    mut shown: Local<String>,
+    mut new_status: EventWriter<NewNetStatus>,
+) {
+    let status = session.status();
+    if *shown != status {
+        new_status.send(NewNetStatus(status.clone()));
+        *shown = status;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ai::Rival, barrel, level::LevelRules};
+
+    const FRAMES: u32 = 240;
+
+    fn input(steer: i8) -> NetInput {
+        NetInput { steer, ..default() }
+    }
+
+    fn snapshot(frame: u32, checksum: u64) -> Snapshot {
+        Snapshot {
+            frame,
+            entities: Vec::new(),
+            saved: Vec::new(),
+            state: GameState::Playing,
+            next_state: None,
+            time_speed: 1.0,
+            checksum,
+        }
+    }
+
+    #[test]
+    fn inputs_after_a_gap_are_ignored() {
+        let mut log = InputLog::new();
+        assert_eq!(log.receive(INPUT_DELAY + 1, &[input(1), input(2)]), None);
+        assert_eq!(log.received(), INPUT_DELAY);
+    }
+
+    #[test]
+    fn inputs_received_twice_are_kept_once() {
+        let mut log = InputLog::new();
+        log.receive(INPUT_DELAY, &[input(1), input(2)]);
+        log.receive(INPUT_DELAY, &[input(1), input(2), input(3)]);
+        assert_eq!(log.received(), INPUT_DELAY + 3);
+        assert!(log.known[INPUT_DELAY as usize..] == [input(1), input(2), input(3)]);
+        // Older than anything still needed
+        assert_eq!(log.receive(0, &[input(5)]), None);
+        assert_eq!(log.received(), INPUT_DELAY + 3);
+    }
+
+    #[test]
+    fn earliest_wrong_guess_is_returned() {
+        let mut log = InputLog::new();
+        for frame in INPUT_DELAY..INPUT_DELAY + 4 {
+            assert!(log.get(frame) == NetInput::default());
+        }
+        let start = INPUT_DELAY;
+        let wrong = log.receive(start, &[input(0), input(1), input(0), input(2)]);
+        assert_eq!(wrong, Some(start + 1));
+        assert!(log.guesses.is_empty());
+        // Right guesses leave nothing to play again
+        assert!(log.get(start + 4) == input(2));
+        assert_eq!(log.receive(start + 4, &[input(2)]), None);
+    }
+
+    #[test]
+    fn snapshots_keep_the_last_frames() {
+        let mut snapshots = Snapshots::default();
+        for frame in 0..SNAPSHOTS as u32 + 5 {
+            snapshots.save(snapshot(frame, 0));
+        }
+        assert_eq!(snapshots.0.len(), SNAPSHOTS);
+        assert_eq!(snapshots.0.front().map(|s| s.frame), Some(5));
+        assert!(snapshots.get(4).is_none());
+    }
+
+    #[test]
+    fn saving_an_older_frame_drops_the_later_ones() {
+        let mut snapshots = Snapshots::default();
+        for frame in 0..10 {
+            snapshots.save(snapshot(frame, 0));
+        }
+        snapshots.save(snapshot(6, 1));
+        assert_eq!(snapshots.0.len(), 7);
+        assert_eq!(snapshots.get(6).map(|s| s.checksum), Some(1));
+        assert!(snapshots.get(7).is_none());
+    }
+
+    /// Input of `player` in `frame`, changing often so guesses go wrong
+    fn scripted(player: usize, frame: u32) -> NetInput {
+        NetInput {
+            steer: ((frame / 7 + player as u32) % 3) as i8 - 1,
+            drift: frame % (11 + player as u32) < 3,
+            ..default()
+        }
+    }
+
+    /// Stands in for a frame of the game, the world is a hash of the inputs
+    fn step(state: u64, inputs: &[NetInput]) -> u64 {
+        replay::checksum(|hasher| {
+            state.hash(hasher);
+            for input in inputs {
+                (input.steer, input.drift, input.boost, input.restart).hash(hasher);
+            }
+        })
+    }
+
+    fn frame_inputs(session: &mut NetSession, frame: u32) -> Vec<NetInput> {
+        session
+            .inputs
+            .iter_mut()
+            .map(|log| log.get(frame))
+            .collect()
+    }
+
+    /// What `advance` and the frame do, rolling the state back on wrong guesses
+    fn play(session: &mut NetSession, snapshots: &mut Snapshots, state: &mut u64) {
+        session.poll();
+        if !session.connected() {
+            return;
+        }
+        session.started = true;
+        if session.waiting_for_peers() {
+            return;
+        }
+        let frame = session.frame;
+        if let Some(from) = session.rollback_to.take() {
+            *state = snapshots.get(from).unwrap().checksum;
+            for frame in from..frame {
+                snapshots.save(snapshot(frame, *state));
+                *state = step(*state, &frame_inputs(session, frame));
+            }
+        }
+        snapshots.save(snapshot(frame, *state));
+        session.check_sync(snapshots);
+        if frame < FRAMES {
+            let local = scripted(session.local, frame);
+            session.inputs[session.local].known.push(local);
+            *state = step(*state, &frame_inputs(session, frame));
+            session.frame += 1;
+            session.send_inputs();
+        } else if session.last_sent.elapsed() > RESEND_INTERVAL {
+            session.send_inputs();
+        }
+    }
+
+    #[test]
+    fn peers_agree_over_a_lossy_link() {
+        let config = |role| NetConfig {
+            role: Some(role),
+            players: 2,
+            lag: Duration::from_millis(5),
+            loss: 0.2,
+            seed: Some(3),
+        };
+        let mut host = NetSession::new(&config(Role::Host { port: 0 })).unwrap();
+        let port = host.link.socket.local_addr().unwrap().port();
+        let host_address = SocketAddr::from(([127, 0, 0, 1], port));
+        let mut join = NetSession::new(&config(Role::Join { host: host_address })).unwrap();
+
+        let mut peers = [
+            (&mut host, Snapshots::default(), 0),
+            (&mut join, Snapshots::default(), 0),
+        ];
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while peers
+            .iter()
+            .any(|(session, ..)| session.confirmed() < FRAMES || session.checksums.is_empty())
+        {
+            assert!(Instant::now() < deadline, "peers did not finish");
+            for (session, snapshots, state) in &mut peers {
+                play(session, snapshots, state);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // Both match the frames played in order with every input known
+        let mut expected = BTreeMap::new();
+        let mut state = 0;
+        for frame in 0..FRAMES {
+            if frame % CHECKSUM_INTERVAL == 0 {
+                expected.insert(frame, state);
+            }
+            let inputs: Vec<NetInput> = (0..2)
+                .map(|player| {
+                    frame
+                        .checked_sub(INPUT_DELAY)
+                        .map_or_else(NetInput::default, |frame| scripted(player, frame))
+                })
+                .collect();
+            state = step(state, &inputs);
+        }
+        expected.insert(FRAMES, state);
+        for (session, ..) in &peers {
+            assert_eq!(session.desync, None);
+            assert!(!session.checksums.is_empty());
+            for (frame, checksum) in &session.checksums {
+                assert_eq!(expected.get(frame), Some(checksum), "frame {frame}");
+            }
+        }
+        assert_eq!(host.checksums, join.checksums);
+    }
+
+    /// Barrels placed at top difficulty by a peer sitting in `seat`, cars
+    /// seated the way `player::spawn_player` and `take_seat` leave them
+    fn barrel_spots(seat: usize) -> u64 {
+        let config = NetConfig {
+            role: Some(Role::Host { port: 0 }),
+            ..default()
+        };
+        let mut session = NetSession::new(&config).unwrap();
+        session.local = seat;
+        let mut app = App::new();
+        app.insert_resource(session).add_systems(Update, take_seat);
+        for player in 0..3 {
+            let position = Vec3::new(player as f32 * 120.0 - 120.0, 40.0, 0.0);
+            let mut car = app.world.spawn((
+                Transform::from_translation(position),
+                PlayerId(player),
+                Controls(player),
+            ));
+            if player == 0 {
+                car.insert(CameraFocus);
+            }
+        }
+        app.world
+            .spawn((Transform::from_xyz(0.0, -80.0, 0.0), Rival(0)));
+        app.update();
+
+        let cars = barrel::car_motions(
+            app.world
+                .query::<(Option<&PlayerId>, Option<&Rival>, &Transform)>()
+                .iter(&app.world)
+                .map(|(id, rival, transform)| {
+                    (id, rival, transform.translation.truncate(), Vec2::X * 200.0)
+                }),
+        );
+        let rules = LevelRules::default();
+        let mut global_rng = GlobalRng::with_seed(11);
+        replay::checksum(|hasher| {
+            for _ in 0..50 {
+                let spot = barrel::find_spawn_position(
+                    &mut global_rng,
+                    &rules,
+                    barrel::MAX_DIFICULTY,
+                    &cars,
+                    &[],
+                );
+                spot.map(|spot| spot.to_array().map(f32::to_bits))
+                    .hash(hasher);
+            }
+        })
+    }
+
+    #[test]
+    fn seats_do_not_change_barrel_spawns() {
+        let spots = barrel_spots(0);
+        for seat in 1..3 {
+            assert_eq!(barrel_spots(seat), spots, "seat {seat}");
+        }
+    }
+}
@@ -0,0 +1,218 @@
+#![allow(clippy::needless_pass_by_value, clippy::cast_possible_truncation)]
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collision::{Collider, Shape, Static},
+    shadow::CastShadow,
+    stacked_sprite::StackedSprite,
+    vox::{self, VoxModel, VoxModels},
+    TILE_SIZE,
+};
+
+const CONE_RADIUS: f32 = TILE_SIZE * 0.3;
+const CRATE_SIZE: f32 = TILE_SIZE;
+const WALL_THICKNESS: f32 = TILE_SIZE * 0.5;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ObstacleKind {
+    /// Light and round, the car bounces off it
+    Cone,
+    /// Heavy box that kills most of the speed
+    Crate,
+    /// Long straight barrier the car slides along
+    Wall,
+    /// Invisible border of walled arenas, see `edge::EdgeMode`
+    Edge,
+}
+
+impl ObstacleKind {
+    /// Share of the speed into the obstacle the car gets back as a bounce
+    pub fn restitution(self) -> f32 {
+        match self {
+            ObstacleKind::Cone => 1.2,
+            ObstacleKind::Crate => 0.3,
+            ObstacleKind::Wall => 0.0,
+            ObstacleKind::Edge => 0.8,
+        }
+    }
+
+    /// Share of the car speed lost on a head-on hit
+    pub fn speed_loss(self) -> f32 {
+        match self {
+            ObstacleKind::Cone => 0.1,
+            ObstacleKind::Crate => 0.6,
+            ObstacleKind::Wall => 0.3,
+            ObstacleKind::Edge => 0.2,
+        }
+    }
+}
+
+/// Part of an obstacle, walls are drawn with one block per tile but only the
+/// entity holding the `Collider` is hit
+#[derive(Component)]
+pub struct Obstacle {
+    pub kind: ObstacleKind,
+}
+
+/// Where an obstacle goes in a level
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ObstacleSpec {
+    Cone(Vec2),
+    Crate {
+        position: Vec2,
+        #[serde(default)]
+        yaw: f32,
+    },
+    Wall {
+        from: Vec2,
+        to: Vec2,
+    },
+}
+
+impl ObstacleSpec {
+    /// Distance from a point to the closest part of the obstacle
+    pub fn distance_to(&self, point: Vec2) -> f32 {
+        match *self {
+            ObstacleSpec::Cone(position) | ObstacleSpec::Crate { position, .. } => {
+                position.distance(point)
+            }
+            ObstacleSpec::Wall { from, to } => {
+                let along = to - from;
+                let t = ((point - from).dot(along) / along.length_squared().max(f32::EPSILON))
+                    .clamp(0.0, 1.0);
+                (from + along * t).distance(point)
+            }
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct ObstacleAssets {
+    cone: Handle<VoxModel>,
+    crate_box: Handle<VoxModel>,
+    wall: Handle<VoxModel>,
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, load_obstacles);
+    }
+}
+
+fn load_obstacles(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut vox_models: ResMut<VoxModels>,
+) {
+    commands.insert_resource(ObstacleAssets {
+        cone: vox_models.load(&asset_server, "cone.vox"),
+        crate_box: vox_models.load(&asset_server, "crate.vox"),
+        wall: vox_models.load(&asset_server, "wall.vox"),
+    });
+}
+
+pub fn spawn_obstacle(
+    commands: &mut Commands,
+    assets: &ObstacleAssets,
+    models: &Assets<VoxModel>,
+    spec: &ObstacleSpec,
+) {
+    match *spec {
+        ObstacleSpec::Cone(position) => {
+            commands.spawn((
+                SpatialBundle::from_transform(Transform::from_translation(position.extend(0.0))),
+                vox::stack(
+                    models,
+                    &assets.cone,
+                    StackedSprite {
+                        custom_size: Some(Vec2::splat(TILE_SIZE * 0.625)),
+                        ..default()
+                    },
+                ),
+                Collider {
+                    shape: Shape::Circle {
+                        radius: CONE_RADIUS,
+                    },
+                    yaw: 0.0,
+                },
+                Static,
+                CastShadow,
+                Obstacle {
+                    kind: ObstacleKind::Cone,
+                },
+            ));
+        }
+        ObstacleSpec::Crate { position, yaw } => {
+            commands.spawn((
+                SpatialBundle::from_transform(Transform::from_translation(position.extend(0.0))),
+                vox::stack(
+                    models,
+                    &assets.crate_box,
+                    StackedSprite {
+                        yaw,
+                        custom_size: Some(Vec2::splat(CRATE_SIZE)),
+                        ..default()
+                    },
+                ),
+                Collider {
+                    shape: Shape::Box {
+                        half_size: Vec2::splat(CRATE_SIZE * 0.5),
+                    },
+                    yaw,
+                },
+                Static,
+                CastShadow,
+                Obstacle {
+                    kind: ObstacleKind::Crate,
+                },
+            ));
+        }
+        ObstacleSpec::Wall { from, to } => {
+            let along = to - from;
+            let yaw = along.y.atan2(along.x);
+            // One collider for the whole wall so the car never snags between blocks
+            commands.spawn((
+                SpatialBundle::from_transform(Transform::from_translation(
+                    ((from + to) * 0.5).extend(0.0),
+                )),
+                Collider {
+                    shape: Shape::Box {
+                        half_size: Vec2::new(along.length() * 0.5, WALL_THICKNESS * 0.5),
+                    },
+                    yaw,
+                },
+                Static,
+                Obstacle {
+                    kind: ObstacleKind::Wall,
+                },
+            ));
+            let blocks = (along.length() / TILE_SIZE).round().max(1.0) as usize;
+            for block in 0..blocks {
+                let position = from + along * ((block as f32 + 0.5) / blocks as f32);
+                commands.spawn((
+                    SpatialBundle::from_transform(Transform::from_translation(
+                        position.extend(0.0),
+                    )),
+                    vox::stack(
+                        models,
+                        &assets.wall,
+                        StackedSprite {
+                            yaw,
+                            custom_size: Some(Vec2::new(
+                                along.length() / blocks as f32,
+                                WALL_THICKNESS,
+                            )),
+                            ..default()
+                        },
+                    ),
+                    CastShadow,
+                    Obstacle {
+                        kind: ObstacleKind::Wall,
+                    },
+                ));
+            }
+        }
+    }
+}
@@ -1,9 +1,13 @@
 #![allow(clippy::needless_pass_by_value, clippy::cast_precision_loss)]
 use bevy::prelude::*;
-use bevy_turborand::{DelegatedRng, GlobalRng};
+use bevy_turborand::{DelegatedRng, RngComponent};
 use interpolation::Ease;
 
-use crate::TILE_SIZE;
+use crate::{
+    config::{P8_DARK_GREY, P8_ORANGE, P8_YELLOW},
+    edge::Wrapping,
+    TILE_SIZE,
+};
 
 const PARTICLE_LIFE: f32 = 0.5;
 
@@ -13,12 +17,68 @@ struct ParticleAssets {
 }
 
 #[derive(Event)]
-pub struct SpawnEvent(pub Vec3);
+pub struct SpawnEvent {
+    pub position: Vec3,
+    pub effect: Effect,
+}
+
+#[derive(Clone, Copy)]
+pub enum Effect {
+    Fire,
+    Oil,
+    Sparkle,
+    /// Flames blown out behind a boosting car, towards `direction`
+    Exhaust {
+        direction: Vec2,
+    },
+}
 
 #[derive(Component)]
 struct Particle {
     timer: Timer,
     velosity: Vec3,
+    color: Color,
+}
+
+impl Effect {
+    fn color(self) -> Color {
+        match self {
+            Effect::Fire => Color::WHITE,
+            Effect::Oil => P8_DARK_GREY,
+            Effect::Sparkle => P8_YELLOW,
+            Effect::Exhaust { .. } => P8_ORANGE,
+        }
+    }
+
+    fn amount(self) -> std::ops::Range<usize> {
+        match self {
+            Effect::Fire | Effect::Oil => 20..30,
+            Effect::Sparkle => 10..15,
+            Effect::Exhaust { .. } => 2..4,
+        }
+    }
+
+    fn velocity(self, rng: &mut RngComponent) -> Vec3 {
+        match self {
+            Effect::Exhaust { direction } => {
+                let spread = direction.perp() * rng.f32_normalized() * 30.0;
+                (direction * (rng.f32() * 60.0 + 60.0) + spread).extend(0.0)
+            }
+            _ => {
+                let x_velosity = (rng.f32() - 0.5) * 300.0;
+                let y_velosity = rng.f32() * 100.0 + 150.0;
+                Vec3::new(x_velosity, y_velosity, 0.0)
+            }
+        }
+    }
+
+    /// Width of the area particles start in
+    fn spread(self) -> f32 {
+        match self {
+            Effect::Exhaust { .. } => TILE_SIZE * 0.25,
+            _ => TILE_SIZE,
+        }
+    }
 }
 
 pub struct Plug;
@@ -40,28 +100,35 @@ fn spawn_particle(
     mut commands: Commands,
     particle_assets: Res<ParticleAssets>,
     mut spawn_event: EventReader<SpawnEvent>,
-    mut global_rng: ResMut<GlobalRng>,
+    // Not the game random numbers, so online peers drawing different
+    // particles still play the same game
+    mut rng: Local<RngComponent>,
 ) {
-    for pos in spawn_event.read() {
-        for _i in 0..global_rng.usize(20..30) {
+    for event in spawn_event.read() {
+        for _i in 0..rng.usize(event.effect.amount()) {
+            let spread = event.effect.spread();
             let pos_offset = Vec3::new(
-                (global_rng.f32() - 0.5) * TILE_SIZE,
-                (global_rng.f32() - 0.5) * TILE_SIZE,
-                (global_rng.f32() - 0.5) * TILE_SIZE + 30.0,
+                (rng.f32() - 0.5) * spread,
+                (rng.f32() - 0.5) * spread,
+                (rng.f32() - 0.5) * spread + 30.0,
             );
-            let x_velosity = (global_rng.f32() - 0.5) * 300.0;
-            let y_velosity = global_rng.f32() * 100.0 + 150.0;
-            let velosity = Vec3::new(x_velosity, y_velosity, 0.0);
+            let velosity = event.effect.velocity(&mut rng);
             commands.spawn((
                 SpriteBundle {
                     texture: particle_assets.texture.clone(),
-                    transform: Transform::from_translation(pos.0 + pos_offset),
+                    transform: Transform::from_translation(event.position + pos_offset),
+                    sprite: Sprite {
+                        color: event.effect.color(),
+                        ..default()
+                    },
                     ..Default::default()
                 },
                 Particle {
                     timer: Timer::from_seconds(PARTICLE_LIFE, TimerMode::Once),
                     velosity,
+                    color: event.effect.color(),
                 },
+                Wrapping::default(),
             ));
         }
     }
@@ -79,13 +146,7 @@ fn update_particle(
             commands.entity(entity).despawn_recursive();
         } else {
             let percent = properties.timer.percent().exponential_in();
-            let new_color = Color::Rgba {
-                red: 1.0,
-                green: 1.0,
-                blue: 1.0,
-                alpha: 1.0 - percent,
-            };
-            sprite.color = new_color;
+            sprite.color = properties.color.with_a(1.0 - percent);
             pos.translation += properties.velosity * time.delta_seconds();
         }
     }
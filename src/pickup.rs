@@ -0,0 +1,418 @@
+#![allow(
+    clippy::needless_pass_by_value,
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::type_complexity
+)]
+use bevy::{
+    prelude::*,
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+    utils::HashMap,
+};
+use bevy_turborand::prelude::*;
+
+use crate::{
+    barrel::DefuseEvent,
+    config::{P8_LIGHT_BLUE, P8_ORANGE, P8_PINK, P8_RED, P8_YELLOW, WINDOW_HEIGHT},
+    drop::DropIn,
+    health::Shield,
+    level::LevelRules,
+    net::RollbackApp,
+    particle,
+    player::{Movement, Player, PlayerId, ScoreMultiplier},
+    shadow::CastShadow,
+    stacked_sprite::{spawn_slices, StackedSprite},
+    ui::NewPowerUps,
+    vox::{self, VoxModel, VoxModels},
+    GameState, TILE_SIZE,
+};
+
+const SPAWN_MIN_DELAY: f32 = 6.0;
+const SPAWN_MAX_DELAY: f32 = 12.0;
+const SPAWN_ANIMATION_DURATION: f32 = 1.5;
+const MAX_PICKUPS: usize = 2;
+/// Seconds a pickup waits on the ground before vanishing
+const PICKUP_LIFETIME: f32 = 10.0;
+/// Pickups blink during the last seconds of their life
+const PICKUP_BLINK_TIME: f32 = 2.0;
+const PICKUP_BLINK_RATE: f32 = 8.0;
+/// Turns per second of a pickup on the ground
+const PICKUP_SPIN: f32 = 2.0;
+const PICKUP_RADIUS: f32 = TILE_SIZE;
+
+const SCORE_MULTIPLIER: usize = 2;
+const SLOW_MOTION_SPEED: f32 = 0.5;
+const NITRO_BOOST: f32 = 1.4;
+const DEFUSE_RADIUS: f32 = TILE_SIZE * 3.0;
+const SHIELD_RADIUS: f32 = TILE_SIZE;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PowerUp {
+    /// Nothing can hurt the car
+    Shield,
+    /// Every point counts double
+    Multiplier,
+    /// The whole game runs at half speed
+    SlowMotion,
+    /// Raises the top speed of the car
+    Nitro,
+    /// Barrels close to the car are taken away before they go off
+    Defuse,
+}
+
+impl PowerUp {
+    const ALL: [PowerUp; 5] = [
+        PowerUp::Shield,
+        PowerUp::Multiplier,
+        PowerUp::SlowMotion,
+        PowerUp::Nitro,
+        PowerUp::Defuse,
+    ];
+
+    /// Seconds the power-up lasts, in real time so slow motion does not stretch it
+    fn duration(self) -> f32 {
+        match self {
+            PowerUp::Shield => 6.0,
+            PowerUp::Multiplier => 10.0,
+            PowerUp::SlowMotion => 5.0,
+            PowerUp::Nitro => 4.0,
+            PowerUp::Defuse => 3.0,
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            PowerUp::Shield => P8_LIGHT_BLUE,
+            PowerUp::Multiplier => P8_YELLOW,
+            PowerUp::SlowMotion => P8_PINK,
+            PowerUp::Nitro => P8_ORANGE,
+            PowerUp::Defuse => P8_RED.with_a(1.0),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            PowerUp::Shield => "Shield",
+            PowerUp::Multiplier => "x2",
+            PowerUp::SlowMotion => "Slow-mo",
+            PowerUp::Nitro => "Nitro",
+            PowerUp::Defuse => "Defuse",
+        }
+    }
+}
+
+/// Sent when a car grabs a pickup
+#[derive(Event)]
+pub struct PickedUpEvent {
+    pub car: Entity,
+}
+
+#[derive(Component, Clone)]
+pub struct Pickup {
+    kind: PowerUp,
+    time: Timer,
+}
+
+/// Bubble drawn around the car while the shield is up
+#[derive(Component)]
+struct ShieldBubble;
+
+#[derive(Resource)]
+struct PickupAssets {
+    model: Handle<VoxModel>,
+    circle_mesh: Mesh2dHandle,
+    markers: HashMap<PowerUp, Handle<ColorMaterial>>,
+    bubble_material: Handle<ColorMaterial>,
+}
+
+#[derive(Resource, Clone)]
+struct PickupManager {
+    spawn_time: Timer,
+}
+
+/// Time left on every power-up running on a car
+#[derive(Component, Default, Clone)]
+pub struct ActivePowerUps(HashMap<PowerUp, Timer>);
+
+/// Cars grab pickups and power-ups take effect in this set
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PickupSet;
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PickedUpEvent>()
+            .insert_resource(PickupManager {
+                spawn_time: Timer::from_seconds(SPAWN_MAX_DELAY, TimerMode::Once),
+            })
+            .rollback_entities::<Pickup>()
+            .rollback_component::<Pickup>()
+            .rollback_component::<ActivePowerUps>()
+            .rollback_resource::<PickupManager>()
+            .add_systems(Startup, load_pickups)
+            .add_systems(
+                Update,
+                (
+                    spawn_pickups,
+                    update_pickups,
+                    collect_pickups,
+                    update_power_ups,
+                )
+                    .chain()
+                    .in_set(PickupSet)
+                    .after(spawn_slices)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(OnExit(GameState::GameOver), clear_pickups);
+    }
+}
+
+fn load_pickups(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut vox_models: ResMut<VoxModels>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let markers = PowerUp::ALL
+        .iter()
+        .map(|kind| (*kind, materials.add(kind.color().with_a(0.5).into())))
+        .collect();
+    commands.insert_resource(PickupAssets {
+        model: vox_models.load(&asset_server, "pickup.vox"),
+        circle_mesh: meshes
+            .add(shape::Circle::new(TILE_SIZE * 0.5).into())
+            .into(),
+        markers,
+        bubble_material: materials.add(PowerUp::Shield.color().with_a(0.4).into()),
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_pickups(
+    mut commands: Commands,
+    mut manager: ResMut<PickupManager>,
+    pickup_assets: Res<PickupAssets>,
+    models: Res<Assets<VoxModel>>,
+    pickup_query: Query<(), With<Pickup>>,
+    rules: Res<LevelRules>,
+    mut global_rng: ResMut<GlobalRng>,
+    time: Res<Time>,
+) {
+    manager.spawn_time.tick(time.delta());
+    if !manager.spawn_time.finished() {
+        return;
+    }
+    manager.spawn_time = Timer::from_seconds(
+        SPAWN_MIN_DELAY + global_rng.f32() * (SPAWN_MAX_DELAY - SPAWN_MIN_DELAY),
+        TimerMode::Once,
+    );
+    if pickup_query.iter().count() >= MAX_PICKUPS {
+        return;
+    }
+
+    let position = rules.random_spawn_point(&mut global_rng);
+    let kind = *global_rng.sample(&PowerUp::ALL).unwrap();
+    commands.spawn((
+        SpatialBundle::from_transform(Transform::from_translation(position.extend(0.0))),
+        vox::stack(
+            &models,
+            &pickup_assets.model,
+            StackedSprite {
+                elevation: WINDOW_HEIGHT,
+                custom_size: Some(Vec2::splat(TILE_SIZE * 0.75)),
+                color: kind.color(),
+                ..default()
+            },
+        ),
+        DropIn::new(
+            SPAWN_ANIMATION_DURATION,
+            pickup_assets.markers[&kind].clone(),
+        ),
+        CastShadow,
+        Pickup {
+            kind,
+            time: Timer::from_seconds(PICKUP_LIFETIME, TimerMode::Once),
+        },
+    ));
+}
+
+fn update_pickups(
+    mut commands: Commands,
+    mut pickup_query: Query<
+        (&mut Pickup, &mut StackedSprite, &mut Visibility, Entity),
+        Without<DropIn>,
+    >,
+    time: Res<Time>,
+) {
+    for (mut pickup, mut stack, mut visibility, entity) in &mut pickup_query {
+        pickup.time.tick(time.delta());
+        if pickup.time.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        stack.yaw += PICKUP_SPIN * time.delta_seconds();
+        let left = pickup.time.remaining_secs();
+        let blink = (left * PICKUP_BLINK_RATE) as u32 % 2 == 1;
+        *visibility = if left < PICKUP_BLINK_TIME && blink {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+    }
+}
+
+fn collect_pickups(
+    mut commands: Commands,
+    pickup_query: Query<(&Pickup, &Transform, Entity), Without<DropIn>>,
+    mut player_query: Query<(Entity, &Transform, &mut ActivePowerUps), With<Player>>,
+    mut spawn_event: EventWriter<particle::SpawnEvent>,
+    mut picked_up_event: EventWriter<PickedUpEvent>,
+) {
+    for (pickup, pos, entity) in &pickup_query {
+        let position = pos.translation.truncate();
+        // The closest car in reach gets it
+        let Some((car, _, mut active)) = player_query
+            .iter_mut()
+            .filter(|(_, player, _)| {
+                player.translation.truncate().distance(position) < PICKUP_RADIUS
+            })
+            .min_by(|(_, a, _), (_, b, _)| {
+                let a = a.translation.truncate().distance(position);
+                let b = b.translation.truncate().distance(position);
+                a.total_cmp(&b)
+            })
+        else {
+            continue;
+        };
+        commands.entity(entity).despawn_recursive();
+        spawn_event.send(particle::SpawnEvent {
+            position: pos.translation,
+            effect: particle::Effect::Sparkle,
+        });
+        picked_up_event.send(PickedUpEvent { car });
+        // Picking the same power-up again starts it over
+        active.0.insert(
+            pickup.kind,
+            Timer::from_seconds(pickup.kind.duration(), TimerMode::Once),
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_power_ups(
+    mut commands: Commands,
+    mut player_query: Query<
+        (
+            &Transform,
+            &mut Movement,
+            &mut ScoreMultiplier,
+            &mut ActivePowerUps,
+            Option<&PlayerId>,
+            Has<Shield>,
+            Entity,
+        ),
+        With<Player>,
+    >,
+    bubble_query: Query<(Entity, &Parent), With<ShieldBubble>>,
+    pickup_assets: Res<PickupAssets>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    real_time: Res<Time<Real>>,
+    mut defuse_event: EventWriter<DefuseEvent>,
+    mut new_power_ups: EventWriter<NewPowerUps>,
+) {
+    let mut slow_motion = false;
+    for (transform, mut movement, mut multiplier, mut active, id, shielded, player) in
+        &mut player_query
+    {
+        for timer in active.0.values_mut() {
+            timer.tick(real_time.delta());
+        }
+        active.0.retain(|_, timer| !timer.finished());
+        let is_active = |kind| active.0.contains_key(&kind);
+
+        if is_active(PowerUp::Shield) && !shielded {
+            commands.entity(player).insert(Shield).with_children(|car| {
+                car.spawn((
+                    MaterialMesh2dBundle {
+                        mesh: pickup_assets.circle_mesh.clone(),
+                        material: pickup_assets.bubble_material.clone(),
+                        transform: Transform::from_xyz(0.0, 0.0, -0.003)
+                            .with_scale(Vec3::splat(SHIELD_RADIUS / (TILE_SIZE * 0.5))),
+                        ..default()
+                    },
+                    ShieldBubble,
+                ));
+            });
+        } else if !is_active(PowerUp::Shield) && shielded {
+            commands.entity(player).remove::<Shield>();
+            for (bubble, parent) in &bubble_query {
+                if parent.get() == player {
+                    commands.entity(bubble).despawn_recursive();
+                }
+            }
+        }
+        multiplier.0 = if is_active(PowerUp::Multiplier) {
+            SCORE_MULTIPLIER
+        } else {
+            1
+        };
+        if is_active(PowerUp::Nitro) {
+            movement.boost(NITRO_BOOST);
+        }
+        if is_active(PowerUp::Defuse) {
+            defuse_event.send(DefuseEvent {
+                position: transform.translation,
+                radius: DEFUSE_RADIUS,
+            });
+        }
+        // Slow motion is picked by one car but slows down everyone
+        slow_motion |= is_active(PowerUp::SlowMotion);
+
+        let Some(id) = id else {
+            continue;
+        };
+        let mut power_ups: Vec<(PowerUp, f32)> = active
+            .0
+            .iter()
+            .map(|(kind, timer)| (*kind, timer.remaining_secs()))
+            .collect();
+        power_ups.sort_by(|a, b| a.1.total_cmp(&b.1));
+        new_power_ups.send(NewPowerUps {
+            player: id.0,
+            power_ups: power_ups
+                .into_iter()
+                .map(|(kind, left)| (kind.name(), left))
+                .collect(),
+        });
+    }
+    let speed = if slow_motion { SLOW_MOTION_SPEED } else { 1.0 };
+    if virtual_time.relative_speed() != speed {
+        virtual_time.set_relative_speed(speed);
+    }
+}
+
+fn clear_pickups(
+    mut commands: Commands,
+    pickup_query: Query<Entity, Or<(With<Pickup>, With<ShieldBubble>)>>,
+    mut player_query: Query<(Entity, &mut ActivePowerUps, Option<&PlayerId>), With<Player>>,
+    mut manager: ResMut<PickupManager>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    mut new_power_ups: EventWriter<NewPowerUps>,
+) {
+    for entity in &pickup_query {
+        commands.entity(entity).despawn_recursive();
+    }
+    for (player, mut active, id) in &mut player_query {
+        commands.entity(player).remove::<Shield>();
+        active.0.clear();
+        if let Some(id) = id {
+            new_power_ups.send(NewPowerUps {
+                player: id.0,
+                power_ups: Vec::new(),
+            });
+        }
+    }
+    manager.spawn_time = Timer::from_seconds(SPAWN_MAX_DELAY, TimerMode::Once);
+    virtual_time.set_relative_speed(1.0);
+}
@@ -1,41 +1,255 @@
-#![allow(clippy::needless_pass_by_value, clippy::cast_precision_loss)]
-use bevy::prelude::*;
+#![allow(
+    clippy::needless_pass_by_value,
+    clippy::cast_precision_loss,
+    clippy::type_complexity
+)]
+use std::hash::Hash;
+
+use bevy::{ecs::system::EntityCommands, prelude::*};
 
 use crate::{
-    config::{WINDOW_HEIGHT, WINDOW_WIDTH},
-    ui::NewScore,
-    TILE_SIZE,
+    ai::Rival,
+    barrel::{BarrelExplodedEvent, BarrelSet, OilSlick},
+    camera::CameraFocus,
+    collision::{Collider, CollisionEvent, CollisionSet, Shape},
+    config::{P8_LIGHT_BLUE, P8_LIGHT_GREY, P8_YELLOW},
+    edge::Wrapping,
+    health::{knock_out, Health, HurtEvent},
+    input::{CarInput, Controls, InputSet, KeyBindings},
+    level::LevelRules,
+    mode::GameMode,
+    net::{self, RollbackApp, StateHash},
+    obstacle::Obstacle,
+    particle,
+    pickup::ActivePowerUps,
+    replay::Fnv,
+    shadow::CastShadow,
+    stacked_sprite::StackedSprite,
+    ui::{NewBoost, NewCombo, NewPlayerCount, NewScore},
+    GameState, TILE_SIZE,
 };
 
 const CAR_SIZE: f32 = TILE_SIZE * 1.5;
+/// Frames of the `car.png` strip
+const CAR_SLICES: usize = 12;
+/// Footprint of the car body inside its sprite, length by width
+const CAR_BODY: Vec2 = Vec2::new(CAR_SIZE * 0.8, CAR_SIZE * 0.45);
+const OIL_GRIP: f32 = 0.05;
+/// Blasts push the car up to this many blast radii away from their center
+const KNOCKBACK_REACH: f32 = 3.0;
+/// How fast knockback speed and spin die out, per second
+const KNOCKBACK_DAMPING: f32 = 4.0;
+/// Bonuses in a row needed to raise the combo multiplier by one
+const COMBO_STEP: usize = 3;
+/// Share of the boost meter filled by every second of drifting
+const BOOST_FILL_RATE: f32 = 0.25;
+/// Share of the boost meter spent by every second of boosting
+const BOOST_DRAIN_RATE: f32 = 0.5;
+/// Drifting slower than this does not charge the meter
+const BOOST_MIN_DRIFT_SPEED: f32 = 100.0;
+const BOOST_MULTIPLIER: f32 = 1.3;
+const EXHAUST_INTERVAL: f32 = 0.05;
+/// Most cars sharing the screen
+pub const MAX_PLAYERS: usize = 4;
+/// Tint of every player's car
+const CAR_COLORS: [Color; MAX_PLAYERS] = [Color::WHITE, P8_LIGHT_BLUE, P8_YELLOW, P8_LIGHT_GREY];
+/// Gap between the cars lined up at the start
+const START_SPACING: f32 = CAR_SIZE;
+/// Share of the closing speed two cars get back as a bounce when they bump
+const BUMP_RESTITUTION: f32 = 0.8;
+/// How hard it is to spin a car around compared to pushing it, a flat box
+/// of the car body with the weight in the wheels
+const CAR_INERTIA: f32 = (CAR_BODY.x * CAR_BODY.x + CAR_BODY.y * CAR_BODY.y) / 12.0 * 2.0;
+
+#[derive(Component, Default)]
+pub struct Player;
 
-#[derive(Component)]
-struct Player;
+/// Which player drives the car, starting at 0
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerId(pub usize);
+
+/// Key putting the cars in the same order on every peer, players by seat
+/// and then rivals in the order they were spawned. Queries go through cars
+/// in an order that depends on their components, and only the local car has
+/// `Controls` and `CameraFocus`.
+pub fn car_order(id: Option<&PlayerId>, rival: Option<&Rival>) -> (Option<usize>, Option<usize>) {
+    (id.map(|id| id.0), rival.map(|rival| rival.0))
+}
+
+/// Cars on the screen, changed on the game over screen or by the number
+/// of players in an online game
+#[derive(Resource)]
+pub struct PlayerCount(pub usize);
+
+impl Default for PlayerCount {
+    fn default() -> Self {
+        Self(1)
+    }
+}
 
-#[derive(Component)]
-struct PlayerSprite;
+#[derive(Resource)]
+pub struct CarAssets {
+    atlas: Handle<TextureAtlas>,
+}
 
-#[derive(Component)]
-struct Movement {
+#[derive(Component, Clone)]
+pub struct Movement {
     top_aceleration: f32,
     acceleration: f32,
     acceleration_rate: f32,
     drag: f32,
+    grip: f32,
     angle: f32,
-    velocity: Vec3,
+    pub velocity: Vec3,
+    /// Push from explosions and hits, on top of the driving velocity. Kept
+    /// apart from `velocity` so knockback is not capped by the top speed or
+    /// steered by the grip, it dies out on its own
+    impulse: Vec3,
+    /// Turn speed from explosions and hits, dies out with `impulse`
+    spin: f32,
+    /// Top speed multiplier for the current frame, see `Movement::boost`
+    boost: f32,
+    /// Whether the drift key was held on the last move
+    pub drifting: bool,
+}
+
+impl Movement {
+    /// Raise the top speed for the next frame, the strongest boost wins
+    pub fn boost(&mut self, multiplier: f32) {
+        self.boost = self.boost.max(multiplier);
+    }
+
+    /// Angle the car is facing
+    pub fn heading(&self) -> f32 {
+        self.angle
+    }
+
+    /// Speed of a point of the car `arm` away from its center, spin included
+    fn point_velocity(&self, arm: Vec2) -> Vec2 {
+        (self.velocity + self.impulse).truncate() + arm.perp() * self.spin
+    }
+
+    /// Stand still facing `angle`, dropping every push
+    pub fn stop(&mut self, angle: f32) {
+        self.acceleration = 0.0;
+        self.angle = angle;
+        self.velocity = Vec3::ZERO;
+        self.impulse = Vec3::ZERO;
+        self.spin = 0.0;
+        self.boost = 1.0;
+        self.drifting = false;
+    }
 }
 
-#[derive(Component)]
-struct ScoreManager {
+/// Charge between 0 and 1 earned by drifting, spent with the boost key
+#[derive(Component, Clone)]
+pub struct BoostMeter {
+    charge: f32,
+    exhaust: Timer,
+}
+
+/// Multiplies every point the car scores
+#[derive(Component, Clone)]
+pub struct ScoreMultiplier(pub usize);
+
+#[derive(Component, Clone)]
+pub struct ScoreManager {
     score: usize,
     timer: Timer,
+    /// Bonuses scored in a row without getting hurt
+    combo: usize,
+}
+
+impl ScoreManager {
+    pub fn score(&self) -> usize {
+        self.score
+    }
+}
+
+impl StateHash for ScoreManager {
+    fn state_hash(&self, hasher: &mut Fnv) {
+        (self.score, self.combo).hash(hasher);
+        self.timer.elapsed().hash(hasher);
+    }
+}
+
+impl StateHash for BoostMeter {
+    fn state_hash(&self, hasher: &mut Fnv) {
+        self.charge.to_bits().hash(hasher);
+    }
+}
+
+/// Scores are counted in this set, every system handing out points or hurting
+/// cars goes before it.
+///
+/// Events are read in the frame they are sent, a rolled back frame in an
+/// online game would lose or repeat the ones left for the next frame.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScoreSet;
+
+/// Points earned on top of the survival score
+#[derive(Event)]
+pub struct BonusScore {
+    /// Car that earned them, `None` gives them to every car
+    pub car: Option<Entity>,
+    pub points: usize,
+}
+
+/// Where a car lines up at the start, side by side across the level heading
+pub fn start_position(rules: &LevelRules, player: usize, players: usize) -> Vec2 {
+    let across = Vec2::from_angle(rules.player_angle).perp();
+    let slot = player as f32 - (players.max(1) - 1) as f32 * 0.5;
+    rules.player_start + across * slot * START_SPACING
 }
 
 pub struct Plug;
 impl Plugin for Plug {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, set_up_player)
-            .add_systems(Update, (rotate_player, move_player, update_score));
+        app.add_event::<BonusScore>()
+            .init_resource::<PlayerCount>()
+            .rollback_entities::<Player>()
+            .rollback_marker::<Player>()
+            .rollback_component::<PlayerId>()
+            .rollback_component::<Movement>()
+            .rollback_checked_component::<BoostMeter>()
+            .rollback_component::<ScoreMultiplier>()
+            .rollback_checked_component::<ScoreManager>()
+            .add_systems(Startup, set_up_player)
+            .add_systems(
+                Update,
+                spawn_players
+                    .before(InputSet)
+                    .run_if(resource_changed::<PlayerCount>()),
+            )
+            .add_systems(
+                Update,
+                (
+                    (update_grip, move_player, rotate_player)
+                        .chain()
+                        .after(InputSet)
+                        .before(CollisionSet),
+                    // The boost raises the top speed of this frame's move
+                    use_boost
+                        .after(InputSet)
+                        .after(update_grip)
+                        .before(move_player),
+                    (hit_obstacles, bump_cars).chain().after(CollisionSet),
+                    apply_knockback.after(BarrelSet),
+                    (reset_combo, update_score)
+                        .chain()
+                        .in_set(ScoreSet)
+                        .after(knock_out)
+                        .after(BarrelSet),
+                )
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Update,
+                switch_player_count
+                    .run_if(in_state(GameState::GameOver))
+                    .run_if(net::offline),
+            )
+            .add_systems(OnEnter(GameState::Playing), reset_player);
     }
 }
 
@@ -45,121 +259,408 @@ fn set_up_player(
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
 ) {
     let texture_handle = asset_server.load("car.png");
-    let texture_atlas =
-        TextureAtlas::from_grid(texture_handle, Vec2::splat(TILE_SIZE), 12, 1, None, None);
-    let texture_atlas_handle = texture_atlases.add(texture_atlas);
-    commands
-        .spawn((
-            SpatialBundle::from_transform(Transform::from_xyz(0.0, 0.0, 0.0)),
-            Movement {
-                top_aceleration: 950.0,
-                acceleration: 0.0,
-                acceleration_rate: 250.0,
-                drag: 0.5,
-                angle: 0.0,
-                velocity: Vec3::new(0.0, 0.0, 0.0),
-            },
-            ScoreManager {
-                score: 0,
-                timer: Timer::from_seconds(1.0, TimerMode::Repeating),
+    let texture_atlas = TextureAtlas::from_grid(
+        texture_handle,
+        Vec2::splat(TILE_SIZE),
+        CAR_SLICES,
+        1,
+        None,
+        None,
+    );
+    commands.insert_resource(CarAssets {
+        atlas: texture_atlases.add(texture_atlas),
+    });
+}
+
+/// Spawns a car with nobody at the wheel, see `input::CarInput`
+pub fn spawn_car<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    assets: &CarAssets,
+    color: Color,
+) -> EntityCommands<'w, 's, 'a> {
+    commands.spawn((
+        SpatialBundle::from_transform(Transform::from_xyz(0.0, 0.0, 0.0)),
+        car_sprite(assets, color),
+        Movement {
+            top_aceleration: 950.0,
+            acceleration: 0.0,
+            acceleration_rate: 250.0,
+            drag: 0.5,
+            grip: 1.0,
+            angle: 0.0,
+            velocity: Vec3::new(0.0, 0.0, 0.0),
+            impulse: Vec3::ZERO,
+            spin: 0.0,
+            boost: 1.0,
+            drifting: false,
+        },
+        BoostMeter {
+            charge: 0.0,
+            exhaust: Timer::from_seconds(EXHAUST_INTERVAL, TimerMode::Repeating),
+        },
+        ScoreMultiplier(1),
+        ActivePowerUps::default(),
+        Collider {
+            shape: Shape::Box {
+                half_size: CAR_BODY * 0.5,
             },
-            Player,
-        ))
-        .with_children(|player| {
-            for i in 0..12 {
-                player.spawn((
-                    SpriteSheetBundle {
-                        texture_atlas: texture_atlas_handle.clone(),
-                        sprite: TextureAtlasSprite {
-                            index: i,
-                            flip_x: true,
-                            custom_size: Some(Vec2::splat(CAR_SIZE)),
-                            ..default()
-                        },
-                        transform: Transform::from_xyz(0.0, 1.0 * i as f32, i as f32),
-                        ..default()
-                    },
-                    PlayerSprite,
-                ));
-            }
-        });
+            yaw: 0.0,
+        },
+        Health::default(),
+        Wrapping::default(),
+        CastShadow,
+        CarInput::default(),
+        Player,
+    ))
 }
 
-fn rotate_player(
-    player_query: Query<(&Children, &Movement), With<Player>>,
-    mut sprites_query: Query<&mut Transform, With<PlayerSprite>>,
+/// Look of a car, for the ones only drawn
+pub fn car_sprite(assets: &CarAssets, color: Color) -> StackedSprite {
+    StackedSprite {
+        atlas: assets.atlas.clone(),
+        slices: CAR_SLICES,
+        custom_size: Some(Vec2::splat(CAR_SIZE)),
+        flip_x: true,
+        color,
+        ..default()
+    }
+}
+
+fn spawn_player(commands: &mut Commands, assets: &CarAssets, player: usize) {
+    let mut car = spawn_car(commands, assets, CAR_COLORS[player]);
+    car.insert((
+        ScoreManager {
+            score: 0,
+            timer: Timer::from_seconds(1.0, TimerMode::Repeating),
+            combo: 0,
+        },
+        PlayerId(player),
+        Controls(player),
+    ));
+    // The camera can only turn with one car
+    if player == 0 {
+        car.insert(CameraFocus);
+    }
+}
+
+/// P on the game over screen switches between one player and one per
+/// keyboard half
+fn switch_player_count(keys: Res<Input<KeyCode>>, mut count: ResMut<PlayerCount>) {
+    if keys.just_pressed(KeyCode::P) {
+        count.0 = count.0 % KeyBindings::PLAYERS.len() + 1;
+    }
+}
+
+/// Gives every player a car and takes away the cars of players that left
+fn spawn_players(
+    mut commands: Commands,
+    assets: Res<CarAssets>,
+    count: Res<PlayerCount>,
+    query: Query<(Entity, &PlayerId)>,
+    mut new_count: EventWriter<NewPlayerCount>,
 ) {
-    for (children, movement) in &player_query {
-        let rotation = Quat::from_rotation_z(movement.angle);
-        for child in children {
-            let mut transform = sprites_query.get_mut(*child).unwrap();
-            transform.rotation = rotation;
+    for (entity, id) in &query {
+        if id.0 >= count.0 {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for player in 0..count.0 {
+        if !query.iter().any(|(_, id)| id.0 == player) {
+            spawn_player(&mut commands, &assets, player);
         }
     }
+    new_count.send(NewPlayerCount(count.0));
+}
+
+fn rotate_player(mut query: Query<(&mut StackedSprite, &mut Collider, &Movement), With<Player>>) {
+    for (mut stack, mut collider, movement) in &mut query {
+        stack.yaw = movement.angle;
+        collider.yaw = movement.angle;
+    }
 }
 
 fn move_player(
-    keys: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Transform, &mut Movement), With<Player>>,
+    mut query: Query<(&mut Transform, &mut Movement, &CarInput), With<Player>>,
     time: Res<Time>,
 ) {
-    for (mut transform, mut movement) in &mut query {
-        let is_drifting = keys.pressed(KeyCode::Space);
-        if keys.pressed(KeyCode::A) {
-            movement.angle += 0.015;
-            if is_drifting {
-                movement.angle += 0.003;
-            }
-        }
-        if keys.pressed(KeyCode::D) {
-            movement.angle -= 0.015;
-            if is_drifting {
-                movement.angle -= 0.003;
-            }
-        }
+    for (mut transform, mut movement, input) in &mut query {
+        let is_drifting = input.drift;
+        movement.drifting = is_drifting;
+        // Drifting turns a bit sharper
+        let turn = if is_drifting { 0.018 } else { 0.015 };
+        movement.angle += input.steer * turn;
 
         let direction = Vec3::new(movement.angle.cos(), movement.angle.sin(), 0.0).normalize();
         movement.acceleration = f32::min(
-            movement.top_aceleration,
+            movement.top_aceleration * movement.boost,
             movement.acceleration + (movement.acceleration_rate * time.delta_seconds()),
         );
-        if is_drifting {
+        movement.boost = 1.0;
+        let new_velocity = if is_drifting {
             let target_velocity = direction * (movement.acceleration * 0.8);
-            movement.velocity = (target_velocity - movement.velocity) * movement.drag;
+            (target_velocity - movement.velocity) * movement.drag
         } else {
             let target_velocity = direction * movement.acceleration;
-            movement.velocity = (target_velocity - movement.velocity) * movement.drag;
+            (target_velocity - movement.velocity) * movement.drag
+        };
+        // Without grip the car keeps sliding the way it was going
+        movement.velocity = movement.velocity.lerp(new_velocity, movement.grip);
+        transform.translation += (movement.velocity + movement.impulse) * time.delta_seconds();
+
+        // Ride out the blasts
+        movement.angle += movement.spin * time.delta_seconds();
+        let damping = (-KNOCKBACK_DAMPING * time.delta_seconds()).exp();
+        movement.impulse *= damping;
+        movement.spin *= damping;
+    }
+}
+
+fn use_boost(
+    mut query: Query<
+        (
+            &Transform,
+            &mut Movement,
+            &mut BoostMeter,
+            &CarInput,
+            Option<&PlayerId>,
+        ),
+        With<Player>,
+    >,
+    mut spawn_event: EventWriter<particle::SpawnEvent>,
+    mut new_boost: EventWriter<NewBoost>,
+    time: Res<Time>,
+) {
+    for (transform, mut movement, mut meter, input, id) in &mut query {
+        let last_charge = meter.charge;
+        if input.drift && movement.velocity.length() > BOOST_MIN_DRIFT_SPEED {
+            meter.charge = f32::min(1.0, meter.charge + BOOST_FILL_RATE * time.delta_seconds());
         }
-        transform.translation += movement.velocity * time.delta_seconds();
+        if input.boost && meter.charge > 0.0 {
+            meter.charge = f32::max(0.0, meter.charge - BOOST_DRAIN_RATE * time.delta_seconds());
+            movement.boost(BOOST_MULTIPLIER);
 
-        // Fix car position if it goes out of screen
-        let screen_limit_x = (WINDOW_WIDTH + CAR_SIZE) * 0.5;
-        let screen_limit_y = (WINDOW_HEIGHT + CAR_SIZE) * 0.5;
-        if transform.translation.x > screen_limit_x {
-            transform.translation.x = -screen_limit_x;
+            meter.exhaust.tick(time.delta());
+            if meter.exhaust.just_finished() {
+                let heading = Vec2::new(movement.angle.cos(), movement.angle.sin());
+                spawn_event.send(particle::SpawnEvent {
+                    position: transform.translation - (heading * CAR_SIZE * 0.5).extend(0.0),
+                    effect: particle::Effect::Exhaust {
+                        direction: -heading,
+                    },
+                });
+            }
         }
-        if transform.translation.x < -screen_limit_x {
-            transform.translation.x = screen_limit_x;
+        if let Some(id) = id.filter(|_| meter.charge != last_charge) {
+            new_boost.send(NewBoost {
+                player: id.0,
+                charge: meter.charge,
+            });
+        }
+    }
+}
+
+fn hit_obstacles(
+    mut query: Query<(&mut Transform, &mut Movement), With<Player>>,
+    obstacle_query: Query<&Obstacle>,
+    mut collision_event: EventReader<CollisionEvent>,
+) {
+    for collision in collision_event.read() {
+        let Ok((mut transform, mut movement)) = query.get_mut(collision.entity) else {
+            continue;
+        };
+        let Ok(obstacle) = obstacle_query.get(collision.other) else {
+            continue;
+        };
+        // Getting pushed out while driving on is what makes the car slide along
+        transform.translation += (collision.normal * collision.depth).extend(0.0);
+
+        let speed = (movement.velocity + movement.impulse).truncate();
+        let into = -speed.dot(collision.normal);
+        if into <= 0.0 {
+            continue;
+        }
+        // Blast pushes stop at the obstacle instead of going through it
+        let impulse_into = movement.impulse.truncate().dot(collision.normal).min(0.0);
+        movement.impulse -= (collision.normal * impulse_into).extend(0.0);
+        movement.impulse += (collision.normal * into * obstacle.kind.restitution()).extend(0.0);
+        let head_on = into / speed.length();
+        movement.acceleration *= 1.0 - obstacle.kind.speed_loss() * head_on;
+    }
+}
+
+/// Cars that run into each other are pushed apart and bounce off.
+///
+/// Both cars weigh the same and the bounce depends on how fast they close in
+/// at the contact point, so hitting a car off center also sends it spinning.
+pub fn bump_cars(
+    mut query: Query<(&mut Transform, &mut Movement), With<Player>>,
+    mut collision_event: EventReader<CollisionEvent>,
+) {
+    for collision in collision_event.read() {
+        let Ok([(mut transform, mut movement), (mut other_transform, mut other_movement)]) =
+            query.get_many_mut([collision.entity, collision.other])
+        else {
+            continue;
+        };
+        // Lever arms from where the cars were when they touched
+        let arm = collision.point - transform.translation.truncate();
+        let other_arm = collision.point - other_transform.translation.truncate();
+        let push = collision.normal * collision.depth * 0.5;
+        transform.translation += push.extend(0.0);
+        other_transform.translation -= push.extend(0.0);
+
+        let relative = movement.point_velocity(arm) - other_movement.point_velocity(other_arm);
+        let closing = -relative.dot(collision.normal);
+        if closing <= 0.0 {
+            continue;
         }
-        if transform.translation.y > screen_limit_y {
-            transform.translation.y = -screen_limit_y;
+        let turn = arm.perp_dot(collision.normal);
+        let other_turn = other_arm.perp_dot(collision.normal);
+        let strength = closing * (1.0 + BUMP_RESTITUTION)
+            / (2.0 + (turn * turn + other_turn * other_turn) / CAR_INERTIA);
+
+        movement.impulse += (collision.normal * strength).extend(0.0);
+        movement.spin += turn * strength / CAR_INERTIA;
+        other_movement.impulse -= (collision.normal * strength).extend(0.0);
+        other_movement.spin -= other_turn * strength / CAR_INERTIA;
+    }
+}
+
+fn apply_knockback(
+    mut query: Query<(&Transform, &mut Movement), With<Player>>,
+    mut exploded_event: EventReader<BarrelExplodedEvent>,
+) {
+    for explosion in exploded_event.read() {
+        for (transform, mut movement) in &mut query {
+            let away = (transform.translation - explosion.position).truncate();
+            let reach = explosion.radius * KNOCKBACK_REACH;
+            let distance = away.length();
+            if distance >= reach {
+                continue;
+            }
+            let falloff = (1.0 - distance / reach).powi(2);
+            let direction = away
+                .try_normalize()
+                .unwrap_or(Vec2::new(movement.angle.cos(), movement.angle.sin()));
+            movement.impulse += (direction * explosion.knockback * falloff).extend(0.0);
+            // Spin away from the side the blast hit
+            let heading = Vec2::new(movement.angle.cos(), movement.angle.sin());
+            let side = if heading.perp_dot(direction) >= 0.0 {
+                1.0
+            } else {
+                -1.0
+            };
+            movement.spin += side * explosion.spin * falloff;
         }
-        if transform.translation.y < -screen_limit_y {
-            transform.translation.y = screen_limit_y;
+    }
+}
+
+fn update_grip(
+    mut query: Query<(&Transform, &mut Movement), With<Player>>,
+    slick_query: Query<(&Transform, &OilSlick), Without<Player>>,
+) {
+    for (transform, mut movement) in &mut query {
+        let on_oil = slick_query.iter().any(|(slick_pos, slick)| {
+            slick_pos
+                .translation
+                .truncate()
+                .distance(transform.translation.truncate())
+                < slick.radius
+        });
+        movement.grip = if on_oil { OIL_GRIP } else { 1.0 };
+    }
+}
+
+fn reset_combo(
+    mut query: Query<(&mut ScoreManager, &PlayerId), With<Player>>,
+    mut hurt_event: EventReader<HurtEvent>,
+    mut new_combo: EventWriter<NewCombo>,
+) {
+    for hurt in hurt_event.read() {
+        if let Ok((mut score, id)) = query.get_mut(hurt.target) {
+            score.combo = 0;
+            new_combo.send(NewCombo {
+                player: id.0,
+                combo: score.combo,
+            });
         }
     }
 }
 
 fn update_score(
-    mut query: Query<&mut ScoreManager, With<Player>>,
+    mut query: Query<(Entity, &mut ScoreManager, &ScoreMultiplier, &PlayerId), With<Player>>,
+    mode: Res<GameMode>,
     time: Res<Time>,
+    mut bonus_score: EventReader<BonusScore>,
     mut new_score: EventWriter<NewScore>,
+    mut new_combo: EventWriter<NewCombo>,
 ) {
-    for mut score in &mut query {
+    let bonuses: Vec<&BonusScore> = bonus_score.read().collect();
+    for (car, mut score, multiplier, id) in &mut query {
         score.timer.tick(time.delta());
-        if score.timer.finished() {
-            score.score += 1;
-            new_score.send(NewScore(score.score));
+        if score.timer.finished() && mode.time_points() {
+            score.score += multiplier.0;
+        }
+        // Every bonus in a row is worth a bit more than the last one
+        let mut scored = false;
+        for bonus in bonuses
+            .iter()
+            .filter(|bonus| bonus.car.is_none_or(|entity| entity == car))
+        {
+            score.combo += 1;
+            score.score += bonus.points * (1 + score.combo / COMBO_STEP) * multiplier.0;
+            scored = true;
+        }
+        if scored {
+            new_combo.send(NewCombo {
+                player: id.0,
+                combo: score.combo,
+            });
         }
+        if score.timer.just_finished() || scored {
+            new_score.send(NewScore {
+                player: id.0,
+                score: score.score,
+            });
+        }
+    }
+}
+
+fn reset_player(
+    mut query: Query<
+        (
+            &mut Transform,
+            &mut Movement,
+            &mut ScoreManager,
+            &mut BoostMeter,
+            &PlayerId,
+        ),
+        With<Player>,
+    >,
+    mut new_score: EventWriter<NewScore>,
+    mut new_combo: EventWriter<NewCombo>,
+    mut new_boost: EventWriter<NewBoost>,
+    rules: Res<LevelRules>,
+    count: Res<PlayerCount>,
+) {
+    for (mut transform, mut movement, mut score, mut meter, id) in &mut query {
+        transform.translation = start_position(&rules, id.0, count.0).extend(0.0);
+        movement.stop(rules.player_angle);
+        score.score = 0;
+        score.combo = 0;
+        score.timer.reset();
+        new_score.send(NewScore {
+            player: id.0,
+            score: score.score,
+        });
+        new_combo.send(NewCombo {
+            player: id.0,
+            combo: score.combo,
+        });
+        meter.charge = 0.0;
+        new_boost.send(NewBoost {
+            player: id.0,
+            charge: meter.charge,
+        });
     }
 }
@@ -0,0 +1,311 @@
+#![allow(clippy::needless_pass_by_value, clippy::cast_precision_loss)]
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{P8_LIGHT_GREY, P8_WHITE, P8_YELLOW, WINDOW_HEIGHT},
+    edge::knock_out_cars,
+    level::{LevelPiece, LevelRules},
+    net::RollbackApp,
+    pickup::PickupSet,
+    player::{BonusScore, PlayerId, ScoreSet},
+    ui::{NewRaceTime, RaceTime},
+    GameState,
+};
+
+/// Gates are drawn on the ground just above the tiles
+const GATE_Z: f32 = 0.001;
+const GATE_WIDTH: f32 = 4.0;
+/// Points for finishing a lap
+const LAP_BONUS: usize = 10;
+
+/// Line the car has to drive through, the first gate of a level is the
+/// start and finish line. Seen from a car driving through it the right way,
+/// `from` is on its left and `to` on its right.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Gate {
+    pub from: Vec2,
+    pub to: Vec2,
+}
+
+impl Gate {
+    /// Whether the step from `start` to `end` goes through the gate the
+    /// right way, driving back over it does not count
+    pub fn crossed(&self, start: Vec2, end: Vec2) -> bool {
+        let gate = self.to - self.from;
+        let step = end - start;
+        // Negative going the wrong way, zero along the gate
+        let denominator = gate.perp_dot(step);
+        if denominator < f32::EPSILON {
+            return false;
+        }
+        let offset = start - self.from;
+        let along_gate = offset.perp_dot(step) / denominator;
+        let along_step = offset.perp_dot(gate) / denominator;
+        (0.0..=1.0).contains(&along_gate) && (0.0..=1.0).contains(&along_step)
+    }
+
+    /// Distance from a point to the closest part of the gate
+    pub fn distance_to(&self, point: Vec2) -> f32 {
+        let along = self.to - self.from;
+        let t = ((point - self.from).dot(along) / along.length_squared().max(f32::EPSILON))
+            .clamp(0.0, 1.0);
+        (self.from + along * t).distance(point)
+    }
+}
+
+/// Where a car is in the race, the lap timer only runs after the start line
+#[derive(Component, Default, Clone)]
+pub struct RaceProgress {
+    next_gate: usize,
+    started: bool,
+    lap: u32,
+    lap_time: f32,
+    /// Time into the lap at every gate passed this lap
+    splits: Vec<f32>,
+    /// Difference to the best lap at the last gate passed
+    split: Option<f32>,
+    last_position: Option<Vec2>,
+}
+
+impl RaceProgress {
+    /// Index in `LevelRules::checkpoints` of the gate to go through next
+    pub fn next_gate(&self) -> usize {
+        self.next_gate
+    }
+}
+
+/// Best times on the current level
+#[derive(Resource, Default, Clone)]
+struct RaceRecords {
+    best_lap: Option<f32>,
+    /// Time into the best lap at each of its gates
+    best_splits: Vec<f32>,
+}
+
+#[derive(Component)]
+struct GateMarker {
+    index: usize,
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RaceRecords>()
+            .rollback_component::<RaceProgress>()
+            .rollback_resource::<RaceRecords>()
+            .add_systems(OnEnter(GameState::Loading), clear_records)
+            .add_systems(OnEnter(GameState::Playing), reset_progress)
+            .add_systems(
+                Update,
+                (pass_gates, color_gates)
+                    .chain()
+                    .after(knock_out_cars)
+                    .before(PickupSet)
+                    .before(ScoreSet)
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+/// Ground markings for the gates, built with the level
+pub fn spawn_gates(commands: &mut Commands, gates: &[Gate]) {
+    for (index, gate) in gates.iter().enumerate() {
+        let along = gate.to - gate.from;
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: P8_LIGHT_GREY,
+                    custom_size: Some(Vec2::new(along.length(), GATE_WIDTH)),
+                    ..default()
+                },
+                transform: Transform::from_translation(
+                    ((gate.from + gate.to) * 0.5).extend(GATE_Z),
+                )
+                .with_rotation(Quat::from_rotation_z(along.y.atan2(along.x))),
+                ..default()
+            },
+            GateMarker { index },
+            LevelPiece,
+        ));
+    }
+}
+
+fn clear_records(mut records: ResMut<RaceRecords>) {
+    *records = RaceRecords::default();
+}
+
+fn reset_progress(
+    mut commands: Commands,
+    query: Query<(Entity, &PlayerId)>,
+    rules: Res<LevelRules>,
+    mut new_race_time: EventWriter<NewRaceTime>,
+) {
+    for (entity, id) in &query {
+        commands.entity(entity).insert(RaceProgress::default());
+        if rules.checkpoints.is_empty() {
+            new_race_time.send(NewRaceTime {
+                player: id.0,
+                time: None,
+            });
+        }
+    }
+}
+
+fn pass_gates(
+    mut query: Query<(Entity, &Transform, Option<&PlayerId>, &mut RaceProgress)>,
+    rules: Res<LevelRules>,
+    mut records: ResMut<RaceRecords>,
+    mut bonus_score: EventWriter<BonusScore>,
+    mut new_race_time: EventWriter<NewRaceTime>,
+    time: Res<Time>,
+) {
+    if rules.checkpoints.is_empty() {
+        return;
+    }
+    for (car, transform, id, mut progress) in &mut query {
+        let position = transform.translation.truncate();
+        let last_position = progress.last_position.replace(position);
+        if progress.started {
+            progress.lap_time += time.delta_seconds();
+        }
+        let crossed = last_position.is_some_and(|last| {
+            drove_through(&rules.checkpoints[progress.next_gate], last, position)
+        });
+
+        if crossed && !progress.started {
+            progress.started = true;
+            progress.next_gate = 1 % rules.checkpoints.len();
+        } else if crossed {
+            let gate = progress.splits.len();
+            let lap_time = progress.lap_time;
+            progress.split = records.best_splits.get(gate).map(|best| lap_time - best);
+            progress.splits.push(lap_time);
+            progress.next_gate = (progress.next_gate + 1) % rules.checkpoints.len();
+
+            if progress.next_gate == 1 % rules.checkpoints.len() {
+                // Rivals race too, but the records are the players' to beat
+                if id.is_some() && records.best_lap.is_none_or(|best| lap_time < best) {
+                    records.best_lap = Some(lap_time);
+                    records.best_splits = std::mem::take(&mut progress.splits);
+                }
+                progress.splits.clear();
+                progress.lap += 1;
+                progress.lap_time = 0.0;
+                bonus_score.send(BonusScore {
+                    car: Some(car),
+                    points: LAP_BONUS,
+                });
+            }
+        }
+
+        let Some(id) = id else {
+            continue;
+        };
+        new_race_time.send(NewRaceTime {
+            player: id.0,
+            time: Some(RaceTime {
+                lap: progress.lap + 1,
+                time: progress.lap_time,
+                best: records.best_lap,
+                split: progress.split,
+            }),
+        });
+    }
+}
+
+/// Whether a car that moved from `last` to `position` drove through the gate,
+/// a jump across a wrapping edge is not a step through the arena
+fn drove_through(gate: &Gate, last: Vec2, position: Vec2) -> bool {
+    last.distance(position) < WINDOW_HEIGHT * 0.5 && gate.crossed(last, position)
+}
+
+/// Lights up the gate every player has to go through next
+fn color_gates(
+    progress_query: Query<&RaceProgress, With<PlayerId>>,
+    mut gate_query: Query<(&GateMarker, &mut Sprite)>,
+) {
+    for (gate, mut sprite) in &mut gate_query {
+        let color = if progress_query
+            .iter()
+            .any(|progress| progress.next_gate == gate.index)
+        {
+            P8_YELLOW
+        } else if gate.index == 0 {
+            P8_WHITE
+        } else {
+            P8_LIGHT_GREY
+        };
+        if sprite.color != color {
+            sprite.color = color;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Driven through going right
+    const GATE: Gate = Gate {
+        from: Vec2::new(0.0, 10.0),
+        to: Vec2::new(0.0, -10.0),
+    };
+
+    #[test]
+    fn crossing_forward() {
+        assert!(GATE.crossed(Vec2::new(-1.0, 0.0), Vec2::new(1.0, 0.0)));
+        assert!(GATE.crossed(Vec2::new(-1.0, 5.0), Vec2::new(1.0, -5.0)));
+    }
+
+    #[test]
+    fn reverse_pass_is_not_a_crossing() {
+        assert!(!GATE.crossed(Vec2::new(1.0, 0.0), Vec2::new(-1.0, 0.0)));
+        assert!(!GATE.crossed(Vec2::new(1.0, 5.0), Vec2::new(-1.0, 5.0)));
+        // Back and forth over the line only counts the forward pass
+        let passes = [-1.0, 1.0, -1.0, 1.0, -1.0]
+            .windows(2)
+            .filter(|step| GATE.crossed(Vec2::new(step[0], 0.0), Vec2::new(step[1], 0.0)))
+            .count();
+        assert_eq!(passes, 2);
+    }
+
+    #[test]
+    fn missing_the_gate() {
+        // Short of the line, and past its end
+        assert!(!GATE.crossed(Vec2::new(-3.0, 0.0), Vec2::new(-1.0, 0.0)));
+        assert!(!GATE.crossed(Vec2::new(-1.0, 11.0), Vec2::new(1.0, 11.0)));
+    }
+
+    #[test]
+    fn hitting_an_endpoint() {
+        assert!(GATE.crossed(Vec2::new(-1.0, 10.0), Vec2::new(1.0, 10.0)));
+        assert!(GATE.crossed(Vec2::new(-1.0, -9.0), Vec2::new(1.0, -11.0)));
+        // Ending the step right on the line counts
+        assert!(GATE.crossed(Vec2::new(-1.0, 0.0), Vec2::ZERO));
+    }
+
+    #[test]
+    fn moving_along_the_gate() {
+        assert!(!GATE.crossed(Vec2::new(0.0, -5.0), Vec2::new(0.0, 5.0)));
+        assert!(!GATE.crossed(Vec2::new(1.0, -5.0), Vec2::new(1.0, 5.0)));
+    }
+
+    #[test]
+    fn wrap_jump_is_not_a_crossing() {
+        let gate = Gate {
+            from: Vec2::new(10.0, 0.0),
+            to: Vec2::new(-10.0, 0.0),
+        };
+        let top = Vec2::new(0.0, WINDOW_HEIGHT * 0.5 - 1.0);
+        let bottom = Vec2::new(0.0, -WINDOW_HEIGHT * 0.5 + 1.0);
+        // The line between the two edges goes through the gate
+        assert!(gate.crossed(top, bottom));
+        assert!(!drove_through(&gate, top, bottom));
+        assert!(drove_through(
+            &gate,
+            Vec2::new(0.0, 2.0),
+            Vec2::new(0.0, -2.0)
+        ));
+    }
+}
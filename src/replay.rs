@@ -0,0 +1,601 @@
+#![allow(clippy::needless_pass_by_value, clippy::module_name_repetitions)]
+use std::{
+    fs,
+    hash::{Hash, Hasher},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    ecs::schedule::{ExecutorKind, ScheduleLabel},
+    input::InputSystem,
+    log::LogPlugin,
+    prelude::*,
+    render::{settings::WgpuSettings, RenderPlugin},
+    time::TimeUpdateStrategy,
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use bevy_turborand::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    ai::RivalSettings,
+    barrel::BarrelDangers,
+    input::{read_input, InputSet, MAX_STEER},
+    level::{CurrentLevel, LEVELS},
+    net::{self, NetConfig},
+    player::{Movement, Player, PlayerCount, PlayerId, ScoreManager},
+    GameState,
+};
+
+// Replays are made of these, whoever builds one needs them
+pub use crate::{ai::Skill, input::CarInput, mode::GameMode};
+
+/// Where `--record` saves the replays
+const REPLAY_DIR: &str = "replays";
+/// Time step of the frames played back before the run starts
+const LOADING_STEP: Duration = Duration::from_millis(16);
+/// Shortest and longest time step of a frame, 240 and 20 frames a second.
+/// Runs are recorded within them and replays with other steps turned down.
+const MIN_STEP: Duration = Duration::from_nanos(1_000_000_000 / 240);
+const MAX_STEP: Duration = Duration::from_nanos(1_000_000_000 / 20);
+/// Frames a replay gets to load its level before it is given up
+const MAX_LOADING_FRAMES: usize = 1200;
+/// FNV-1a, the checksums have to match between builds and platforms
+const CHECKSUM_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const CHECKSUM_PRIME: u64 = 0x0100_0000_01b3;
+
+/// One run recorded from its first frame to the game over, played back the
+/// same way anywhere by `simulate`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Replay {
+    pub seed: u64,
+    pub level: usize,
+    pub mode: GameMode,
+    pub players: usize,
+    pub rivals: usize,
+    pub skill: Skill,
+    pub frames: Vec<ReplayFrame>,
+    /// Score of every player when the run ended
+    pub scores: Vec<usize>,
+    /// Game time the run lasted, slow motion included
+    pub time: Duration,
+}
+
+/// Time step and what every player did in one frame
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReplayFrame {
+    /// Nanoseconds of real time, the game clock is slowed down from it the
+    /// same way as when playing
+    pub step: u64,
+    /// One per player
+    pub inputs: Vec<CarInput>,
+    /// Checksum of the cars, scores and barrels at the end of the frame
+    pub state: u64,
+}
+
+/// What a replay played back to
+pub struct RunSummary {
+    /// Frames played, the run ended after the last one when `over` is set
+    pub frames: usize,
+    pub over: bool,
+    /// Score of every player after the last frame
+    pub scores: Vec<usize>,
+    pub time: Duration,
+    /// First frame that did not end in the recorded state
+    pub diverged: Option<usize>,
+    /// State every played frame ended in
+    pub states: Vec<u64>,
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("could not read replay file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse replay: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("there is no level {0}")]
+    Level(usize),
+    #[error("the level did not load")]
+    Loading,
+}
+
+/// Why a replay does not back up the run it claims
+#[derive(Debug, Error)]
+pub enum VerifyError {
+    #[error(transparent)]
+    Replay(#[from] ReplayError),
+    #[error("frame {frame} lasts {step:.2?}, longer or shorter than a frame can")]
+    Step { frame: usize, step: Duration },
+    #[error("frame {0} has an input no driver can give")]
+    Input(usize),
+    #[error("the game went another way from frame {0}")]
+    Diverged(usize),
+    #[error("the run did not end after {0} frames")]
+    NotOver(usize),
+    #[error("claimed scores for {claimed} players but the replay has {played}")]
+    Players { claimed: usize, played: usize },
+    #[error("claimed a score of {claimed} but the replay scores {played}")]
+    Score { claimed: usize, played: usize },
+    #[error("claimed {claimed:.2?} but the replay lasts {played:.2?}")]
+    Time { claimed: Duration, played: Duration },
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+/// Sent when a recorded run ends
+#[derive(Event)]
+pub struct NewReplay(pub Replay);
+
+/// Records every run, also saving them in `REPLAY_DIR` when `save` is set.
+///
+/// Inserted before the plugin by whoever needs the replays, `--record` adds
+/// it on the command line.
+#[derive(Resource, Clone, Copy, Default)]
+pub struct Recording {
+    pub save: bool,
+}
+
+/// Frame of the run being played, counted from its start
+#[derive(Resource, Default)]
+struct Run {
+    seed: u64,
+    frame: Option<u32>,
+    /// Game time since the first frame
+    time: Duration,
+}
+
+#[derive(Resource)]
+struct Recorder {
+    save: bool,
+    frames: Vec<ReplayFrame>,
+    /// When the last frame ended, the next one lasts the time since
+    last_frame: Instant,
+}
+
+/// Replay fed to the cars instead of the keyboard
+#[derive(Resource)]
+struct Playback {
+    replay: Replay,
+    played: usize,
+    diverged: Option<usize>,
+    states: Vec<u64>,
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.add_event::<NewReplay>();
+        let playback = app.world.remove_resource::<Playback>();
+        let record = std::env::args().any(|arg| arg == "--record");
+        let recording = match app.world.remove_resource::<Recording>() {
+            Some(recording) => Some(Recording {
+                save: recording.save || record,
+            }),
+            None => record.then_some(Recording { save: true }),
+        };
+        if playback.is_none() && recording.is_none() {
+            return;
+        }
+
+        // Systems have to run in the same order as when the run was recorded
+        for label in [Update.intern(), OnEnter(GameState::Playing).intern()] {
+            app.edit_schedule(label, |schedule| {
+                schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+            });
+        }
+        app.init_resource::<Run>()
+            .add_systems(
+                PreUpdate,
+                count_frames.after(InputSystem).run_if(net::offline),
+            )
+            // Both sides get the same systems, the order of the ones not
+            // ordered against each other depends on the whole schedule
+            .add_systems(
+                Update,
+                (
+                    play_inputs
+                        .in_set(InputSet)
+                        .after(read_input)
+                        .run_if(resource_exists::<Playback>()),
+                    record_inputs
+                        .after(InputSet)
+                        .run_if(resource_exists::<Recorder>())
+                        .run_if(net::offline),
+                )
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Last,
+                check_state
+                    .run_if(in_state(GameState::Playing))
+                    .run_if(net::offline),
+            );
+
+        if let Some(playback) = playback {
+            let replay = &playback.replay;
+            app.insert_resource(Run {
+                seed: replay.seed,
+                ..default()
+            })
+            .insert_resource(replay.mode)
+            .insert_resource(PlayerCount(replay.players))
+            .insert_resource(RivalSettings {
+                count: replay.rivals,
+                skill: replay.skill,
+            })
+            .insert_resource(playback)
+            .add_systems(PostStartup, load_replay_level)
+            .add_systems(Last, queue_step);
+        } else if let Some(recording) = recording {
+            app.insert_resource(Recorder {
+                save: recording.save,
+                frames: Vec::new(),
+                last_frame: Instant::now(),
+            })
+            .add_systems(
+                OnEnter(GameState::GameOver),
+                finish_replay.run_if(net::offline),
+            )
+            .add_systems(Last, queue_recorded_step.run_if(net::offline));
+        }
+    }
+}
+
+/// Counts the frames of the run and seeds the random numbers of each one,
+/// the first frame is the one switching to `GameState::Playing`
+fn count_frames(
+    state: Res<State<GameState>>,
+    next_state: Res<NextState<GameState>>,
+    mut run: ResMut<Run>,
+    recorder: Option<ResMut<Recorder>>,
+    mut global_rng: ResMut<GlobalRng>,
+) {
+    let playing = *state.get() == GameState::Playing;
+    if !playing && next_state.0 == Some(GameState::Playing) {
+        run.frame = Some(0);
+        run.time = Duration::ZERO;
+        if let Some(mut recorder) = recorder {
+            // Picked here and trusted by `verify`, see there
+            run.seed = RngComponent::new().u64(..);
+            recorder.frames.clear();
+        }
+    } else if playing {
+        run.frame = run.frame.map(|frame| frame + 1);
+    } else {
+        run.frame = None;
+    }
+    if let Some(frame) = run.frame {
+        *global_rng = GlobalRng::with_seed(net::frame_seed(run.seed, frame));
+    }
+}
+
+fn record_inputs(
+    mut recorder: ResMut<Recorder>,
+    query: Query<(&PlayerId, &CarInput)>,
+    time: Res<Time<Real>>,
+) {
+    let mut inputs: Vec<(&PlayerId, &CarInput)> = query.iter().collect();
+    inputs.sort_unstable_by_key(|(id, _)| id.0);
+    recorder.frames.push(ReplayFrame {
+        step: time.delta().as_nanos() as u64,
+        inputs: inputs.into_iter().map(|(_, input)| *input).collect(),
+        state: 0,
+    });
+}
+
+/// Fowler-Noll-Vo hasher, unlike the std one it is the same on every
+/// platform and toolchain
+pub struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(CHECKSUM_PRIME);
+        }
+    }
+
+    // Numbers are written the same way whatever the byte order and pointer size
+    fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+}
+
+/// Hash of one piece of the state, like a car or a barrel. Pieces are summed
+/// so the order of the queries does not matter.
+pub fn checksum(hash: impl FnOnce(&mut Fnv)) -> u64 {
+    let mut hasher = Fnv(CHECKSUM_BASIS);
+    hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Gives the next frame the time the last one took, kept within the steps
+/// `verify` accepts. Real time is stepped by hand so the power-up timers
+/// running on it also see the kept step.
+fn queue_recorded_step(mut commands: Commands, mut recorder: ResMut<Recorder>) {
+    let elapsed = recorder.last_frame.elapsed();
+    if elapsed < MIN_STEP {
+        std::thread::sleep(MIN_STEP - elapsed);
+    }
+    let step = recorder.last_frame.elapsed().clamp(MIN_STEP, MAX_STEP);
+    recorder.last_frame = Instant::now();
+    commands.insert_resource(TimeUpdateStrategy::ManualDuration(step));
+}
+
+/// Stores the state every recorded frame ended in, or compares it with the
+/// recorded one when playing back
+fn check_state(
+    mut run: ResMut<Run>,
+    recorder: Option<ResMut<Recorder>>,
+    playback: Option<ResMut<Playback>>,
+    car_query: Query<(&Transform, &Movement, Option<&ScoreManager>), With<Player>>,
+    dangers: Res<BarrelDangers>,
+    time: Res<Time>,
+) {
+    run.time += time.delta();
+    let mut state = 0u64;
+    for (transform, movement, score) in &car_query {
+        state = state.wrapping_add(checksum(|hasher| {
+            for value in [transform.translation, movement.velocity] {
+                value.to_array().map(f32::to_bits).hash(hasher);
+            }
+            score.map(ScoreManager::score).hash(hasher);
+        }));
+    }
+    for danger in &dangers.0 {
+        state = state.wrapping_add(checksum(|hasher| {
+            danger.position.to_array().map(f32::to_bits).hash(hasher);
+            danger.kind.hash(hasher);
+            danger.fuse.map(f32::to_bits).hash(hasher);
+        }));
+    }
+
+    if let Some(mut recorder) = recorder {
+        if let Some(frame) = recorder.frames.last_mut() {
+            frame.state = state;
+        }
+    } else if let Some(mut playback) = playback {
+        playback.states.push(state);
+        let index = playback.played.saturating_sub(1);
+        let recorded = playback.replay.frames.get(index).map(|frame| frame.state);
+        if playback.diverged.is_none() && recorded.is_some_and(|recorded| recorded != state) {
+            playback.diverged = Some(index);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn finish_replay(
+    recorder: Res<Recorder>,
+    run: Res<Run>,
+    current: Res<CurrentLevel>,
+    mode: Res<GameMode>,
+    count: Res<PlayerCount>,
+    rivals: Res<RivalSettings>,
+    score_query: Query<(&PlayerId, &ScoreManager)>,
+    mut new_replay: EventWriter<NewReplay>,
+) {
+    let mut scores: Vec<(usize, usize)> = score_query
+        .iter()
+        .map(|(id, score)| (id.0, score.score()))
+        .collect();
+    scores.sort_unstable();
+    let replay = Replay {
+        seed: run.seed,
+        level: current.index,
+        mode: *mode,
+        players: count.0,
+        rivals: rivals.count,
+        skill: rivals.skill,
+        frames: recorder.frames.clone(),
+        scores: scores.into_iter().map(|(_, score)| score).collect(),
+        time: run.time,
+    };
+    if recorder.save {
+        let path = Path::new(REPLAY_DIR).join(format!("{:016x}.replay.ron", replay.seed));
+        let saved = fs::create_dir_all(REPLAY_DIR)
+            .and_then(|()| fs::write(&path, ron::to_string(&replay).unwrap_or_default()));
+        match saved {
+            Ok(()) => info!("replay saved to {}", path.display()),
+            Err(error) => error!("could not save replay: {error}"),
+        }
+    }
+    new_replay.send(NewReplay(replay));
+}
+
+fn load_replay_level(
+    playback: Res<Playback>,
+    mut current: ResMut<CurrentLevel>,
+    asset_server: Res<AssetServer>,
+) {
+    current.index = playback.replay.level;
+    current.handle = asset_server.load(LEVELS[current.index]);
+}
+
+fn play_inputs(mut playback: ResMut<Playback>, mut query: Query<(&PlayerId, &mut CarInput)>) {
+    if let Some(frame) = playback.replay.frames.get(playback.played) {
+        for (id, mut input) in &mut query {
+            if let Some(recorded) = frame.inputs.get(id.0) {
+                *input = *recorded;
+            }
+        }
+    }
+    playback.played += 1;
+}
+
+/// Gives the next frame the time step it had when it was recorded
+fn queue_step(
+    mut commands: Commands,
+    playback: Res<Playback>,
+    state: Res<State<GameState>>,
+    next_state: Res<NextState<GameState>>,
+) {
+    let running = *state.get() == GameState::Playing || next_state.0 == Some(GameState::Playing);
+    let step = playback
+        .replay
+        .frames
+        .get(playback.played)
+        .filter(|_| running)
+        .map_or(LOADING_STEP, |frame| Duration::from_nanos(frame.step));
+    commands.insert_resource(TimeUpdateStrategy::ManualDuration(step));
+}
+
+/// Plays a replay back without a window, as fast as possible
+pub fn simulate(replay: &Replay) -> Result<RunSummary, ReplayError> {
+    if replay.level >= LEVELS.len() {
+        return Err(ReplayError::Level(replay.level));
+    }
+    let mut app = App::new();
+    app.add_plugins((
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            })
+            .set(RenderPlugin {
+                render_creation: WgpuSettings {
+                    backends: None,
+                    ..default()
+                }
+                .into(),
+            })
+            .set(ImagePlugin::default_nearest())
+            .disable::<WinitPlugin>()
+            .disable::<LogPlugin>(),
+        RngPlugin::default(),
+    ))
+    // Nothing from the command line of whoever runs the check
+    .insert_resource(NetConfig::default())
+    .insert_resource(Playback {
+        replay: replay.clone(),
+        played: 0,
+        diverged: None,
+        states: Vec::new(),
+    });
+    crate::add_game(&mut app);
+    app.finish();
+    app.cleanup();
+
+    let mut loading = 0;
+    loop {
+        app.update();
+        let state = *app.world.resource::<State<GameState>>().get();
+        let playback = app.world.resource::<Playback>();
+        let (played, diverged) = (playback.played, playback.diverged);
+        let over = state == GameState::GameOver;
+        if over || played > replay.frames.len() {
+            let mut scores: Vec<(usize, usize)> = app
+                .world
+                .query::<(&PlayerId, &ScoreManager)>()
+                .iter(&app.world)
+                .map(|(id, score)| (id.0, score.score()))
+                .collect();
+            scores.sort_unstable();
+            return Ok(RunSummary {
+                frames: played,
+                over,
+                scores: scores.into_iter().map(|(_, score)| score).collect(),
+                time: app.world.resource::<Run>().time,
+                diverged,
+                states: std::mem::take(&mut app.world.resource_mut::<Playback>().states),
+            });
+        }
+        if state != GameState::Playing {
+            loading += 1;
+            if loading > MAX_LOADING_FRAMES {
+                return Err(ReplayError::Loading);
+            }
+        }
+    }
+}
+
+/// Plays the inputs and time steps of `script` and records the run they
+/// make, up to the game over or the last frame. The recorded states of
+/// `script` are not looked at.
+pub fn record(script: &Replay) -> Result<Replay, ReplayError> {
+    let summary = simulate(script)?;
+    let mut replay = script.clone();
+    replay.frames.truncate(summary.frames);
+    for (frame, state) in replay.frames.iter_mut().zip(summary.states) {
+        frame.state = state;
+    }
+    replay.scores = summary.scores;
+    replay.time = summary.time;
+    Ok(replay)
+}
+
+/// Plays a replay back and checks it ends with the score and time it claims,
+/// frame after frame in the recorded state.
+///
+/// The seed comes from the replay, picked by the game that recorded it.
+/// Trying seeds until one plays out well is not caught, that is out of
+/// scope here.
+pub fn verify(replay: &Replay) -> Result<RunSummary, VerifyError> {
+    // Tiny steps would make for slow motion, huge ones skip over things
+    for (frame, recorded) in replay.frames.iter().enumerate() {
+        let step = Duration::from_nanos(recorded.step);
+        if !(MIN_STEP..=MAX_STEP).contains(&step) {
+            return Err(VerifyError::Step { frame, step });
+        }
+        // Not a number is outside the range too
+        let steering = -MAX_STEER..=MAX_STEER;
+        if !recorded
+            .inputs
+            .iter()
+            .all(|input| steering.contains(&input.steer))
+        {
+            return Err(VerifyError::Input(frame));
+        }
+    }
+    let summary = simulate(replay)?;
+    if let Some(frame) = summary.diverged {
+        return Err(VerifyError::Diverged(frame));
+    }
+    if !summary.over || summary.frames != replay.frames.len() {
+        return Err(VerifyError::NotOver(replay.frames.len()));
+    }
+    if replay.scores.len() != summary.scores.len() {
+        return Err(VerifyError::Players {
+            claimed: replay.scores.len(),
+            played: summary.scores.len(),
+        });
+    }
+    for (claimed, played) in replay.scores.iter().zip(&summary.scores) {
+        if claimed != played {
+            return Err(VerifyError::Score {
+                claimed: *claimed,
+                played: *played,
+            });
+        }
+    }
+    if replay.time != summary.time {
+        return Err(VerifyError::Time {
+            claimed: replay.time,
+            played: summary.time,
+        });
+    }
+    Ok(summary)
+}
@@ -0,0 +1,147 @@
+#![allow(clippy::needless_pass_by_value, clippy::type_complexity)]
+use bevy::{
+    prelude::*,
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+    transform::TransformSystem,
+};
+
+use crate::{
+    config::{P8_GREY, SHADOW_LIGHT_DIRECTION, WINDOW_HEIGHT},
+    net::RollbackApp,
+    stacked_sprite::StackedSprite,
+};
+
+/// Shadows are drawn on the ground, under every stacked object
+const SHADOW_Z: f32 = 2.0;
+/// Height at which a shadow has shrunk and faded completely
+const SHADOW_MAX_HEIGHT: f32 = WINDOW_HEIGHT;
+/// How far the light pushes the shadow for every pixel of height
+const SHADOW_HEIGHT_OFFSET: f32 = 0.2;
+const SHADOW_GROUND_OFFSET: f32 = 3.0;
+
+/// Give a `StackedSprite` entity a shadow sized from its footprint
+#[derive(Component, Default)]
+pub struct CastShadow;
+
+#[derive(Component)]
+struct Shadow {
+    owner: Entity,
+}
+
+#[derive(Resource)]
+pub struct ShadowLight {
+    pub direction: Vec2,
+}
+
+#[derive(Resource)]
+struct ShadowAssets {
+    mesh: Mesh2dHandle,
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ShadowLight {
+            direction: SHADOW_LIGHT_DIRECTION.normalize(),
+        })
+        .rollback_marker::<CastShadow>()
+        .add_systems(Startup, load_shadows)
+        // Orphans go in Update, removing them in PostUpdate could race with
+        // the bounds added to new shadows
+        .add_systems(Update, (spawn_shadows, remove_orphan_shadows))
+        .add_systems(
+            PostUpdate,
+            update_shadows.before(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+fn load_shadows(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    commands.insert_resource(ShadowAssets {
+        mesh: meshes.add(shape::Circle::new(0.5).into()).into(),
+    });
+}
+
+fn spawn_shadows(
+    mut commands: Commands,
+    query: Query<Entity, Added<CastShadow>>,
+    shadow_assets: Res<ShadowAssets>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for owner in &query {
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: shadow_assets.mesh.clone(),
+                // Every shadow fades on its own so they can not share the material
+                material: materials.add(P8_GREY.into()),
+                transform: Transform::from_scale(Vec3::ZERO),
+                ..default()
+            },
+            Shadow { owner },
+        ));
+    }
+}
+
+fn remove_orphan_shadows(
+    mut commands: Commands,
+    shadow_query: Query<(Entity, &Shadow)>,
+    owner_query: Query<(), With<CastShadow>>,
+) {
+    for (entity, shadow) in &shadow_query {
+        if owner_query.get(shadow.owner).is_err() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+fn update_shadows(
+    mut shadow_query: Query<(
+        &Shadow,
+        &mut Transform,
+        &mut Visibility,
+        &Handle<ColorMaterial>,
+    )>,
+    owner_query: Query<
+        (&Transform, &Visibility, &StackedSprite),
+        (With<CastShadow>, Without<Shadow>),
+    >,
+    atlases: Res<Assets<TextureAtlas>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    light: Res<ShadowLight>,
+) {
+    for (shadow, mut transform, mut visibility, material) in &mut shadow_query {
+        let Ok((owner_transform, owner_visibility, stack)) = owner_query.get(shadow.owner) else {
+            continue;
+        };
+        // Blinks along with its owner
+        visibility.set_if_neq(if owner_visibility == Visibility::Hidden {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        });
+        let Some(footprint) = stack.custom_size.or_else(|| {
+            atlases
+                .get(&stack.atlas)
+                .and_then(|atlas| atlas.textures.first())
+                .map(Rect::size)
+        }) else {
+            continue;
+        };
+
+        let height = (stack.elevation / SHADOW_MAX_HEIGHT).clamp(0.0, 1.0);
+        let offset =
+            light.direction * (SHADOW_GROUND_OFFSET + stack.elevation * SHADOW_HEIGHT_OFFSET);
+        transform.translation = (owner_transform.translation.truncate() + offset).extend(SHADOW_Z);
+        transform.scale = Vec3::splat(footprint.min_element() * stack.scale * (1.0 - height));
+        // Writing the material uploads it again, only do it when it fades
+        let alpha = 1.0 - height;
+        if materials
+            .get(material)
+            .is_some_and(|material| material.color.a() != alpha)
+        {
+            if let Some(material) = materials.get_mut(material) {
+                material.color.set_a(alpha);
+            }
+        }
+    }
+}
@@ -0,0 +1,589 @@
+#![allow(
+    clippy::needless_pass_by_value,
+    clippy::type_complexity,
+    clippy::too_many_arguments
+)]
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    input::mouse::MouseWheel,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    barrel::{BarrelView, BarrelViews},
+    camera::{CameraFocus, CameraView, GameCamera},
+    level::{CurrentLevel, LEVELS},
+    player::{car_sprite, CarAssets, Player, PlayerCount, PlayerId, ScoreManager, MAX_PLAYERS},
+    shadow::CastShadow,
+    stacked_sprite::StackedSprite,
+    ui::{NewNetStatus, NewPlayerCount, NewScore},
+    GameState,
+};
+
+/// Frames between two views sent to the spectators
+const SEND_INTERVAL: u32 = 2;
+/// Spectators say they are still watching this often
+const HELLO_INTERVAL: Duration = Duration::from_secs(1);
+/// A spectator silent for this long is not sent anything anymore
+const WATCHER_TIMEOUT: Duration = Duration::from_secs(5);
+/// The watched game counts as gone after this long without a view
+const VIEW_TIMEOUT: Duration = Duration::from_secs(3);
+/// Views of crowded arenas take more room than the online inputs
+const PACKET_SIZE: usize = 64 * 1024;
+/// Most a UDP packet can carry, bigger ones are not sent
+const MAX_PACKET: usize = 65_507;
+/// Barrels sent in one packet, views with more are split over a few
+const BARRELS_PER_PACKET: usize = 256;
+/// Speed of the free camera, in pixels per second at normal zoom
+const PAN_SPEED: f32 = 300.0;
+/// Zoom change for every step of the mouse wheel
+const ZOOM_STEP: f32 = 1.1;
+const MIN_ZOOM: f32 = 0.5;
+const MAX_ZOOM: f32 = 2.0;
+/// How fast the camera catches up with the followed car, per second
+const FOLLOW_SPEED: f32 = 5.0;
+
+/// Watching a game from somewhere else, read from the command line.
+///
+/// `--broadcast PORT` sends the game, online or not, to whoever watches it
+/// and `--watch ADDRESS:PORT` watches a game sent that way.
+#[derive(Resource, Clone, Copy)]
+pub enum SpectateConfig {
+    Broadcast { port: u16 },
+    Watch { host: SocketAddr },
+}
+
+impl SpectateConfig {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut config = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--broadcast" => {
+                    let port = args.next().and_then(|port| port.parse().ok());
+                    if port.is_none() {
+                        error!("--broadcast needs a port number");
+                    }
+                    config = port.map(|port| Self::Broadcast { port });
+                }
+                "--watch" => {
+                    config = args
+                        .next()
+                        .and_then(|host| host.to_socket_addrs().ok()?.next())
+                        .map(|host| Self::Watch { host });
+                    if config.is_none() {
+                        error!("--watch needs a reachable address like 127.0.0.1:7778");
+                    }
+                }
+                _ => (),
+            }
+        }
+        config
+    }
+}
+
+/// What spectators are shown of a car
+#[derive(Clone, Serialize, Deserialize)]
+struct CarView {
+    /// Same for as long as the car is around
+    id: u64,
+    /// `None` for rivals
+    player: Option<usize>,
+    color: Color,
+    position: Vec2,
+    yaw: f32,
+    score: Option<usize>,
+}
+
+/// Everything a spectator draws for one frame
+#[derive(Clone, Serialize, Deserialize)]
+struct WorldView {
+    frame: u64,
+    /// Which of the packets of the frame this is, each has some of the barrels
+    part: usize,
+    level: usize,
+    over: bool,
+    cars: Vec<CarView>,
+    barrels: Vec<BarrelView>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Message {
+    /// Sent by spectators to start and keep getting views
+    Watch,
+    View(WorldView),
+}
+
+fn send(socket: &UdpSocket, to: SocketAddr, message: &Message) {
+    let Ok(packet) = ron::to_string(message) else {
+        return;
+    };
+    if packet.len() > MAX_PACKET {
+        warn!(
+            "packet of {} bytes to {to} is too big to send",
+            packet.len()
+        );
+        return;
+    }
+    if let Err(error) = socket.send_to(packet.as_bytes(), to) {
+        debug!("packet to {to} not sent: {error}");
+    }
+}
+
+fn receive(socket: &UdpSocket) -> Vec<(SocketAddr, Message)> {
+    let mut buffer = vec![0; PACKET_SIZE];
+    let mut messages = Vec::new();
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((size, from)) => {
+                let message = std::str::from_utf8(&buffer[..size])
+                    .ok()
+                    .and_then(|packet| ron::from_str(packet).ok());
+                if let Some(message) = message {
+                    messages.push((from, message));
+                }
+            }
+            Err(error) => match error.kind() {
+                ErrorKind::WouldBlock => break,
+                // Spectators that went away show up as errors on some systems
+                ErrorKind::ConnectionReset | ErrorKind::ConnectionRefused => (),
+                _ => {
+                    warn!("could not receive: {error}");
+                    break;
+                }
+            },
+        }
+    }
+    messages
+}
+
+#[derive(Resource)]
+pub struct Broadcaster {
+    socket: UdpSocket,
+    /// Spectators and when they were last heard
+    watchers: HashMap<SocketAddr, Instant>,
+    frame: u64,
+}
+
+/// Car drawn from a `CarView` by a spectator
+#[derive(Component)]
+struct ShownCar(u64);
+
+#[derive(Resource)]
+pub struct Spectator {
+    socket: UdpSocket,
+    host: SocketAddr,
+    last_hello: Option<Instant>,
+    last_view: Option<Instant>,
+    view: Option<WorldView>,
+    /// Parts of the view already received
+    parts: HashSet<usize>,
+    /// Car the camera goes after, the camera is free when `None`
+    follow: Option<u64>,
+    /// Scores last shown on the HUD, one per player
+    scores: Vec<usize>,
+}
+
+impl Spectator {
+    fn status(&self) -> String {
+        let Some(view) = &self.view else {
+            return format!("Waiting for {}", self.host);
+        };
+        if self
+            .last_view
+            .is_none_or(|last_view| last_view.elapsed() > VIEW_TIMEOUT)
+        {
+            return format!("Lost {}", self.host);
+        }
+        let camera = match self
+            .follow
+            .and_then(|id| view.cars.iter().find(|car| car.id == id))
+        {
+            Some(CarView {
+                player: Some(player),
+                ..
+            }) => format!("P{}", player + 1),
+            Some(_) => "rival".to_string(),
+            None => "free camera".to_string(),
+        };
+        let over = if view.over { ", run over" } else { "" };
+        format!(
+            "Watching {}{over} - {camera} - F free, Tab or 1-4 follow",
+            self.host
+        )
+    }
+}
+
+/// Systems filling the views sent to spectators go in this set
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ViewSet;
+
+/// Sending the game to spectators
+pub fn broadcasting(broadcaster: Option<Res<Broadcaster>>) -> bool {
+    broadcaster.is_some()
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        // A config set up before the plugin wins over the command line
+        let config = app
+            .world
+            .remove_resource::<SpectateConfig>()
+            .or_else(|| SpectateConfig::from_args(std::env::args().skip(1)));
+        match config {
+            Some(SpectateConfig::Broadcast { port }) => {
+                let socket = match bind(port) {
+                    Ok(socket) => socket,
+                    Err(error) => {
+                        error!("can not broadcast: {error}");
+                        return;
+                    }
+                };
+                info!("broadcasting on port {port}");
+                app.insert_resource(Broadcaster {
+                    socket,
+                    watchers: HashMap::new(),
+                    frame: 0,
+                })
+                .add_systems(PostUpdate, broadcast.after(ViewSet));
+            }
+            Some(SpectateConfig::Watch { host }) => {
+                let socket = match bind(0) {
+                    Ok(socket) => socket,
+                    Err(error) => {
+                        error!("can not watch: {error}");
+                        return;
+                    }
+                };
+                app.insert_resource(Spectator {
+                    socket,
+                    host,
+                    last_hello: None,
+                    last_view: None,
+                    view: None,
+                    parts: HashSet::new(),
+                    follow: None,
+                    scores: Vec::new(),
+                })
+                // Cars come from the views, none of them is driven here
+                .insert_resource(PlayerCount(0))
+                .add_systems(
+                    Update,
+                    (
+                        watch,
+                        (show_cars, steer_camera)
+                            .chain()
+                            .run_if(in_state(GameState::Spectating)),
+                        show_status,
+                    )
+                        .chain(),
+                );
+            }
+            None => (),
+        }
+    }
+}
+
+fn bind(port: u16) -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(("0.0.0.0", port))?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Sends every spectator what the game looks like after this frame
+fn broadcast(
+    mut broadcaster: ResMut<Broadcaster>,
+    car_query: Query<
+        (
+            Entity,
+            &Transform,
+            &StackedSprite,
+            Option<&PlayerId>,
+            Option<&ScoreManager>,
+        ),
+        With<Player>,
+    >,
+    barrels: Res<BarrelViews>,
+    current: Res<CurrentLevel>,
+    state: Res<State<GameState>>,
+) {
+    let broadcaster = broadcaster.as_mut();
+    for (from, message) in receive(&broadcaster.socket) {
+        if matches!(message, Message::Watch)
+            && broadcaster.watchers.insert(from, Instant::now()).is_none()
+        {
+            info!("spectator joined from {from}");
+        }
+    }
+    broadcaster
+        .watchers
+        .retain(|_, last_heard| last_heard.elapsed() < WATCHER_TIMEOUT);
+
+    broadcaster.frame += 1;
+    if broadcaster.watchers.is_empty() || broadcaster.frame % u64::from(SEND_INTERVAL) != 0 {
+        return;
+    }
+    let view = WorldView {
+        frame: broadcaster.frame,
+        part: 0,
+        level: current.index,
+        over: *state.get() == GameState::GameOver,
+        cars: car_query
+            .iter()
+            .map(|(entity, transform, stack, id, score)| CarView {
+                id: entity.to_bits(),
+                player: id.map(|id| id.0),
+                color: stack.color,
+                position: transform.translation.truncate(),
+                yaw: stack.yaw,
+                score: score.map(ScoreManager::score),
+            })
+            .collect(),
+        barrels: Vec::new(),
+    };
+    // Every part has the cars, so any of them alone can be shown
+    let parts = barrels.0.len().div_ceil(BARRELS_PER_PACKET).max(1);
+    for part in 0..parts {
+        let start = part * BARRELS_PER_PACKET;
+        let end = barrels.0.len().min(start + BARRELS_PER_PACKET);
+        let message = Message::View(WorldView {
+            part,
+            barrels: barrels.0[start..end].to_vec(),
+            ..view.clone()
+        });
+        for watcher in broadcaster.watchers.keys() {
+            send(&broadcaster.socket, *watcher, &message);
+        }
+    }
+}
+
+/// Keeps asking for views and switches to the arena they are played in
+fn watch(
+    mut spectator: ResMut<Spectator>,
+    mut barrels: ResMut<BarrelViews>,
+    mut current: ResMut<CurrentLevel>,
+    asset_server: Res<AssetServer>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let spectator = spectator.as_mut();
+    if spectator
+        .last_hello
+        .is_none_or(|last_hello| last_hello.elapsed() >= HELLO_INTERVAL)
+    {
+        send(&spectator.socket, spectator.host, &Message::Watch);
+        spectator.last_hello = Some(Instant::now());
+    }
+    for (from, message) in receive(&spectator.socket) {
+        let Message::View(view) = message else {
+            continue;
+        };
+        if from != spectator.host {
+            continue;
+        }
+        // Player numbers size the HUD, no game has more than `MAX_PLAYERS`
+        if view
+            .cars
+            .iter()
+            .any(|car| car.player.is_some_and(|player| player >= MAX_PLAYERS))
+        {
+            continue;
+        }
+        match &mut spectator.view {
+            // Views arrive out of order now and then, the newest one wins
+            Some(shown) if shown.frame > view.frame => continue,
+            Some(shown) if shown.frame == view.frame => {
+                if !spectator.parts.insert(view.part) {
+                    continue;
+                }
+                shown.barrels.extend(view.barrels);
+            }
+            _ => {
+                spectator.parts.clear();
+                spectator.parts.insert(view.part);
+                spectator.view = Some(view);
+            }
+        }
+        spectator.last_view = Some(Instant::now());
+        if let Some(view) = &spectator.view {
+            barrels.0.clone_from(&view.barrels);
+        }
+    }
+
+    let Some(level) = spectator.view.as_ref().map(|view| view.level) else {
+        return;
+    };
+    if level != current.index && level < LEVELS.len() {
+        current.index = level;
+        current.handle = asset_server.load(LEVELS[level]);
+        next_state.set(GameState::Loading);
+    }
+}
+
+/// Keeps a car on screen for every view and the scores of the players on the HUD
+fn show_cars(
+    mut commands: Commands,
+    mut spectator: ResMut<Spectator>,
+    assets: Res<CarAssets>,
+    mut car_query: Query<(Entity, &ShownCar, &mut Transform, &mut StackedSprite)>,
+    mut new_score: EventWriter<NewScore>,
+    mut new_count: EventWriter<NewPlayerCount>,
+) {
+    let spectator = spectator.as_mut();
+    let Some(view) = &spectator.view else {
+        return;
+    };
+    for (entity, shown, ..) in &car_query {
+        if !view.cars.iter().any(|car| car.id == shown.0) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for car in &view.cars {
+        if let Some((_, _, mut transform, mut stack)) = car_query
+            .iter_mut()
+            .find(|(_, shown, ..)| shown.0 == car.id)
+        {
+            transform.translation = car.position.extend(transform.translation.z);
+            stack.yaw = car.yaw;
+        } else {
+            // Only drawn, the car is driven in the watched game
+            commands.spawn((
+                SpatialBundle::from_transform(Transform::from_translation(
+                    car.position.extend(0.0),
+                )),
+                StackedSprite {
+                    yaw: car.yaw,
+                    ..car_sprite(&assets, car.color)
+                },
+                CastShadow,
+                ShownCar(car.id),
+            ));
+        }
+    }
+
+    let players = view
+        .cars
+        .iter()
+        .filter_map(|car| car.player)
+        .max()
+        .map_or(0, |last| last + 1);
+    if players != spectator.scores.len() {
+        spectator.scores = vec![0; players];
+        new_count.send(NewPlayerCount(players));
+    }
+    for car in &view.cars {
+        let (Some(player), Some(score)) = (car.player, car.score) else {
+            continue;
+        };
+        let Some(shown) = spectator.scores.get_mut(player) else {
+            continue;
+        };
+        if *shown != score {
+            *shown = score;
+            new_score.send(NewScore { player, score });
+        }
+    }
+}
+
+/// F frees the camera to pan with the arrows, Tab goes through the cars and
+/// the number keys follow a player. The mouse wheel zooms either way.
+fn steer_camera(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut wheel: EventReader<MouseWheel>,
+    mut spectator: ResMut<Spectator>,
+    mut view: ResMut<CameraView>,
+    camera_query: Query<&Transform, With<GameCamera>>,
+    car_query: Query<(Entity, &ShownCar, &Transform, Has<CameraFocus>)>,
+    time: Res<Time>,
+) {
+    let spectator = spectator.as_mut();
+    let cars = spectator
+        .view
+        .as_ref()
+        .map_or(&[][..], |view| view.cars.as_slice());
+    if keys.just_pressed(KeyCode::F) {
+        spectator.follow = None;
+    }
+    if keys.just_pressed(KeyCode::Tab) && !cars.is_empty() {
+        let next = spectator
+            .follow
+            .and_then(|id| cars.iter().position(|car| car.id == id))
+            .map_or(0, |index| (index + 1) % cars.len());
+        spectator.follow = Some(cars[next].id);
+    }
+    for (player, key) in [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4]
+        .into_iter()
+        .enumerate()
+    {
+        if let Some(car) = cars
+            .iter()
+            .find(|car| keys.just_pressed(key) && car.player == Some(player))
+        {
+            spectator.follow = Some(car.id);
+        }
+    }
+
+    for scroll in wheel.read() {
+        view.zoom = (view.zoom * ZOOM_STEP.powf(-scroll.y)).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+    let followed = spectator.follow.and_then(|id| {
+        car_query
+            .iter()
+            .find(|(_, shown, ..)| shown.0 == id)
+            .map(|(entity, _, transform, _)| (entity, transform.translation.truncate()))
+    });
+    // The followed car is the one the camera turns with
+    for (entity, _, _, focused) in &car_query {
+        let wanted = followed.is_some_and(|(followed, _)| followed == entity);
+        if wanted && !focused {
+            commands.entity(entity).insert(CameraFocus);
+        } else if !wanted && focused {
+            commands.entity(entity).remove::<CameraFocus>();
+        }
+    }
+
+    let tick = time.delta_seconds();
+    if let Some((_, position)) = followed {
+        view.center = view
+            .center
+            .lerp(position, f32::min(1.0, FOLLOW_SPEED * tick));
+        return;
+    }
+    let mut direction = Vec2::ZERO;
+    for (key, step) in [
+        (KeyCode::Up, Vec2::Y),
+        (KeyCode::Down, Vec2::NEG_Y),
+        (KeyCode::Left, Vec2::NEG_X),
+        (KeyCode::Right, Vec2::X),
+    ] {
+        if keys.pressed(key) {
+            direction += step;
+        }
+    }
+    // Arrows move along the screen, however the view is turned
+    let rotation = camera_query
+        .get_single()
+        .map_or(Quat::IDENTITY, |camera| camera.rotation);
+    let direction = (rotation * direction.extend(0.0)).truncate();
+    let step = direction.normalize_or_zero() * PAN_SPEED * view.zoom * tick;
+    view.center += step;
+}
+
+fn show_status(
+    spectator: Res<Spectator>,
+    mut shown: Local<String>,
+    mut new_status: EventWriter<NewNetStatus>,
+) {
+    let status = spectator.status();
+    if *shown != status {
+        new_status.send(NewNetStatus(status.clone()));
+        *shown = status;
+    }
+}
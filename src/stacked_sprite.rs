@@ -0,0 +1,144 @@
+#![allow(clippy::needless_pass_by_value, clippy::cast_precision_loss)]
+use bevy::{prelude::*, transform::TransformSystem};
+
+use crate::{camera::GameCamera, net::RollbackApp};
+
+/// Stacked objects are drawn around this depth, closer to the viewer the lower
+/// they are on screen
+const Y_SORT_BASE: f32 = 10.0;
+/// Depth given to each pixel of screen height
+const Y_SORT_SCALE: f32 = 0.01;
+
+/// Pseudo 3D sprite made from a stack of atlas slices, the slice `0` is the
+/// bottom of the object and every next index is drawn `spacing` pixels above.
+///
+/// The entity holding it needs a `SpatialBundle`, the slices are spawned as
+/// children the first time the component is seen.
+#[derive(Component, Clone)]
+pub struct StackedSprite {
+    pub atlas: Handle<TextureAtlas>,
+    pub slices: usize,
+    pub spacing: f32,
+    pub yaw: f32,
+    pub scale: f32,
+    pub elevation: f32,
+    pub custom_size: Option<Vec2>,
+    pub flip_x: bool,
+    /// Tint multiplied into every slice
+    pub color: Color,
+}
+
+impl Default for StackedSprite {
+    fn default() -> Self {
+        Self {
+            atlas: Handle::default(),
+            slices: 1,
+            spacing: 1.0,
+            yaw: 0.0,
+            scale: 1.0,
+            elevation: 0.0,
+            custom_size: None,
+            flip_x: false,
+            color: Color::WHITE,
+        }
+    }
+}
+
+/// One layer of a `StackedSprite`, `scale` is applied on top of the stack scale
+#[derive(Component)]
+pub struct StackedSlice {
+    pub index: usize,
+    pub scale: f32,
+}
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.rollback_component::<StackedSprite>()
+            .add_systems(Update, spawn_slices)
+            .add_systems(
+                PostUpdate,
+                (update_slices, y_sort).before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+fn slice_transform(stack: &StackedSprite, slice: &StackedSlice, up: Vec3) -> Transform {
+    let height = stack.elevation + stack.spacing * slice.index as f32;
+    let mut transform = Transform::from_translation(up * height);
+    // Keep the whole stack inside half a pixel of y sort depth so objects never interleave
+    transform.translation.z = slice.index as f32 / stack.slices as f32 * Y_SORT_SCALE * 0.5;
+    transform.rotation = Quat::from_rotation_z(stack.yaw);
+    transform.scale = Vec3::new(stack.scale * slice.scale, stack.scale * slice.scale, 1.0);
+    transform
+}
+
+fn camera_up(camera_query: &Query<&Transform, With<GameCamera>>) -> Vec3 {
+    // Slices are stacked towards the top of the screen, wherever that is in the world
+    camera_query
+        .get_single()
+        .map_or(Vec3::Y, |camera| camera.rotation * Vec3::Y)
+}
+
+pub fn spawn_slices(
+    mut commands: Commands,
+    query: Query<(Entity, &StackedSprite), Added<StackedSprite>>,
+    camera_query: Query<&Transform, With<GameCamera>>,
+) {
+    let up = camera_up(&camera_query);
+    for (entity, stack) in &query {
+        commands.entity(entity).with_children(|parent| {
+            for index in 0..stack.slices {
+                let slice = StackedSlice { index, scale: 1.0 };
+                parent.spawn((
+                    SpriteSheetBundle {
+                        texture_atlas: stack.atlas.clone(),
+                        sprite: TextureAtlasSprite {
+                            index,
+                            flip_x: stack.flip_x,
+                            custom_size: stack.custom_size,
+                            color: stack.color,
+                            ..default()
+                        },
+                        transform: slice_transform(stack, &slice, up),
+                        ..default()
+                    },
+                    slice,
+                ));
+            }
+        });
+    }
+}
+
+fn update_slices(
+    stack_query: Query<(&StackedSprite, &Children)>,
+    mut slices_query: Query<
+        (&mut Transform, &mut TextureAtlasSprite, &StackedSlice),
+        Without<GameCamera>,
+    >,
+    camera_query: Query<&Transform, With<GameCamera>>,
+) {
+    let up = camera_up(&camera_query);
+    for (stack, children) in &stack_query {
+        for child in children {
+            if let Ok((mut transform, mut sprite, slice)) = slices_query.get_mut(*child) {
+                *transform = slice_transform(stack, slice, up);
+                sprite.color = stack.color;
+            }
+        }
+    }
+}
+
+fn y_sort(
+    mut stack_query: Query<&mut Transform, (With<StackedSprite>, Without<GameCamera>)>,
+    camera_query: Query<&Transform, With<GameCamera>>,
+) {
+    let view = camera_query
+        .get_single()
+        .map_or(Quat::IDENTITY, |camera| camera.rotation.inverse());
+    for mut transform in &mut stack_query {
+        // Sort by the ground position as seen from the camera, ignoring the slice offsets
+        let screen_y = (view * transform.translation.truncate().extend(0.0)).y;
+        transform.translation.z = Y_SORT_BASE - screen_y * Y_SORT_SCALE;
+    }
+}
@@ -1,22 +1,199 @@
 #![allow(clippy::needless_pass_by_value)]
-use bevy::{prelude::*, text::BreakLineOn};
+use bevy::{prelude::*, render::view::RenderLayers, sprite::Anchor, text::BreakLineOn};
 
-use crate::config::{P8_BLACK, WINDOW_HEIGHT};
+use crate::{
+    config::{
+        P8_BLACK, P8_BROWN, P8_DARK_BLUE, P8_DARK_GREEN, P8_RED, P8_WINE, WINDOW_HEIGHT,
+        WINDOW_WIDTH,
+    },
+    net,
+    player::MAX_PLAYERS,
+    GameState,
+};
 
 const SCORE_HEIGHT: f32 = 64.0;
+const HUD_MARGIN: f32 = 16.0;
+/// Gap between the HUD rows of each player, later rows are closer to the middle
+const HUD_ROW: f32 = 22.0;
+/// Text color of every player's HUD
+const HUD_COLORS: [Color; MAX_PLAYERS] = [P8_BLACK, P8_DARK_BLUE, P8_WINE, P8_BROWN];
+/// Characters in the boost meter bar
+const BOOST_METER_LENGTH: usize = 10;
 
 #[derive(Component)]
 struct Score;
 
+#[derive(Component)]
+struct Lives;
+
+#[derive(Component)]
+struct Combo;
+
+#[derive(Component)]
+struct PowerUps;
+
+#[derive(Component)]
+struct Boost;
+
+/// HUD text showing the state of one player, the first section is a
+/// player label only shown when there are several
+#[derive(Component)]
+struct PlayerHud(usize);
+
+#[derive(Component)]
+struct GameOverText;
+
+#[derive(Component)]
+struct EditorStatus;
+
+#[derive(Component)]
+struct RaceTimer;
+
+#[derive(Component)]
+struct ModeStatus;
+
+#[derive(Component)]
+struct NetStatus;
+
+#[derive(Component)]
+struct LevelStatus;
+
+#[derive(Event)]
+pub struct NewScore {
+    pub player: usize,
+    pub score: usize,
+}
+
 #[derive(Event)]
-pub struct NewScore(pub usize);
+pub struct NewCombo {
+    pub player: usize,
+    pub combo: usize,
+}
+
+#[derive(Event)]
+pub struct NewHealth {
+    pub player: usize,
+    pub hp: u32,
+    pub max_hp: u32,
+    pub lives: u32,
+}
+
+/// Boost meter charge, between 0 and 1
+#[derive(Event)]
+pub struct NewBoost {
+    pub player: usize,
+    pub charge: f32,
+}
+
+/// Name and seconds left of every power-up running on a car
+#[derive(Event)]
+pub struct NewPowerUps {
+    pub player: usize,
+    pub power_ups: Vec<(&'static str, f32)>,
+}
+
+pub struct RaceTime {
+    pub lap: u32,
+    /// Seconds into the current lap
+    pub time: f32,
+    pub best: Option<f32>,
+    /// Seconds ahead or behind the best lap at the last gate
+    pub split: Option<f32>,
+}
+
+/// Race HUD of a player, `None` hides it on levels without a race route
+#[derive(Event)]
+pub struct NewRaceTime {
+    pub player: usize,
+    pub time: Option<RaceTime>,
+}
+
+/// Computer driven cars joining the next run
+#[derive(Event)]
+pub struct NewRivals {
+    pub count: usize,
+    pub skill: &'static str,
+}
+
+/// Number of cars on the screen, every player gets a HUD row
+#[derive(Event)]
+pub struct NewPlayerCount(pub usize);
+
+/// Progress towards the goal of the game mode
+#[derive(Event)]
+pub struct NewModeStatus(pub String);
+
+/// Name of the game mode the next run is played in
+#[derive(Event)]
+pub struct NewGameMode(pub &'static str);
+
+/// How the last run ended, shown on the game over screen
+#[derive(Event)]
+pub struct NewRunResult {
+    pub title: &'static str,
+    pub detail: String,
+}
+
+/// Connection of an online game
+#[derive(Event)]
+pub struct NewNetStatus(pub String);
+
+/// What the leaderboard made of the last run
+#[derive(Event)]
+pub struct NewLeaderboard(pub String);
+
+/// Problem with the level files, empty once a level loads fine
+#[derive(Event)]
+pub struct NewLevelStatus(pub String);
+
+/// Tool and key help shown while editing, `None` hides it
+#[derive(Event)]
+pub struct NewEditorStatus(pub Option<String>);
 
 pub struct Plug;
 impl Plugin for Plug {
     fn build(&self, app: &mut App) {
         app.add_event::<NewScore>()
+            .add_event::<NewCombo>()
+            .add_event::<NewHealth>()
+            .add_event::<NewPowerUps>()
+            .add_event::<NewBoost>()
+            .add_event::<NewEditorStatus>()
+            .add_event::<NewRaceTime>()
+            .add_event::<NewModeStatus>()
+            .add_event::<NewGameMode>()
+            .add_event::<NewRunResult>()
+            .add_event::<NewPlayerCount>()
+            .add_event::<NewRivals>()
+            .add_event::<NewNetStatus>()
+            .add_event::<NewLeaderboard>()
+            .add_event::<NewLevelStatus>()
             .add_systems(Startup, setup_ui)
-            .add_systems(Update, update_ui);
+            .add_systems(
+                Update,
+                (
+                    update_ui,
+                    update_lives,
+                    update_combo,
+                    update_power_ups,
+                    update_boost,
+                    update_editor_status,
+                    update_race_timer,
+                    update_mode_status,
+                    update_run_result,
+                    update_player_count,
+                    update_net_status,
+                    update_level_status,
+                ),
+            )
+            .add_systems(
+                Update,
+                restart_game
+                    .run_if(in_state(GameState::GameOver))
+                    .run_if(net::offline),
+            )
+            .add_systems(OnEnter(GameState::GameOver), show_game_over)
+            .add_systems(OnExit(GameState::GameOver), hide_game_over);
     }
 }
 
@@ -27,31 +204,466 @@ fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
         font_size: 18.0,
         color: P8_BLACK,
     };
+    let hud_y = (WINDOW_HEIGHT - SCORE_HEIGHT) * 0.5;
+
+    for player in 0..MAX_PLAYERS {
+        spawn_player_hud(&mut commands, &text_style, player);
+    }
+
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_sections([
+                TextSection::new(
+                    "GAME OVER\n",
+                    TextStyle {
+                        font_size: 48.0,
+                        color: P8_RED.with_a(1.0),
+                        ..text_style.clone()
+                    },
+                ),
+                TextSection::new("", text_style.clone()),
+                TextSection::new("", text_style.clone()),
+                TextSection::new("", text_style.clone()),
+                TextSection::new("", text_style.clone()),
+                TextSection::new(
+                    "Press R to try again, N for the next arena, M to change mode,\n\
+                     P for one or two players, I for more rivals or K for their skill",
+                    text_style.clone(),
+                ),
+            ])
+            .with_alignment(TextAlignment::Center),
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 99.)),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        RenderLayers::layer(1),
+        GameOverText,
+    ));
+
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_section("", text_style.clone()),
+            transform: Transform::from_translation(Vec3::new(
+                0.0,
+                hud_y - SCORE_HEIGHT * 0.375,
+                99.,
+            )),
+            ..default()
+        },
+        RenderLayers::layer(1),
+        ModeStatus,
+    ));
+
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_section("", text_style.clone()),
+            transform: Transform::from_translation(Vec3::new(
+                0.0,
+                -hud_y - SCORE_HEIGHT * 0.25,
+                99.,
+            )),
+            ..default()
+        },
+        RenderLayers::layer(1),
+        NetStatus,
+    ));
+
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    color: P8_RED,
+                    ..text_style.clone()
+                },
+            ),
+            transform: Transform::from_translation(Vec3::new(
+                0.0,
+                -hud_y - SCORE_HEIGHT * 0.25 + HUD_ROW,
+                99.,
+            )),
+            ..default()
+        },
+        RenderLayers::layer(1),
+        LevelStatus,
+    ));
+
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_section("", text_style).with_alignment(TextAlignment::Center),
+            text_anchor: Anchor::TopCenter,
+            transform: Transform::from_translation(Vec3::new(0.0, hud_y - SCORE_HEIGHT * 0.5, 99.)),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        RenderLayers::layer(1),
+        EditorStatus,
+    ));
+}
+
+/// Score, lives, combo, nitro, power-ups and race timer of one player
+fn spawn_player_hud(commands: &mut Commands, text_style: &TextStyle, player: usize) {
+    let hud_y = (WINDOW_HEIGHT - SCORE_HEIGHT) * 0.5;
+    let row = player as f32 * HUD_ROW;
+    let text_style = TextStyle {
+        color: HUD_COLORS[player],
+        ..text_style.clone()
+    };
+    let label = || TextSection::new("", text_style.clone());
+    // Only the first player is on screen until the player count says otherwise
+    let visibility = if player == 0 {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
 
     commands.spawn((
         Text2dBundle {
             text: Text {
                 sections: vec![
+                    label(),
                     TextSection::new("Score: ", text_style.clone()),
-                    TextSection::new("000000", text_style),
+                    TextSection::new("000000", text_style.clone()),
                 ],
                 alignment: TextAlignment::Left,
                 linebreak_behavior: BreakLineOn::AnyCharacter,
             },
+            transform: Transform::from_translation(Vec3::new(0.0, hud_y - row, 99.)),
+            visibility,
+            ..default()
+        },
+        // Draw with the windows camera so the HUD does not turn with the game camera
+        RenderLayers::layer(1),
+        PlayerHud(player),
+        Score,
+    ));
+
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_sections([
+                label(),
+                TextSection::new("Lives: ", text_style.clone()),
+                TextSection::new("", text_style.clone()),
+                TextSection::new("  HP: ", text_style.clone()),
+                TextSection::new("", text_style.clone()),
+            ]),
+            text_anchor: Anchor::CenterLeft,
             transform: Transform::from_translation(Vec3::new(
-                0.0,
-                (WINDOW_HEIGHT - SCORE_HEIGHT) * 0.5,
+                -WINDOW_WIDTH * 0.5 + HUD_MARGIN,
+                hud_y - row,
                 99.,
             )),
+            visibility,
             ..default()
         },
-        Score,
+        RenderLayers::layer(1),
+        PlayerHud(player),
+        Lives,
+    ));
+
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_sections([
+                label(),
+                TextSection::new("Combo x", text_style.clone()),
+                TextSection::new("0", text_style.clone()),
+            ]),
+            text_anchor: Anchor::CenterRight,
+            transform: Transform::from_translation(Vec3::new(
+                WINDOW_WIDTH * 0.5 - HUD_MARGIN,
+                hud_y - row,
+                99.,
+            )),
+            visibility,
+            ..default()
+        },
+        RenderLayers::layer(1),
+        PlayerHud(player),
+        Combo,
+    ));
+
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_sections([label(), TextSection::new("", text_style.clone())]),
+            transform: Transform::from_translation(Vec3::new(0.0, -hud_y + row, 99.)),
+            visibility,
+            ..default()
+        },
+        RenderLayers::layer(1),
+        PlayerHud(player),
+        PowerUps,
+    ));
+
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_sections([
+                label(),
+                TextSection::new("Nitro: ", text_style.clone()),
+                TextSection::new("-".repeat(BOOST_METER_LENGTH), text_style.clone()),
+            ]),
+            text_anchor: Anchor::CenterLeft,
+            transform: Transform::from_translation(Vec3::new(
+                -WINDOW_WIDTH * 0.5 + HUD_MARGIN,
+                -hud_y + row,
+                99.,
+            )),
+            visibility,
+            ..default()
+        },
+        RenderLayers::layer(1),
+        PlayerHud(player),
+        Boost,
+    ));
+
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_sections([
+                label(),
+                TextSection::new("", text_style.clone()),
+                TextSection::new("", text_style.clone()),
+            ])
+            .with_alignment(TextAlignment::Right),
+            text_anchor: Anchor::BottomRight,
+            // Two lines high, so leave room for two rows
+            transform: Transform::from_translation(Vec3::new(
+                WINDOW_WIDTH * 0.5 - HUD_MARGIN,
+                -hud_y - SCORE_HEIGHT * 0.25 + row * 2.0,
+                99.,
+            )),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        RenderLayers::layer(1),
+        PlayerHud(player),
+        RaceTimer,
     ));
 }
 
-fn update_ui(mut query: Query<&mut Text, With<Score>>, mut new_score: EventReader<NewScore>) {
-    if let Some(new_score) = new_score.read().last() {
-        let mut text = query.single_mut();
-        text.sections[1].value = format!("{:0>6}", new_score.0);
+fn update_ui(
+    mut query: Query<(&mut Text, &PlayerHud), With<Score>>,
+    mut new_score: EventReader<NewScore>,
+) {
+    for new_score in new_score.read() {
+        for (mut text, hud) in &mut query {
+            if hud.0 == new_score.player {
+                text.sections[2].value = format!("{:0>6}", new_score.score);
+            }
+        }
+    }
+}
+
+fn update_lives(
+    mut query: Query<(&mut Text, &PlayerHud), With<Lives>>,
+    mut new_health: EventReader<NewHealth>,
+) {
+    for health in new_health.read() {
+        for (mut text, hud) in &mut query {
+            if hud.0 != health.player {
+                continue;
+            }
+            text.sections[2].value = health.lives.to_string();
+            text.sections[4].value = format!(
+                "{}{}",
+                "#".repeat(health.hp as usize),
+                "-".repeat(health.max_hp.saturating_sub(health.hp) as usize)
+            );
+        }
+    }
+}
+
+fn update_combo(
+    mut query: Query<(&mut Text, &PlayerHud), With<Combo>>,
+    mut new_combo: EventReader<NewCombo>,
+) {
+    for combo in new_combo.read() {
+        for (mut text, hud) in &mut query {
+            if hud.0 == combo.player {
+                text.sections[2].value = combo.combo.to_string();
+            }
+        }
+    }
+}
+
+fn update_power_ups(
+    mut query: Query<(&mut Text, &PlayerHud), With<PowerUps>>,
+    mut new_power_ups: EventReader<NewPowerUps>,
+) {
+    for power_ups in new_power_ups.read() {
+        for (mut text, hud) in &mut query {
+            if hud.0 != power_ups.player {
+                continue;
+            }
+            text.sections[1].value = power_ups
+                .power_ups
+                .iter()
+                .map(|(name, left)| format!("{name} {left:.1}"))
+                .collect::<Vec<_>>()
+                .join("   ");
+        }
+    }
+}
+
+fn update_boost(
+    mut query: Query<(&mut Text, &PlayerHud), With<Boost>>,
+    mut new_boost: EventReader<NewBoost>,
+) {
+    for boost in new_boost.read() {
+        for (mut text, hud) in &mut query {
+            if hud.0 != boost.player {
+                continue;
+            }
+            let filled = (boost.charge * BOOST_METER_LENGTH as f32).round() as usize;
+            text.sections[2].value = format!(
+                "{}{}",
+                "#".repeat(filled),
+                "-".repeat(BOOST_METER_LENGTH - filled)
+            );
+        }
+    }
+}
+
+fn update_editor_status(
+    mut query: Query<(&mut Text, &mut Visibility), With<EditorStatus>>,
+    mut new_status: EventReader<NewEditorStatus>,
+) {
+    if let Some(status) = new_status.read().last() {
+        let (mut text, mut visibility) = query.single_mut();
+        match &status.0 {
+            Some(status) => {
+                text.sections[0].value.clone_from(status);
+                *visibility = Visibility::Visible;
+            }
+            None => *visibility = Visibility::Hidden,
+        }
+    }
+}
+
+fn lap_time(seconds: f32) -> String {
+    let minutes = (seconds / 60.0).floor();
+    format!("{minutes:.0}:{:05.2}", seconds - minutes * 60.0)
+}
+
+fn update_race_timer(
+    mut query: Query<(&mut Text, &mut Visibility, &PlayerHud), With<RaceTimer>>,
+    mut new_race_time: EventReader<NewRaceTime>,
+) {
+    for race in new_race_time.read() {
+        for (mut text, mut visibility, hud) in &mut query {
+            if hud.0 != race.player {
+                continue;
+            }
+            let Some(race) = &race.time else {
+                *visibility = Visibility::Hidden;
+                continue;
+            };
+            *visibility = Visibility::Visible;
+            text.sections[1].value = match race.split {
+                Some(split) if split <= 0.0 => format!("Split -{:.2}\n", -split),
+                Some(split) => format!("Split +{split:.2}\n"),
+                None => String::new(),
+            };
+            text.sections[1].style.color = if race.split.is_some_and(|split| split > 0.0) {
+                P8_RED
+            } else {
+                P8_DARK_GREEN
+            };
+            let best = race.best.map_or("-:--.--".to_string(), lap_time);
+            text.sections[2].value =
+                format!("Lap {}  {}  Best {best}", race.lap, lap_time(race.time));
+        }
+    }
+}
+
+/// Shows a HUD row for every player and labels them once there are several
+fn update_player_count(
+    mut hud_query: Query<(&mut Text, &mut Visibility, &PlayerHud, Has<RaceTimer>)>,
+    mut mode_query: Query<&mut Transform, With<ModeStatus>>,
+    mut new_count: EventReader<NewPlayerCount>,
+) {
+    let Some(count) = new_count.read().last() else {
+        return;
+    };
+    for (mut text, mut visibility, hud, is_race_timer) in &mut hud_query {
+        text.sections[0].value = if count.0 > 1 {
+            format!("P{} ", hud.0 + 1)
+        } else {
+            String::new()
+        };
+        if hud.0 >= count.0 {
+            *visibility = Visibility::Hidden;
+        } else if !is_race_timer {
+            // The race timer is shown by its own updates
+            *visibility = Visibility::Inherited;
+        }
+    }
+    let hud_y = (WINDOW_HEIGHT - SCORE_HEIGHT) * 0.5;
+    mode_query.single_mut().translation.y =
+        hud_y - SCORE_HEIGHT * 0.375 - count.0.saturating_sub(1) as f32 * HUD_ROW;
+}
+
+fn update_mode_status(
+    mut query: Query<&mut Text, With<ModeStatus>>,
+    mut new_status: EventReader<NewModeStatus>,
+) {
+    if let Some(status) = new_status.read().last() {
+        query.single_mut().sections[0].value.clone_from(&status.0);
+    }
+}
+
+fn update_net_status(
+    mut query: Query<&mut Text, With<NetStatus>>,
+    mut new_status: EventReader<NewNetStatus>,
+) {
+    if let Some(status) = new_status.read().last() {
+        query.single_mut().sections[0].value.clone_from(&status.0);
+    }
+}
+
+fn update_level_status(
+    mut query: Query<&mut Text, With<LevelStatus>>,
+    mut new_status: EventReader<NewLevelStatus>,
+) {
+    if let Some(status) = new_status.read().last() {
+        query.single_mut().sections[0].value.clone_from(&status.0);
+    }
+}
+
+fn update_run_result(
+    mut query: Query<&mut Text, With<GameOverText>>,
+    mut new_result: EventReader<NewRunResult>,
+    mut new_mode: EventReader<NewGameMode>,
+    mut new_rivals: EventReader<NewRivals>,
+    mut new_leaderboard: EventReader<NewLeaderboard>,
+) {
+    let mut text = query.single_mut();
+    if let Some(result) = new_result.read().last() {
+        text.sections[0].value = format!("{}\n", result.title);
+        text.sections[1].value = format!("{}\n\n", result.detail);
+    }
+    if let Some(mode) = new_mode.read().last() {
+        text.sections[2].value = format!("Next mode: {}\n", mode.0);
+    }
+    if let Some(rivals) = new_rivals.read().last() {
+        text.sections[3].value = match rivals.count {
+            0 => "No rivals\n".to_string(),
+            count => format!("Rivals: {count} {}\n", rivals.skill),
+        };
+    }
+    if let Some(leaderboard) = new_leaderboard.read().last() {
+        text.sections[4].value = format!("\n{}\n\n", leaderboard.0);
+    }
+}
+
+fn show_game_over(mut query: Query<&mut Visibility, With<GameOverText>>) {
+    *query.single_mut() = Visibility::Visible;
+}
+
+fn hide_game_over(mut query: Query<&mut Visibility, With<GameOverText>>) {
+    *query.single_mut() = Visibility::Hidden;
+}
+
+fn restart_game(keys: Res<Input<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+    if keys.just_pressed(KeyCode::R) {
+        next_state.set(GameState::Playing);
     }
 }
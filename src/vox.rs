@@ -0,0 +1,207 @@
+#![allow(
+    clippy::needless_pass_by_value,
+    clippy::cast_precision_loss,
+    clippy::module_name_repetitions
+)]
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext, LoadState},
+    prelude::*,
+    reflect::TypePath,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{config::P8_PALETTE, stacked_sprite::StackedSprite};
+
+const RGBA_PIXEL_SIZE: usize = 4;
+
+/// MagicaVoxel model sliced into a stacked sprite atlas, one slice per voxel
+/// layer. A sprite strip can be added as one too, to be stacked the same way.
+#[derive(Asset, TypePath)]
+pub struct VoxModel {
+    pub atlas: Handle<TextureAtlas>,
+    pub slices: usize,
+}
+
+/// Colors used to paint the slices
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
+pub enum VoxPalette {
+    /// Snap every voxel color to the closest P8 color
+    #[default]
+    Pico8,
+    /// Keep the colors stored in the `.vox` file
+    Source,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct VoxSettings {
+    pub palette: VoxPalette,
+}
+
+#[derive(Debug, Error)]
+pub enum VoxError {
+    #[error("could not read vox file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse vox file: {0}")]
+    Parse(&'static str),
+    #[error("vox file has no models")]
+    Empty,
+    #[error("voxel at {x}, {y}, {z} is outside the model")]
+    OutOfBounds { x: u8, y: u8, z: u8 },
+}
+
+/// Every `.vox` model the game draws, levels wait in `GameState::Loading`
+/// until they are all in
+#[derive(Resource, Default)]
+pub struct VoxModels(Vec<Handle<VoxModel>>);
+
+impl VoxModels {
+    pub fn load(&mut self, asset_server: &AssetServer, path: &'static str) -> Handle<VoxModel> {
+        let model = asset_server.load(path);
+        self.0.push(model.clone());
+        model
+    }
+}
+
+#[derive(Default)]
+struct VoxLoader;
+
+pub struct Plug;
+impl Plugin for Plug {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<VoxModel>()
+            .init_asset_loader::<VoxLoader>()
+            .init_resource::<VoxModels>();
+    }
+}
+
+impl AssetLoader for VoxLoader {
+    type Asset = VoxModel;
+    type Settings = VoxSettings;
+    type Error = VoxError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        settings: &'a VoxSettings,
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<VoxModel, VoxError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let data = dot_vox::load_bytes(&bytes).map_err(VoxError::Parse)?;
+            let model = data.models.first().ok_or(VoxError::Empty)?;
+
+            let (width, depth, height) = (
+                model.size.x as usize,
+                model.size.y as usize,
+                model.size.z as usize,
+            );
+            // Every z layer becomes a column of the strip, bottom layer first
+            let strip_width = width * height;
+            let mut pixels = vec![0; strip_width * depth * RGBA_PIXEL_SIZE];
+            for voxel in &model.voxels {
+                let (x, y, z) = (voxel.x as usize, voxel.y as usize, voxel.z as usize);
+                if x >= width || y >= depth || z >= height {
+                    return Err(VoxError::OutOfBounds {
+                        x: voxel.x,
+                        y: voxel.y,
+                        z: voxel.z,
+                    });
+                }
+                let color = data.palette[voxel.i as usize];
+                let color = match settings.palette {
+                    VoxPalette::Pico8 => closest_p8_color([color.r, color.g, color.b]),
+                    VoxPalette::Source => [color.r, color.g, color.b, color.a],
+                };
+                let column = z * width + x;
+                // Voxel y grows away from the viewer, image rows grow downwards
+                let row = depth - 1 - y;
+                let start = (row * strip_width + column) * RGBA_PIXEL_SIZE;
+                pixels[start..start + RGBA_PIXEL_SIZE].copy_from_slice(&color);
+            }
+
+            let image = load_context.add_labeled_asset(
+                "image".to_string(),
+                Image::new(
+                    Extent3d {
+                        width: strip_width as u32,
+                        height: depth as u32,
+                        ..default()
+                    },
+                    TextureDimension::D2,
+                    pixels,
+                    TextureFormat::Rgba8UnormSrgb,
+                ),
+            );
+            let atlas = load_context.add_labeled_asset(
+                "atlas".to_string(),
+                TextureAtlas::from_grid(
+                    image,
+                    Vec2::new(width as f32, depth as f32),
+                    height,
+                    1,
+                    None,
+                    None,
+                ),
+            );
+            Ok(VoxModel {
+                atlas,
+                slices: height,
+            })
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["vox"]
+    }
+}
+
+fn closest_p8_color(color: [u8; 3]) -> [u8; 4] {
+    let distance = |p8: &Color| {
+        let [red, green, blue, _] = p8.as_rgba_u8();
+        [red, green, blue]
+            .iter()
+            .zip(color)
+            .map(|(a, b)| (i32::from(*a) - i32::from(b)).pow(2))
+            .sum::<i32>()
+    };
+    let [red, green, blue, _] = P8_PALETTE
+        .iter()
+        .min_by_key(|p8| distance(p8))
+        .expect("P8 palette is empty")
+        .as_rgba_u8();
+    [red, green, blue, 255]
+}
+
+/// Run condition, true once no model is still loading
+pub fn models_loaded(vox_models: Res<VoxModels>, asset_server: Res<AssetServer>) -> bool {
+    vox_models.0.iter().all(|model| {
+        // A broken file is logged by the asset server, its stacks stay empty
+        matches!(
+            asset_server.load_state(model),
+            LoadState::Loaded | LoadState::Failed
+        )
+    })
+}
+
+/// `sprite` with the atlas and slice count of `model`
+pub fn stack(
+    models: &Assets<VoxModel>,
+    model: &Handle<VoxModel>,
+    sprite: StackedSprite,
+) -> StackedSprite {
+    match models.get(model) {
+        Some(model) => StackedSprite {
+            atlas: model.atlas.clone(),
+            slices: model.slices,
+            ..sprite
+        },
+        None => StackedSprite {
+            slices: 0,
+            ..sprite
+        },
+    }
+}
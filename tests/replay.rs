@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use super_oxi_car::replay::{self, CarInput, GameMode, Replay, ReplayFrame, Skill, VerifyError};
+
+/// Frames scripted, more than the run lasts
+const FRAMES: usize = 1800;
+const STEP: Duration = Duration::from_nanos(16_666_667);
+/// Frame whose input the cheating replay changes
+const EDITED: usize = 120;
+
+/// Short run with a rival, weaving and boosting until three barrels went off
+fn record_run() -> Replay {
+    let frames = (0..FRAMES)
+        .map(|frame| ReplayFrame {
+            step: STEP.as_nanos() as u64,
+            inputs: vec![CarInput {
+                steer: [1.0, 0.0, -1.0][frame / 40 % 3],
+                drift: false,
+                boost: frame % 120 > 100,
+            }],
+            state: 0,
+        })
+        .collect();
+    let script = Replay {
+        seed: 7,
+        level: 0,
+        mode: GameMode::NoDrift { target: 3 },
+        players: 1,
+        rivals: 1,
+        skill: Skill::Normal,
+        frames,
+        scores: Vec::new(),
+        time: Duration::ZERO,
+    };
+    replay::record(&script).unwrap()
+}
+
+#[test]
+fn recorded_run_verifies() {
+    let replay = record_run();
+    assert!(replay.frames.len() < FRAMES, "the run did not end");
+    let summary = replay::verify(&replay).unwrap();
+    assert_eq!(summary.frames, replay.frames.len());
+    assert_eq!(summary.scores, replay.scores);
+    assert_eq!(summary.time, replay.time);
+}
+
+#[test]
+fn edited_input_diverges_on_its_frame() {
+    let mut replay = record_run();
+    let input = &mut replay.frames[EDITED].inputs[0];
+    input.steer = if input.steer > 0.0 { -1.0 } else { 1.0 };
+    match replay::verify(&replay) {
+        Err(VerifyError::Diverged(frame)) => assert_eq!(frame, EDITED),
+        Err(error) => panic!("expected a divergence, got: {error}"),
+        Ok(_) => panic!("the edited replay verified"),
+    }
+}
+
+#[test]
+fn impossible_steering_is_turned_down() {
+    let mut replay = record_run();
+    replay.frames[EDITED].inputs[0].steer = 5.0;
+    match replay::verify(&replay) {
+        Err(VerifyError::Input(frame)) => assert_eq!(frame, EDITED),
+        Err(error) => panic!("expected an input error, got: {error}"),
+        Ok(_) => panic!("the edited replay verified"),
+    }
+}